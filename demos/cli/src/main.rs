@@ -34,6 +34,18 @@ enum Command {
         /// Render as a color-gradient SDF
         #[clap(long)]
         sdf: bool,
+
+        /// Render an anti-aliased coverage mask
+        #[clap(long, conflicts_with_all = ["sdf", "brute"])]
+        antialias: bool,
+
+        /// Estimate coverage by supersampling, for non-SDF fields
+        #[clap(long, requires = "antialias")]
+        supersample: bool,
+
+        /// Name of a `.pfm` file to write with raw field values
+        #[clap(long)]
+        field: Option<PathBuf>,
     },
 
     Render3d {
@@ -45,13 +57,59 @@ enum Command {
         color: bool,
 
         /// Render using an isometric perspective
-        #[clap(long)]
+        #[clap(long, conflicts_with = "fov")]
         isometric: bool,
+
+        /// Render by ray-marching instead of evaluating voxel columns
+        #[clap(long)]
+        raymarch: bool,
+
+        /// Field of view for perspective rendering, in degrees
+        #[clap(long, default_value_t = 30.0)]
+        fov: f32,
+
+        /// Render a lit and shaded image
+        #[clap(long, conflicts_with = "color")]
+        shade: bool,
+
+        /// Trace hard shadows when shading
+        #[clap(long, requires = "shade")]
+        shadows: bool,
+
+        /// Ambient occlusion mode when shading
+        #[clap(long, value_enum, default_value_t, requires = "shade")]
+        occlusion: OcclusionMode,
+
+        /// Name of a `.png` (16-bit) or `.pfm` file to write with depth
+        #[clap(long)]
+        depth: Option<PathBuf>,
+
+        /// Name of a `.pfm` file to write with full-precision normals
+        #[clap(long)]
+        normals: Option<PathBuf>,
     },
     Mesh {
         #[clap(flatten)]
         settings: MeshSettings,
     },
+    Contour {
+        #[clap(flatten)]
+        settings: ContourSettings,
+    },
+    Slice {
+        #[clap(flatten)]
+        settings: SliceSettings,
+    },
+}
+
+#[derive(ValueEnum, Clone, Default)]
+enum OcclusionMode {
+    #[default]
+    None,
+    /// Screen-space ambient occlusion
+    Screen,
+    /// Ambient occlusion sampled from the distance field
+    Sdf,
 }
 
 #[derive(ValueEnum, Clone)]
@@ -87,14 +145,42 @@ struct ImageSettings {
 
 #[derive(Parser)]
 struct MeshSettings {
-    /// Octree depth
+    /// Octree depth (the maximum depth, if `--min-depth` is set)
     #[clap(short, long)]
     depth: u8,
 
-    /// Name of a `.stl` file to write
+    /// Minimum octree depth for adaptive subdivision
+    #[clap(long)]
+    min_depth: Option<u8>,
+
+    /// Isovalue of the surface to mesh
+    #[clap(long, default_value_t = 0.0)]
+    isovalue: f32,
+
+    /// Name of a `.stl`, `.obj`, `.ply`, `.3mf`, or `.glb` file to write
     #[clap(short, long)]
     out: Option<PathBuf>,
 
+    /// Write STL files as ASCII (rather than binary)
+    #[clap(long)]
+    ascii: bool,
+
+    /// Compute per-vertex normals from the shape's gradient
+    #[clap(long)]
+    normals: bool,
+
+    /// Stream a binary STL or PLY file without building the mesh in memory
+    #[clap(long, requires = "out", conflicts_with_all = ["normals", "ascii"])]
+    stream: bool,
+
+    /// Maximum QEF error when collapsing octree cells
+    #[clap(long)]
+    max_error: Option<f32>,
+
+    /// Close the mesh where the shape is clipped by the ±1 bounds
+    #[clap(long)]
+    cap: bool,
+
     /// Evaluator flavor
     #[clap(short, long, value_enum, default_value_t = EvalMode::Vm)]
     eval: EvalMode,
@@ -108,36 +194,126 @@ struct MeshSettings {
     n: usize,
 }
 
+impl MeshSettings {
+    fn to_settings(&self) -> fidget::mesh::Settings {
+        fidget::mesh::Settings {
+            threads: self.threads.into(),
+            depth: self.depth,
+            min_depth: self.min_depth,
+            isovalue: self.isovalue,
+            max_error: self.max_error,
+            cap: self.cap,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Parser)]
+struct ContourSettings {
+    /// Quadtree depth
+    #[clap(short, long, default_value_t = 8)]
+    depth: u8,
+
+    /// Name of a `.svg` or `.dxf` file to write
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+
+    /// Evaluator flavor
+    #[clap(short, long, value_enum, default_value_t = EvalMode::Vm)]
+    eval: EvalMode,
+
+    /// Number of times to render (for benchmarking)
+    #[clap(short = 'N', default_value_t = 1)]
+    n: usize,
+}
+
+#[derive(Parser)]
+struct SliceSettings {
+    /// Directory in which to write one `.png` file per layer
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+
+    /// Evaluator flavor
+    #[clap(short, long, value_enum, default_value_t = EvalMode::Vm)]
+    eval: EvalMode,
+
+    /// Number of threads to use
+    #[clap(short, long, default_value_t = NonZeroUsize::new(8).unwrap())]
+    threads: NonZeroUsize,
+
+    /// Number of times to render (for benchmarking)
+    #[clap(short = 'N', default_value_t = 1)]
+    n: usize,
+
+    /// Image size
+    #[clap(short, long, default_value_t = 128)]
+    size: u32,
+
+    /// Distance between layers
+    #[clap(short, long, default_value_t = 0.05)]
+    pitch: f32,
+
+    /// Z position of the bottom layer
+    #[clap(long, default_value_t = -1.0, allow_negative_numbers = true)]
+    z_min: f32,
+
+    /// Z position of the top layer
+    #[clap(long, default_value_t = 1.0, allow_negative_numbers = true)]
+    z_max: f32,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 fn run3d<F: fidget::eval::Function + fidget::render::RenderHints>(
     shape: fidget::shape::Shape<F>,
     settings: &ImageSettings,
-    isometric: bool,
+    fov: Option<f32>,
+    raymarch: bool,
     mode_color: bool,
-) -> Vec<u8> {
-    let mut mat = nalgebra::Transform3::identity();
-    if !isometric {
-        *mat.matrix_mut().get_mut((3, 2)).unwrap() = 0.3;
-    }
-    let cfg = fidget::render::VoxelRenderConfig {
+    shade: Option<&fidget::render::ShadeConfig>,
+    normals: bool,
+) -> (Vec<u8>, Vec<u32>, Option<Vec<[f32; 3]>>) {
+    let mut cfg = fidget::render::VoxelRenderConfig {
         image_size: fidget::render::VoxelSize::from(settings.size),
         tile_sizes: F::tile_sizes_3d(),
         threads: settings.threads.into(),
         ..Default::default()
     };
-    let shape = shape.apply_transform(mat.into());
+    if let Some(fov) = fov {
+        cfg.view
+            .set_perspective(Some(fidget::render::Perspective::new(
+                fov.to_radians(),
+            )));
+    }
 
     let mut depth = vec![];
     let mut color = vec![];
     for _ in 0..settings.n {
-        (depth, color) = cfg.run(shape.clone());
+        (depth, color) = if raymarch {
+            cfg.run_raymarch(shape.clone())
+        } else {
+            cfg.run(shape.clone())
+        };
     }
 
-    let out = if mode_color {
+    let normals = normals.then(|| cfg.run_normals(shape.clone(), &depth));
+    let out = if let Some(shade) = shade {
+        let rgb = shade.run(shape, &cfg, &depth);
         depth
-            .into_iter()
+            .iter()
+            .zip(rgb)
+            .flat_map(|(&d, p)| {
+                if d > 0 {
+                    [p[0], p[1], p[2], 255]
+                } else {
+                    [0, 0, 0, 0]
+                }
+            })
+            .collect()
+    } else if mode_color {
+        depth
+            .iter()
             .zip(color)
-            .flat_map(|(d, p)| {
+            .flat_map(|(&d, p)| {
                 if d > 0 {
                     [p[0], p[1], p[2], 255]
                 } else {
@@ -148,8 +324,8 @@ fn run3d<F: fidget::eval::Function + fidget::render::RenderHints>(
     } else {
         let z_max = depth.iter().max().cloned().unwrap_or(1);
         depth
-            .into_iter()
-            .flat_map(|p| {
+            .iter()
+            .flat_map(|&p| {
                 if p > 0 {
                     let z = (p * 255 / z_max) as u8;
                     [z, z, z, 255]
@@ -160,16 +336,24 @@ fn run3d<F: fidget::eval::Function + fidget::render::RenderHints>(
             .collect()
     };
 
-    out
+    (out, depth, normals)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Coverage estimation strategy for anti-aliased 2D rendering
+#[derive(Copy, Clone)]
+enum Antialias {
+    Gradient,
+    Supersample,
+}
+
 fn run2d<F: fidget::eval::Function + fidget::render::RenderHints>(
     shape: fidget::shape::Shape<F>,
     settings: &ImageSettings,
     brute: bool,
     sdf: bool,
+    antialias: Option<Antialias>,
 ) -> Vec<u8> {
     if brute {
         let tape = shape.float_slice_tape(Default::default());
@@ -203,7 +387,25 @@ fn run2d<F: fidget::eval::Function + fidget::render::RenderHints>(
             threads: settings.threads.into(),
             ..Default::default()
         };
-        if sdf {
+        if let Some(aa) = antialias {
+            let mut image = vec![];
+            for _ in 0..settings.n {
+                image = match aa {
+                    Antialias::Gradient => cfg
+                        .run::<_, fidget::render::CoverageRenderMode>(
+                            shape.clone(),
+                        ),
+                    Antialias::Supersample => cfg.run::<
+                        _,
+                        fidget::render::SupersampledCoverageRenderMode<4>,
+                    >(shape.clone()),
+                };
+            }
+            image
+                .into_iter()
+                .flat_map(|a| [a, a, a, 255].into_iter())
+                .collect()
+        } else if sdf {
             let mut image = vec![];
             for _ in 0..settings.n {
                 image =
@@ -232,19 +434,112 @@ fn run2d<F: fidget::eval::Function + fidget::render::RenderHints>(
 fn run_mesh<F: fidget::eval::Function + fidget::render::RenderHints>(
     shape: fidget::shape::Shape<F>,
     settings: &MeshSettings,
-) -> fidget::mesh::Mesh {
+) -> Result<fidget::mesh::Mesh> {
     let mut mesh = fidget::mesh::Mesh::new();
 
     for _ in 0..settings.n {
-        let settings = fidget::mesh::Settings {
-            threads: settings.threads.into(),
+        let settings = settings.to_settings();
+        let octree = fidget::mesh::Octree::build(&shape, settings);
+        mesh = octree.walk_dual(settings);
+    }
+    if settings.normals {
+        mesh.compute_normals(&shape)?;
+    }
+    Ok(mesh)
+}
+
+fn stream_mesh<F: fidget::eval::Function + fidget::render::RenderHints>(
+    shape: fidget::shape::Shape<F>,
+    settings: &MeshSettings,
+    out: &std::path::Path,
+) -> Result<()> {
+    let settings = settings.to_settings();
+    let f = std::io::BufWriter::new(std::fs::File::create(out)?);
+    match out.extension().and_then(|e| e.to_str()) {
+        Some("ply") => {
+            info!("Streaming PLY to {out:?}");
+            // Faces are spooled to a temporary file, then appended to the
+            // output after the vertices
+            let path = std::env::temp_dir()
+                .join(format!("fidget-{}.faces", std::process::id()));
+            let spool = std::fs::File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?;
+            let r = fidget::mesh::Octree::build_to(
+                &shape,
+                settings,
+                &mut fidget::mesh::PlyWriter::new(f, spool),
+            );
+            std::fs::remove_file(&path)?;
+            r?;
+        }
+        Some("obj" | "3mf" | "glb") => {
+            anyhow::bail!("only STL and PLY files can be streamed")
+        }
+        _ => {
+            info!("Streaming STL to {out:?}");
+            fidget::mesh::Octree::build_to(
+                &shape,
+                settings,
+                &mut fidget::mesh::StlWriter::new(f),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn run_contour<F: fidget::eval::Function>(
+    shape: fidget::shape::Shape<F>,
+    settings: &ContourSettings,
+) -> fidget::mesh::Contours {
+    let mut contours = fidget::mesh::Contours::default();
+    for _ in 0..settings.n {
+        let settings = fidget::mesh::ContourSettings {
             depth: settings.depth,
             ..Default::default()
         };
-        let octree = fidget::mesh::Octree::build(&shape, settings);
-        mesh = octree.walk_dual(settings);
+        contours = fidget::mesh::Contours::build(&shape, settings);
+    }
+    contours
+}
+
+fn run_field<F: fidget::eval::Function + fidget::render::RenderHints>(
+    shape: fidget::shape::Shape<F>,
+    settings: &ImageSettings,
+) -> Vec<f32> {
+    let cfg = fidget::render::ImageRenderConfig {
+        image_size: fidget::render::ImageSize::from(settings.size),
+        tile_sizes: F::tile_sizes_2d(),
+        threads: settings.threads.into(),
+        ..Default::default()
+    };
+    cfg.run::<_, fidget::render::FieldRenderMode>(shape)
+}
+
+fn run_slice<F: fidget::eval::Function + fidget::render::RenderHints>(
+    shape: fidget::shape::Shape<F>,
+    settings: &SliceSettings,
+) -> Vec<Vec<bool>> {
+    let cfg = fidget::render::ImageRenderConfig {
+        image_size: fidget::render::ImageSize::from(settings.size),
+        tile_sizes: F::tile_sizes_2d(),
+        threads: settings.threads.into(),
+        ..Default::default()
+    };
+    let slices = fidget::render::SliceConfig::spaced(
+        settings.pitch,
+        settings.z_min,
+        settings.z_max,
+    );
+    let mut layers = vec![];
+    for _ in 0..settings.n {
+        layers =
+            slices.run::<_, fidget::render::BitRenderMode>(shape.clone(), &cfg);
     }
-    mesh
+    layers
 }
 
 fn main() -> Result<()> {
@@ -262,19 +557,33 @@ fn main() -> Result<()> {
             settings,
             brute,
             sdf,
+            antialias,
+            supersample,
+            field,
         } => {
+            let antialias = antialias.then_some(if supersample {
+                Antialias::Supersample
+            } else {
+                Antialias::Gradient
+            });
             let start = Instant::now();
-            let buffer = match settings.eval {
+            let (buffer, values) = match settings.eval {
                 #[cfg(feature = "jit")]
                 EvalMode::Jit => {
                     let shape = fidget::jit::JitShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run2d(shape, &settings, brute, sdf)
+                    let values = field
+                        .as_ref()
+                        .map(|_| run_field(shape.clone(), &settings));
+                    (run2d(shape, &settings, brute, sdf, antialias), values)
                 }
                 EvalMode::Vm => {
                     let shape = fidget::vm::VmShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run2d(shape, &settings, brute, sdf)
+                    let values = field
+                        .as_ref()
+                        .map(|_| run_field(shape.clone(), &settings));
+                    (run2d(shape, &settings, brute, sdf, antialias), values)
                 }
             };
 
@@ -294,24 +603,73 @@ fn main() -> Result<()> {
                     image::ColorType::Rgba8,
                 )?;
             }
+            if let (Some(out), Some(values)) = (field, values) {
+                info!("Writing field values to {out:?}");
+                fidget::render::write_pfm(
+                    &mut std::fs::File::create(out)?,
+                    fidget::render::ImageSize::from(settings.size),
+                    &values,
+                )?;
+            }
         }
         Command::Render3d {
             settings,
             color,
             isometric,
+            fov,
+            raymarch,
+            shade,
+            shadows,
+            occlusion,
+            depth,
+            normals,
         } => {
+            let shade = shade.then(|| fidget::render::ShadeConfig {
+                shadows,
+                occlusion: match occlusion {
+                    OcclusionMode::None => fidget::render::Occlusion::None,
+                    OcclusionMode::Screen => {
+                        fidget::render::Occlusion::ScreenSpace {
+                            radius: (settings.size as usize / 64).max(2),
+                            samples: 16,
+                        }
+                    }
+                    OcclusionMode::Sdf => fidget::render::Occlusion::Sdf {
+                        distance: 0.1,
+                        steps: 5,
+                    },
+                },
+                ..Default::default()
+            });
+            let fov = (!isometric).then_some(fov);
             let start = Instant::now();
-            let buffer = match settings.eval {
+            let (buffer, heightmap, normal_map) = match settings.eval {
                 #[cfg(feature = "jit")]
                 EvalMode::Jit => {
                     let shape = fidget::jit::JitShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run3d(shape, &settings, isometric, color)
+                    run3d(
+                        shape,
+                        &settings,
+                        fov,
+                        raymarch,
+                        color,
+                        shade.as_ref(),
+                        normals.is_some(),
+                    )
                 }
                 EvalMode::Vm => {
                     let shape = fidget::vm::VmShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run3d(shape, &settings, isometric, color)
+                    run3d(
+                        shape,
+                        &settings,
+                        fov,
+                        raymarch,
+                        color,
+                        shade.as_ref(),
+                        normals.is_some(),
+                    )
                 }
             };
             info!(
@@ -332,6 +690,66 @@ fn main() -> Result<()> {
                     image::ColorType::Rgba8,
                 )?;
             }
+            let image_size = fidget::render::ImageSize::from(settings.size);
+            if let Some(out) = depth {
+                match out.extension().and_then(|e| e.to_str()) {
+                    Some("pfm") => {
+                        info!("Writing PFM depth to {out:?}");
+                        let values: Vec<f32> =
+                            heightmap.iter().map(|d| *d as f32).collect();
+                        fidget::render::write_pfm(
+                            &mut std::fs::File::create(&out)?,
+                            image_size,
+                            &values,
+                        )?;
+                    }
+                    _ => {
+                        info!("Writing 16-bit PNG depth to {out:?}");
+                        // Scale so that the full render depth maps to u16::MAX
+                        let max = settings.size.max(1) as u64;
+                        let values: Vec<u16> = heightmap
+                            .iter()
+                            .map(|d| {
+                                ((*d as u64).min(max) * u16::MAX as u64 / max)
+                                    as u16
+                            })
+                            .collect();
+                        image::ImageBuffer::<image::Luma<u16>, _>::from_raw(
+                            settings.size,
+                            settings.size,
+                            values,
+                        )
+                        .unwrap()
+                        .save(out)?;
+                    }
+                }
+            }
+            if let (Some(out), Some(normal_map)) = (normals, normal_map) {
+                info!("Writing normals to {out:?}");
+                fidget::render::write_pfm_rgb(
+                    &mut std::fs::File::create(out)?,
+                    image_size,
+                    &normal_map,
+                )?;
+            }
+        }
+        Command::Mesh { settings } if settings.stream => {
+            let start = Instant::now();
+            let out = settings.out.as_ref().unwrap();
+            match settings.eval {
+                #[cfg(feature = "jit")]
+                EvalMode::Jit => {
+                    let shape = fidget::jit::JitShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    stream_mesh(shape, &settings, out)?
+                }
+                EvalMode::Vm => {
+                    let shape = fidget::vm::VmShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    stream_mesh(shape, &settings, out)?
+                }
+            };
+            info!("Streamed mesh in {:?}", start.elapsed());
         }
         Command::Mesh { settings } => {
             let start = Instant::now();
@@ -340,12 +758,12 @@ fn main() -> Result<()> {
                 EvalMode::Jit => {
                     let shape = fidget::jit::JitShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run_mesh(shape, &settings)
+                    run_mesh(shape, &settings)?
                 }
                 EvalMode::Vm => {
                     let shape = fidget::vm::VmShape::new(&ctx, root)?;
                     info!("Built shape in {:?}", start.elapsed());
-                    run_mesh(shape, &settings)
+                    run_mesh(shape, &settings)?
                 }
             };
             info!(
@@ -356,8 +774,110 @@ fn main() -> Result<()> {
                     / (settings.n as f64)
             );
             if let Some(out) = settings.out {
-                info!("Writing STL to {out:?}");
-                mesh.write_stl(&mut std::fs::File::create(out)?)?;
+                let mut f = std::fs::File::create(&out)?;
+                match out.extension().and_then(|e| e.to_str()) {
+                    Some("obj") => {
+                        info!("Writing OBJ to {out:?}");
+                        mesh.write_obj(&mut f)?;
+                    }
+                    Some("ply") => {
+                        info!("Writing PLY to {out:?}");
+                        mesh.write_ply(&mut f)?;
+                    }
+                    Some("3mf") => {
+                        info!("Writing 3MF to {out:?}");
+                        mesh.write_3mf(&mut f)?;
+                    }
+                    Some("glb") => {
+                        info!("Writing GLB to {out:?}");
+                        mesh.write_glb(&mut f)?;
+                    }
+                    _ if settings.ascii => {
+                        info!("Writing ASCII STL to {out:?}");
+                        mesh.write_stl_ascii(&mut f)?;
+                    }
+                    _ => {
+                        info!("Writing STL to {out:?}");
+                        mesh.write_stl(&mut f)?;
+                    }
+                }
+            }
+        }
+        Command::Contour { settings } => {
+            let start = Instant::now();
+            let contours = match settings.eval {
+                #[cfg(feature = "jit")]
+                EvalMode::Jit => {
+                    let shape = fidget::jit::JitShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    run_contour(shape, &settings)
+                }
+                EvalMode::Vm => {
+                    let shape = fidget::vm::VmShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    run_contour(shape, &settings)
+                }
+            };
+            info!(
+                "Contoured {}x at {:?} ms/iter",
+                settings.n,
+                start.elapsed().as_micros() as f64
+                    / 1000.0
+                    / (settings.n as f64)
+            );
+            if let Some(out) = settings.out {
+                let mut f = std::fs::File::create(&out)?;
+                match out.extension().and_then(|e| e.to_str()) {
+                    Some("dxf") => {
+                        info!("Writing DXF to {out:?}");
+                        contours.write_dxf(&mut f)?;
+                    }
+                    _ => {
+                        info!("Writing SVG to {out:?}");
+                        contours.write_svg(&mut f)?;
+                    }
+                }
+            }
+        }
+        Command::Slice { settings } => {
+            let start = Instant::now();
+            let layers = match settings.eval {
+                #[cfg(feature = "jit")]
+                EvalMode::Jit => {
+                    let shape = fidget::jit::JitShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    run_slice(shape, &settings)
+                }
+                EvalMode::Vm => {
+                    let shape = fidget::vm::VmShape::new(&ctx, root)?;
+                    info!("Built shape in {:?}", start.elapsed());
+                    run_slice(shape, &settings)
+                }
+            };
+            info!(
+                "Sliced {} layers {}x at {:?} ms/iter",
+                layers.len(),
+                settings.n,
+                start.elapsed().as_micros() as f64
+                    / 1000.0
+                    / (settings.n as f64)
+            );
+            if let Some(out) = settings.out {
+                info!("Writing {} layers to {out:?}", layers.len());
+                std::fs::create_dir_all(&out)?;
+                for (i, layer) in layers.iter().enumerate() {
+                    let buffer: Vec<u8> = layer
+                        .iter()
+                        .map(|&p| if p { u8::MAX } else { 0 })
+                        .collect();
+                    image::save_buffer(
+                        out.join(format!("layer_{i:04}.png")),
+                        &buffer,
+                        settings.size,
+                        settings.size,
+                        image::ColorType::L8,
+                    )?;
+                }
             }
         }
     }
@@ -4,7 +4,7 @@ use crossbeam_channel::{unbounded, Receiver, Sender};
 use eframe::egui;
 use env_logger::Env;
 use log::{debug, error, info, warn};
-use nalgebra::{Point2, Vector3};
+use nalgebra::{Point2, Point3};
 use notify::Watcher;
 
 use fidget::render::{ImageRenderConfig, View2, View3, VoxelRenderConfig};
@@ -131,16 +131,38 @@ where
                 egui::Color32::BLACK,
             );
             let render_start = std::time::Instant::now();
-            for s in out.shapes.iter() {
-                let tape = fidget::shape::Shape::<F>::from(s.tree.clone());
-                render(
-                    &render_config.mode,
-                    tape,
-                    render_config.image_size,
-                    s.color_rgb,
-                    &mut image.pixels,
-                );
-            }
+            let shapes: Vec<_> = out
+                .shapes
+                .iter()
+                .map(|s| {
+                    (
+                        fidget::shape::Shape::<F>::from(s.tree.clone()),
+                        s.color_rgb,
+                    )
+                })
+                .collect();
+            // Send intermediate images as they're refined, so that complex
+            // models show up before the whole frame is done
+            let size = image.size;
+            let progress = |pixels: &[egui::Color32]| {
+                let mut image =
+                    egui::ColorImage::new(size, egui::Color32::BLACK);
+                image.pixels.copy_from_slice(pixels);
+                let image = egui::ImageData::Color(std::sync::Arc::new(image));
+                let _ = tx.send(Ok(RenderResult {
+                    image,
+                    dt: render_start.elapsed(),
+                    image_size: render_config.image_size,
+                }));
+                wake.send(()).unwrap();
+            };
+            render(
+                &render_config.mode,
+                &shapes,
+                render_config.image_size,
+                &mut image.pixels,
+                progress,
+            );
             let dt = render_start.elapsed();
             let image = egui::ImageData::Color(std::sync::Arc::new(image));
             tx.send(Ok(RenderResult {
@@ -156,10 +178,10 @@ where
 
 fn render<F: fidget::eval::Function + fidget::render::RenderHints>(
     mode: &RenderMode,
-    shape: fidget::shape::Shape<F>,
+    shapes: &[(fidget::shape::Shape<F>, [u8; 3])],
     image_size: fidget::render::ImageSize,
-    color: [u8; 3],
     pixels: &mut [egui::Color32],
+    mut progress: impl FnMut(&[egui::Color32]),
 ) {
     match mode {
         RenderMode::TwoD { view, mode, .. } => {
@@ -172,62 +194,77 @@ fn render<F: fidget::eval::Function + fidget::render::RenderHints>(
 
             match mode {
                 Mode2D::Color => {
-                    let image =
-                        config.run::<_, fidget::render::BitRenderMode>(shape);
-                    let c = egui::Color32::from_rgba_unmultiplied(
-                        color[0],
-                        color[1],
-                        color[2],
-                        u8::MAX,
-                    );
-                    for (p, &i) in pixels.iter_mut().zip(&image) {
-                        if i {
-                            *p = c;
+                    let image = config.run_scene(shapes);
+                    for (p, i) in pixels.iter_mut().zip(&image) {
+                        if let Some(c) = i {
+                            *p = egui::Color32::from_rgb(c[0], c[1], c[2]);
                         }
                     }
                 }
 
                 Mode2D::Sdf => {
-                    let image =
-                        config.run::<_, fidget::render::SdfRenderMode>(shape);
-                    for (p, i) in pixels.iter_mut().zip(&image) {
-                        *p = egui::Color32::from_rgb(i[0], i[1], i[2]);
+                    for (shape, _) in shapes {
+                        config
+                            .run_progressive::<_, fidget::render::SdfRenderMode>(
+                                shape.clone(),
+                                |image| {
+                                    for (p, i) in pixels.iter_mut().zip(image)
+                                    {
+                                        *p = egui::Color32::from_rgb(
+                                            i[0], i[1], i[2],
+                                        );
+                                    }
+                                    progress(pixels);
+                                },
+                            );
                     }
                 }
 
                 Mode2D::Debug => {
-                    let image =
-                        config.run::<_, fidget::render::DebugRenderMode>(shape);
-                    for (p, i) in pixels.iter_mut().zip(&image) {
-                        let c = i.as_debug_color();
-                        *p = egui::Color32::from_rgb(c[0], c[1], c[2]);
+                    for (shape, _) in shapes {
+                        config
+                            .run_progressive::<_, fidget::render::DebugRenderMode>(
+                                shape.clone(),
+                                |image| {
+                                    for (p, i) in pixels.iter_mut().zip(image)
+                                    {
+                                        let c = i.as_debug_color();
+                                        *p = egui::Color32::from_rgb(
+                                            c[0], c[1], c[2],
+                                        );
+                                    }
+                                    progress(pixels);
+                                },
+                            );
                     }
                 }
             }
         }
         RenderMode::ThreeD(camera, mode) => {
-            // XXX allow selection of depth?
+            // Pick a depth so that the Z axis spans ±1 in world coordinates
             let config = VoxelRenderConfig {
                 image_size: fidget::render::VoxelSize::new(
                     image_size.width(),
                     image_size.height(),
-                    512,
+                    image_size.width().min(image_size.height()),
                 ),
                 tile_sizes: F::tile_sizes_3d(),
-                view: View3::from_center_and_scale(
-                    Vector3::new(camera.offset.x, camera.offset.y, 0.0),
-                    camera.scale,
-                ),
+                view: camera.view,
                 ..Default::default()
             };
-            let (depth, color) = config.run(shape);
+            let (depth, norm, color) = config.run_scene(shapes);
             match mode {
                 ThreeDMode::Color => {
-                    for (p, (&d, &c)) in
-                        pixels.iter_mut().zip(depth.iter().zip(&color))
+                    for (p, (n, c)) in
+                        pixels.iter_mut().zip(norm.iter().zip(&color))
                     {
-                        if d != 0 {
-                            *p = egui::Color32::from_rgb(c[0], c[1], c[2]);
+                        // Shade each shape's color by how much its surface
+                        // faces the camera
+                        if let Some(c) = c {
+                            let s = n[2] as u32;
+                            let [r, g, b] =
+                                c.map(|c| (c as u32 * s / 255) as u8);
+                            *p = egui::Color32::from_rgb(r, g, b);
                         }
                     }
                 }
@@ -340,35 +377,20 @@ enum Mode2D {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 struct ThreeDCamera {
-    // 2D camera parameters
-    scale: f32,
-    offset: nalgebra::Vector3<f32>,
-    #[allow(unused)]
-    drag_start: Option<egui::Vec2>,
-}
+    view: View3,
 
-impl ThreeDCamera {
-    #[allow(unused)]
-    fn mouse_to_uv(
-        &self,
-        rect: egui::Rect,
-        uv: egui::Rect,
-        p: egui::Pos2,
-    ) -> egui::Vec2 {
-        panic!()
-    }
+    /// Previous drag position (in world coordinates) and drag mode
+    drag_start: Option<(Point2<f32>, ThreeDDrag)>,
 }
 
-impl Default for ThreeDCamera {
-    fn default() -> Self {
-        ThreeDCamera {
-            drag_start: None,
-            scale: 1.0,
-            offset: nalgebra::Vector3::zeros(),
-        }
-    }
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum ThreeDDrag {
+    /// Rotate the model with an arcball
+    Orbit,
+    /// Pan the model, keeping the cursor in place
+    Pan,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -518,7 +540,8 @@ impl ViewerApp {
     /// Try to receive an image from the worker thread, populating
     /// `self.texture` and `self.stats`, or `self.err`
     fn try_recv_image(&mut self, ctx: &egui::Context) {
-        if let Ok(r) = self.image_rx.try_recv() {
+        // Skip over intermediate images if we've fallen behind
+        if let Some(r) = self.image_rx.try_iter().last() {
             match r {
                 Ok(r) => {
                     match self.texture.as_mut() {
@@ -562,9 +585,6 @@ impl ViewerApp {
         });
         const PADDING: egui::Vec2 = egui::Vec2 { x: 10.0, y: 10.0 };
 
-        if !matches!(self.mode, RenderMode::TwoD { .. }) {
-            panic!("can't render in 3D");
-        }
         let rect = ui.ctx().available_rect();
         let uv = egui::Rect {
             min: egui::Pos2::new(0.0, 0.0),
@@ -688,8 +708,58 @@ impl eframe::App for ViewerApp {
                     }
                 }
             }
-            RenderMode::ThreeD(..) => {
-                unimplemented!()
+            RenderMode::ThreeD(camera, ..) => {
+                let image_size = fidget::render::ImageSize::new(
+                    rect.width() as u32,
+                    rect.height() as u32,
+                );
+                let mat = image_size.screen_to_world();
+
+                if let Some(pos) = r.interact_pointer_pos() {
+                    let pos = mat.transform_point(&Point2::new(pos.x, pos.y));
+                    let drag = if r.dragged_by(egui::PointerButton::Primary) {
+                        Some(ThreeDDrag::Orbit)
+                    } else if r.dragged_by(egui::PointerButton::Secondary)
+                        || r.dragged_by(egui::PointerButton::Middle)
+                    {
+                        Some(ThreeDDrag::Pan)
+                    } else {
+                        None
+                    };
+                    match (camera.drag_start, drag) {
+                        (Some((prev, m)), Some(d)) if m == d => {
+                            match d {
+                                ThreeDDrag::Orbit => {
+                                    camera.view.arcball(prev, pos)
+                                }
+                                ThreeDDrag::Pan => camera.view.pan(
+                                    Point3::new(prev.x, prev.y, 0.0),
+                                    Point3::new(pos.x, pos.y, 0.0),
+                                ),
+                            }
+                            render_changed |= prev != pos;
+                        }
+                        _ => (),
+                    }
+                    camera.drag_start = drag.map(|d| (pos, d));
+                } else {
+                    camera.drag_start = None;
+                }
+
+                if r.hovered() {
+                    let scroll = ctx.input(|i| i.smooth_scroll_delta.y);
+                    let mouse_pos =
+                        ctx.input(|i| i.pointer.hover_pos()).map(|p| {
+                            let p = mat.transform_point(&Point2::new(p.x, p.y));
+                            camera
+                                .view
+                                .transform_point(&Point3::new(p.x, p.y, 0.0))
+                        });
+                    if scroll != 0.0 {
+                        camera.view.zoom((scroll / 100.0).exp2(), mouse_pos);
+                        render_changed = true;
+                    }
+                }
             }
         }
 
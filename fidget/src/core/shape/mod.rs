@@ -181,6 +181,17 @@ impl<F: Function + Clone> Shape<F> {
     pub fn size(&self) -> usize {
         self.f.size()
     }
+
+    /// Checks whether this shape is known to be identical to another
+    ///
+    /// Functions are compared by the address of their variable map, so this is
+    /// only `true` for clones of the same shape (with the same transform);
+    /// equivalent shapes which were built separately are not detected.
+    pub(crate) fn same_as(&self, other: &Self) -> bool {
+        std::ptr::eq(self.f.vars(), other.f.vars())
+            && self.axes == other.axes
+            && self.transform == other.transform
+    }
 }
 
 impl<F> Shape<F> {
@@ -218,6 +229,7 @@ impl<F> Shape<F> {
 /// Note that this cannot store `X`, `Y`, `Z` variables (which are passed in as
 /// first-class arguments); it only stores [`Var::V`] values (identified by
 /// their inner [`VarIndex`]).
+#[derive(Clone, Debug, PartialEq)]
 pub struct ShapeVars<F>(HashMap<VarIndex, F>);
 
 impl<F> Default for ShapeVars<F> {
@@ -699,7 +711,32 @@ impl Transformable for Interval {
             x * row[0] + y * row[1] + z * row[2] + Interval::from(row[3])
         });
 
-        (out[0] / out[3], out[1] / out[3], out[2] / out[3])
+        // For projective transforms (e.g. perspective), dividing by `w` as an
+        // independent interval produces overly loose bounds.  If `w` doesn't
+        // change sign within the region, then each output coordinate is a
+        // linear-fractional function of the input, so its extrema are found at
+        // the corners of the box.
+        let w = out[3];
+        let affine = mat.row(3).iter().take(3).all(|v| *v == 0.0);
+        if !affine && (w.lower() > 0.0 || w.upper() < 0.0) {
+            let mut lo = [f32::INFINITY; 3];
+            let mut hi = [f32::NEG_INFINITY; 3];
+            for px in [x.lower(), x.upper()] {
+                for py in [y.lower(), y.upper()] {
+                    for pz in [z.lower(), z.upper()] {
+                        let p = mat.transform_point(&Point3::new(px, py, pz));
+                        for i in 0..3 {
+                            lo[i] = lo[i].min(p[i]);
+                            hi[i] = hi[i].max(p[i]);
+                        }
+                    }
+                }
+            }
+            let [x, y, z] = [0, 1, 2].map(|i| Interval::new(lo[i], hi[i]));
+            return (x, y, z);
+        }
+
+        (out[0] / w, out[1] / w, out[2] / w)
     }
 }
 
@@ -724,6 +761,47 @@ mod test {
     use super::*;
     use crate::vm::VmShape;
 
+    #[test]
+    fn interval_projective_transform() {
+        let mut mat = Matrix4::identity();
+        mat[(3, 2)] = 0.5;
+        mat[(3, 3)] = 2.0;
+
+        let x = Interval::new(-1.0, 1.0);
+        let y = Interval::new(0.0, 1.0);
+        let z = Interval::new(-1.0, 1.0);
+        let (ox, oy, oz) = Transformable::transform(x, y, z, mat);
+
+        // Check that every sampled point lands within the interval bounds, and
+        // that the bounds are tight (i.e. reached by some corner)
+        let mut seen = [[f32::INFINITY, f32::NEG_INFINITY]; 3];
+        for i in 0..=8 {
+            for j in 0..=8 {
+                for k in 0..=8 {
+                    let p = Point3::new(
+                        -1.0 + i as f32 / 4.0,
+                        j as f32 / 8.0,
+                        -1.0 + k as f32 / 4.0,
+                    );
+                    let q = mat.transform_point(&p);
+                    for (a, r) in [ox, oy, oz].iter().enumerate() {
+                        assert!(
+                            q[a] >= r.lower() - 1e-6
+                                && q[a] <= r.upper() + 1e-6,
+                            "{q:?} is outside {r:?}"
+                        );
+                        seen[a][0] = seen[a][0].min(q[a]);
+                        seen[a][1] = seen[a][1].max(q[a]);
+                    }
+                }
+            }
+        }
+        for (a, r) in [ox, oy, oz].iter().enumerate() {
+            assert!((seen[a][0] - r.lower()).abs() < 1e-6);
+            assert!((seen[a][1] - r.upper()).abs() < 1e-6);
+        }
+    }
+
     #[test]
     fn shape_vars() {
         let v = Var::new();
@@ -81,6 +81,30 @@ pub enum Error {
     #[error("tile size list must not be empty")]
     EmptyTileSizes,
 
+    /// Per-vertex attribute count does not match the number of vertices
+    #[error("attribute count ({0}) does not match vertex count ({1})")]
+    BadAttributeCount(usize, usize),
+
+    /// Per-triangle material count does not match the number of triangles
+    #[error("material count ({0}) does not match triangle count ({1})")]
+    BadMaterialCount(usize, usize),
+
+    /// Material index is out of range of the material list
+    #[error("material index ({0}) is out of range ({1} materials)")]
+    BadMaterialIndex(u32, usize),
+
+    /// Mesh has too many elements to be represented in an output format
+    #[error("mesh is too large for the {0} format")]
+    MeshTooLarge(&'static str),
+
+    /// Mesh setting is not supported by this mesher
+    #[error("mesh setting `{0}` is not supported by this mesher")]
+    UnsupportedMeshSetting(&'static str),
+
+    /// Mesh has no triangles, which the output format does not allow
+    #[error("mesh has no triangles")]
+    EmptyMesh,
+
     /// Rhai error; see inner code for details
     #[cfg(feature = "rhai")]
     #[error("Rhai evaluation error: {0}")]
@@ -91,6 +115,11 @@ pub enum Error {
     #[error("Rhai evaluation error: {0}")]
     RhaiEvalError(#[from] rhai::EvalAltResult),
 
+    /// ZIP error when writing a 3MF package; see inner code for details
+    #[cfg(feature = "3mf")]
+    #[error("zip error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+
     #[cfg(feature = "jit")]
     /// Dynasm error; see inner code for details
     #[error("dynasm error: {0}")]
@@ -325,7 +325,7 @@ impl<T> AssemblerData<T> {
             + stack_size;
 
         // Round up to the nearest multiple of 16 bytes, for alignment
-        self.mem_offset = ((mem + 15) / 16) * 16;
+        self.mem_offset = mem.div_ceil(16) * 16;
         self.push_stack();
     }
 
@@ -1178,7 +1178,7 @@ impl<T: From<f32> + Copy + SimdSize> JitBulkEval<T> {
         &mut self,
         tape: &JitBulkFn<T>,
         vars: &[V],
-    ) -> BulkOutput<T> {
+    ) -> BulkOutput<'_, T> {
         let n = vars.first().map(|v| v.deref().len()).unwrap_or(0);
 
         const OUTPUT_COUNT: usize = 1;
@@ -1272,7 +1272,7 @@ impl BulkEvaluator for JitFloatSliceEval {
         &mut self,
         tape: &Self::Tape,
         vars: &[V],
-    ) -> Result<BulkOutput<f32>, Error> {
+    ) -> Result<BulkOutput<'_, f32>, Error> {
         tape.vars().check_bulk_arguments(vars)?;
         Ok(self.0.eval(tape, vars))
     }
@@ -1290,7 +1290,7 @@ impl BulkEvaluator for JitGradSliceEval {
         &mut self,
         tape: &Self::Tape,
         vars: &[V],
-    ) -> Result<BulkOutput<Grad>, Error> {
+    ) -> Result<BulkOutput<'_, Grad>, Error> {
         tape.vars().check_bulk_arguments(vars)?;
         Ok(self.0.eval(tape, vars))
     }
@@ -18,11 +18,43 @@ pub struct MeshBuilder {
 }
 
 impl MeshBuilder {
-    pub fn take(self) -> Mesh {
+    pub fn take(mut self) -> Mesh {
+        let order = self.map.iter().filter(|&&i| i != usize::MAX).copied();
+        canonicalize(&mut self.out, order);
         self.out
     }
 }
 
+/// Reorders a mesh into a canonical order
+///
+/// `order` must yield every vertex index in the mesh exactly once, sorted by
+/// the index of the corresponding vertex in a
+/// canonical octree (i.e. in depth-first order).  Triangles are rotated so that
+/// their smallest vertex index is first (preserving winding), then sorted.
+///
+/// The resulting mesh is independent of the order in which vertices and
+/// triangles were emitted during dual contouring (e.g. across threads).
+pub(crate) fn canonicalize(
+    mesh: &mut Mesh,
+    order: impl Iterator<Item = usize>,
+) {
+    let mut remap = vec![0; mesh.vertices.len()];
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    for (i, v) in order.enumerate() {
+        remap[v] = i;
+        vertices.push(mesh.vertices[v]);
+    }
+    debug_assert_eq!(vertices.len(), mesh.vertices.len());
+    mesh.vertices = vertices;
+
+    for t in &mut mesh.triangles {
+        let t2 = t.map(|v| remap[v]);
+        let k = (0..3).min_by_key(|&k| t2[k]).unwrap();
+        *t = nalgebra::Vector3::new(t2[k], t2[(k + 1) % 3], t2[(k + 2) % 3]);
+    }
+    mesh.triangles.sort_unstable_by_key(|t| (t.x, t.y, t.z));
+}
+
 impl DcBuilder for MeshBuilder {
     type VertexIndex = usize;
 
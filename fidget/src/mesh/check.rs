@@ -0,0 +1,454 @@
+//! Mesh validation
+use super::Mesh;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, HashMap};
+
+/// Problems found by [`Mesh::validate`]
+///
+/// Edges are given as pairs of vertex indices (with the smaller index first),
+/// and triangles as indices into [`Mesh::triangles`].  Every list is sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    /// Edges which are shared by more than two triangles
+    pub non_manifold_edges: Vec<[usize; 2]>,
+
+    /// Edges which are only used by a single triangle (i.e. holes)
+    pub boundary_edges: Vec<[usize; 2]>,
+
+    /// Edges shared by two triangles with inconsistent winding
+    pub flipped_edges: Vec<[usize; 2]>,
+
+    /// Triangles with repeated or out-of-range vertices, or with zero area
+    pub degenerate_triangles: Vec<usize>,
+
+    /// Pairs of triangles which intersect away from their shared vertices
+    pub self_intersections: Vec<[usize; 2]>,
+}
+
+impl MeshReport {
+    /// Checks that every edge is shared by at most two triangles
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges.is_empty()
+    }
+
+    /// Checks that every edge is shared by exactly two triangles
+    pub fn is_watertight(&self) -> bool {
+        self.is_manifold() && self.boundary_edges.is_empty()
+    }
+
+    /// Checks that neighboring triangles have consistent winding
+    pub fn is_oriented(&self) -> bool {
+        self.flipped_edges.is_empty()
+    }
+
+    /// Checks that no problems were found
+    pub fn is_valid(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Mesh {
+    /// Checks the mesh for topological and geometric problems
+    ///
+    /// Self-intersections are found by bucketing triangles into a uniform
+    /// grid, then testing each pair of triangles which share a grid cell;
+    /// triangles which are much larger than average are tested against every
+    /// other triangle instead.
+    /// Triangles which are _coplanar_ and overlapping are not detected, and
+    /// degenerate triangles are excluded from intersection tests.
+    pub fn validate(&self) -> MeshReport {
+        let mut out = MeshReport::default();
+
+        // Map from undirected edge to (forward, reverse) use counts
+        let mut edges: BTreeMap<[usize; 2], [usize; 2]> = BTreeMap::new();
+        let mut tris = vec![];
+        for (i, t) in self.triangles.iter().enumerate() {
+            if t.iter().any(|v| *v >= self.vertices.len())
+                || t.x == t.y
+                || t.y == t.z
+                || t.z == t.x
+            {
+                out.degenerate_triangles.push(i);
+                continue;
+            }
+            for (a, b) in [(t.x, t.y), (t.y, t.z), (t.z, t.x)] {
+                if a < b {
+                    edges.entry([a, b]).or_default()[0] += 1;
+                } else {
+                    edges.entry([b, a]).or_default()[1] += 1;
+                }
+            }
+
+            let p = [t.x, t.y, t.z].map(|v| self.vertices[v].cast::<f64>());
+            let longest = (0..3)
+                .map(|j| (p[j] - p[(j + 1) % 3]).norm_squared())
+                .fold(0.0, f64::max);
+            let area = (p[1] - p[0]).cross(&(p[2] - p[0])).norm();
+            if area <= longest * f64::EPSILON {
+                out.degenerate_triangles.push(i);
+            } else {
+                tris.push((i, p));
+            }
+        }
+
+        for (e, [fwd, rev]) in edges {
+            match fwd + rev {
+                1 => out.boundary_edges.push(e),
+                2 if fwd != 1 => out.flipped_edges.push(e),
+                2 => (),
+                _ => out.non_manifold_edges.push(e),
+            }
+        }
+
+        out.self_intersections = self_intersections(&tris);
+        out
+    }
+}
+
+/// Finds intersecting pairs among a set of (index, position) triangles
+///
+/// Triangles are bucketed into a uniform grid, sized by the average triangle.
+/// Triangles which would span more than `MAX_CELLS` cells along any axis
+/// aren't inserted into the grid (which could otherwise need a huge number of
+/// cells); instead, they're tested against every other triangle's bounds.
+fn self_intersections(tris: &[(usize, [Vector3<f64>; 3])]) -> Vec<[usize; 2]> {
+    /// Maximum number of grid cells that a triangle may span along each axis
+    const MAX_CELLS: i64 = 8;
+
+    if tris.is_empty() {
+        return vec![];
+    }
+    let bounds: Vec<_> = tris
+        .iter()
+        .map(|(_, p)| (p[0].inf(&p[1]).inf(&p[2]), p[0].sup(&p[1]).sup(&p[2])))
+        .collect();
+
+    // Pick a grid size based on the average triangle size
+    let size = bounds.iter().map(|(lo, hi)| (hi - lo).max()).sum::<f64>()
+        / bounds.len() as f64;
+    let key = |p: &Vector3<f64>| p.map(|v| (v / size).floor() as i64);
+
+    let mut out = vec![];
+    let mut check = |i: usize, j: usize| {
+        let (lo_i, hi_i) = &bounds[i];
+        let (lo_j, hi_j) = &bounds[j];
+        if (0..3).any(|a| lo_i[a] > hi_j[a] || lo_j[a] > hi_i[a]) {
+            return;
+        }
+        let (a, pa) = &tris[i];
+        let (b, pb) = &tris[j];
+        if triangles_intersect(pa, pb) {
+            out.push([*a.min(b), *a.max(b)]);
+        }
+    };
+
+    let mut grid: HashMap<Vector3<i64>, Vec<usize>> = HashMap::new();
+    let mut large = vec![false; tris.len()];
+    for (i, (lo, hi)) in bounds.iter().enumerate() {
+        let (lo, hi) = (key(lo), key(hi));
+        if (hi - lo).max() >= MAX_CELLS {
+            large[i] = true;
+            continue;
+        }
+        for x in lo.x..=hi.x {
+            for y in lo.y..=hi.y {
+                for z in lo.z..=hi.z {
+                    grid.entry(Vector3::new(x, y, z)).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    for (k, cell) in &grid {
+        for (n, &i) in cell.iter().enumerate() {
+            for &j in &cell[n + 1..] {
+                // Only test each pair once, in the first cell that they share
+                if key(&bounds[i].0).sup(&key(&bounds[j].0)) == *k {
+                    check(i, j);
+                }
+            }
+        }
+    }
+
+    // Test large triangles against everything else, and against each other
+    // only once
+    for i in (0..tris.len()).filter(|i| large[*i]) {
+        for (j, &lj) in large.iter().enumerate() {
+            if j != i && (!lj || j > i) {
+                check(i, j);
+            }
+        }
+    }
+
+    out.sort();
+    out
+}
+
+/// Checks whether two triangles intersect
+///
+/// Vertices at identical positions are treated as shared, so triangles which
+/// meet at a vertex or edge don't count as intersecting.
+fn triangles_intersect(a: &[Vector3<f64>; 3], b: &[Vector3<f64>; 3]) -> bool {
+    let shared: Vec<(usize, usize)> = (0..3)
+        .flat_map(|i| (0..3).map(move |j| (i, j)))
+        .filter(|&(i, j)| a[i] == b[j])
+        .collect();
+    match shared.as_slice() {
+        [] => {
+            (0..3).any(|i| segment_hits(a[i], a[(i + 1) % 3], b))
+                || (0..3).any(|i| segment_hits(b[i], b[(i + 1) % 3], a))
+        }
+        // With a single shared vertex, any intersection is a segment which
+        // starts at that vertex and ends on one of the opposite edges
+        [(i, j)] => {
+            segment_hits(a[(i + 1) % 3], a[(i + 2) % 3], b)
+                || segment_hits(b[(j + 1) % 3], b[(j + 2) % 3], a)
+        }
+        _ => false,
+    }
+}
+
+/// Checks whether the segment `p-q` intersects the given triangle
+///
+/// This is the Möller-Trumbore algorithm, restricted to the segment's extent.
+fn segment_hits(
+    p: Vector3<f64>,
+    q: Vector3<f64>,
+    t: &[Vector3<f64>; 3],
+) -> bool {
+    let dir = q - p;
+    let e1 = t[1] - t[0];
+    let e2 = t[2] - t[0];
+    let h = dir.cross(&e2);
+    let det = e1.dot(&h);
+    if det.abs() <= f64::EPSILON * e1.norm() * e2.norm() * dir.norm() {
+        return false; // segment is parallel to the triangle
+    }
+    let s = p - t[0];
+    let u = s.dot(&h) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let r = s.cross(&e1);
+    let v = dir.dot(&r) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    let d = e2.dot(&r) / det;
+    (0.0..=1.0).contains(&d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        mesh::{Octree, Settings},
+        render::View3,
+        vm::VmShape,
+    };
+
+    /// Builds a tetrahedron with outward-facing triangles
+    fn tetrahedron() -> Mesh {
+        Mesh {
+            vertices: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+            ],
+            triangles: vec![
+                Vector3::new(0, 2, 1),
+                Vector3::new(0, 1, 3),
+                Vector3::new(0, 3, 2),
+                Vector3::new(1, 2, 3),
+            ],
+            normals: None,
+            materials: None,
+        }
+    }
+
+    #[test]
+    fn validate_tetrahedron() {
+        let mesh = tetrahedron();
+        let r = mesh.validate();
+        assert!(r.is_valid(), "{r:?}");
+        assert!(r.is_watertight());
+        assert!(r.is_oriented());
+
+        // Removing a face opens a hole
+        let mut m = tetrahedron();
+        m.triangles.pop();
+        let r = m.validate();
+        assert!(r.is_manifold());
+        assert!(!r.is_watertight());
+        assert_eq!(r.boundary_edges, [[1, 2], [1, 3], [2, 3]]);
+
+        // Flipping a face breaks orientation
+        let mut m = tetrahedron();
+        m.triangles[3] = Vector3::new(1, 3, 2);
+        let r = m.validate();
+        assert!(r.is_watertight());
+        assert_eq!(r.flipped_edges, [[1, 2], [1, 3], [2, 3]]);
+
+        // Adding a fin makes an edge non-manifold
+        let mut m = tetrahedron();
+        m.vertices.push(Vector3::new(-1.0, -1.0, 0.0));
+        m.triangles.push(Vector3::new(0, 4, 1));
+        let r = m.validate();
+        assert_eq!(r.non_manifold_edges, [[0, 1]]);
+        assert!(!r.is_manifold());
+
+        // Repeated, out-of-range, and collinear vertices are degenerate
+        let mut m = tetrahedron();
+        m.vertices.push(Vector3::new(2.0, 0.0, 0.0));
+        m.triangles.push(Vector3::new(0, 0, 1));
+        m.triangles.push(Vector3::new(0, 1, 9));
+        m.triangles.push(Vector3::new(0, 1, 4));
+        let r = m.validate();
+        assert_eq!(r.degenerate_triangles, [4, 5, 6]);
+        assert!(r.self_intersections.is_empty());
+    }
+
+    #[test]
+    fn validate_self_intersection() {
+        // Two tetrahedra, with one poking through the other
+        let mut mesh = tetrahedron();
+        let offset = Vector3::new(0.2, 0.2, 0.2);
+        let vs: Vec<_> = mesh.vertices.iter().map(|v| v + offset).collect();
+        mesh.vertices.extend(vs);
+        let ts: Vec<_> =
+            mesh.triangles.iter().map(|t| t.map(|i| i + 4)).collect();
+        mesh.triangles.extend(ts);
+
+        let r = mesh.validate();
+        assert!(r.is_watertight());
+        assert!(r.is_oriented());
+        assert!(!r.self_intersections.is_empty());
+        for [a, b] in &r.self_intersections {
+            assert!(*a < 4 && *b >= 4, "unexpected pair {a}, {b}");
+        }
+
+        // Separating them removes the intersections
+        for v in &mut mesh.vertices[4..] {
+            *v += Vector3::new(2.0, 0.0, 0.0);
+        }
+        assert!(mesh.validate().is_valid());
+    }
+
+    #[test]
+    fn validate_large_triangle() {
+        // A huge triangle through the middle of a sphere would cover millions
+        // of grid cells, so it's tested against every triangle instead
+        let (x, y, z) = Tree::axes();
+        let sphere = (x.square() + y.square() + z.square()).sqrt() - 0.6;
+        let settings = Settings {
+            depth: 5,
+            ..Default::default()
+        };
+        let (mut mesh, _) = check_mesh(&VmShape::from(sphere), settings);
+        let big = [
+            Vector3::new(-1e4, -1e4, 0.01),
+            Vector3::new(1e4, -1e4, 0.01),
+            Vector3::new(0.0, 1e4, 0.01),
+        ];
+        let n = mesh.vertices.len();
+        mesh.vertices.extend(big);
+        mesh.triangles.push(nalgebra::Vector3::new(n, n + 1, n + 2));
+        let i = mesh.triangles.len() - 1;
+
+        let r = mesh.validate();
+        let big = big.map(|v| v.cast::<f64>());
+        let expected: Vec<_> = mesh.triangles[..i]
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| {
+                let p = [t.x, t.y, t.z].map(|v| mesh.vertices[v].cast());
+                triangles_intersect(&big, &p)
+            })
+            .map(|(j, _)| [j, i])
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(r.self_intersections, expected);
+    }
+
+    fn check_mesh(shape: &VmShape, settings: Settings) -> (Mesh, MeshReport) {
+        let octree = Octree::build(shape, settings);
+        let mesh = octree.walk_dual(settings);
+        assert!(!mesh.triangles.is_empty());
+        let r = mesh.validate();
+        assert!(r.is_watertight(), "mesh is not watertight: {r:?}");
+        assert!(r.is_oriented(), "mesh is not oriented: {r:?}");
+        (mesh, r)
+    }
+
+    #[test]
+    fn validate_models() {
+        // Meshes of these models are manifold and watertight, but dual
+        // contouring doesn't prevent self-intersections: vertices placed by
+        // the QEF near thin features and sharp corners can fold neighboring
+        // triangles through each other, and collapsed cells make large
+        // triangles which cut through their neighbors.  These are known
+        // exceptions; since meshing is deterministic, we pin the number of
+        // intersecting pairs (and of triangles involved) for each model and
+        // depth, so that regressions in either the mesher or the checker
+        // show up here.  The bear, with its thin ears and claws, is the worst
+        // offender.
+        for (text, expected) in [
+            (
+                include_str!("../../../models/bear.vm"),
+                [(4, 511, 414), (6, 4668, 2475)],
+            ),
+            (
+                include_str!("../../../models/colonnade.vm"),
+                [(4, 6, 8), (6, 730, 539)],
+            ),
+        ] {
+            let (ctx, root) =
+                crate::Context::from_text(text.as_bytes()).unwrap();
+            let shape = VmShape::new(&ctx, root).unwrap();
+            for (depth, pairs, tris) in expected {
+                let settings = Settings {
+                    depth,
+                    ..Default::default()
+                };
+                let (mesh, r) = check_mesh(&shape, settings);
+
+                let mut seen = std::collections::HashSet::new();
+                for &[a, b] in &r.self_intersections {
+                    assert!(a < b && b < mesh.triangles.len());
+                    assert!(seen.insert([a, b]), "duplicate pair {a}, {b}");
+                }
+                let involved: std::collections::HashSet<_> =
+                    r.self_intersections.iter().flatten().collect();
+                assert_eq!(
+                    (r.self_intersections.len(), involved.len()),
+                    (pairs, tris),
+                    "unexpected intersections at depth {depth}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn validate_sphere() {
+        let (x, y, z) = Tree::axes();
+        let sphere = (x.square() + y.square() + z.square()).sqrt() - 0.6;
+        let shape = VmShape::from(sphere);
+        let settings = Settings {
+            depth: 5,
+            ..Default::default()
+        };
+        let (_, r) = check_mesh(&shape, settings);
+        assert!(r.is_valid(), "{r:?}");
+
+        let settings = Settings {
+            view: View3::from_center_and_scale(
+                Vector3::new(0.1, 0.2, 0.3),
+                0.9,
+            ),
+            ..settings
+        };
+        check_mesh(&shape, settings);
+    }
+}
@@ -0,0 +1,815 @@
+//! 2D contouring
+//!
+//! This is a 2D counterpart to the [`Octree`](super::Octree): the region is
+//! subdivided into a quadtree, using interval arithmetic to skip empty and
+//! filled cells, then contoured with dual contouring (placing one vertex per
+//! cell, or two in ambiguous saddle cells) to preserve sharp corners.
+use crate::{
+    eval::Function,
+    render::{RenderHandle, View2},
+    shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
+    types::{Grad, Interval},
+};
+use nalgebra::{Matrix2, Point2, Vector2};
+use std::collections::{HashMap, HashSet};
+
+/// Settings when building 2D contours
+#[derive(Copy, Clone, Debug)]
+pub struct ContourSettings {
+    /// Depth to recurse in the quadtree
+    ///
+    /// The region is divided into `2^depth` cells along each axis.
+    pub depth: u8,
+
+    /// Viewport to provide a world-to-model transform
+    pub view: View2,
+}
+
+impl Default for ContourSettings {
+    fn default() -> Self {
+        Self {
+            depth: 8,
+            view: Default::default(),
+        }
+    }
+}
+
+/// A set of closed, oriented 2D polylines
+///
+/// Contours are built by [`Contours::build`], and can be written out as SVG or
+/// DXF files.
+#[derive(Default, Debug)]
+pub struct Contours {
+    /// Polylines, in model coordinates
+    ///
+    /// Each polyline is implicitly closed (i.e. its last point connects back to
+    /// its first point).  Polylines are oriented so that the shape is on their
+    /// left: outer boundaries wind counter-clockwise and holes wind clockwise.
+    pub polylines: Vec<Vec<Vector2<f32>>>,
+}
+
+impl Contours {
+    /// Builds contours of the given shape at `z = 0`
+    ///
+    /// If the shape uses variables other than `x` and `y`, then
+    /// [`build_with_vars`](Contours::build_with_vars) should be used instead.
+    ///
+    /// The shape is evaluated on the ±1 square in world coordinates (as
+    /// specified by `settings.view`), and is clipped to that square, so the
+    /// resulting polylines are always closed.
+    pub fn build<F: Function>(
+        shape: &Shape<F>,
+        settings: ContourSettings,
+    ) -> Self {
+        Self::build_with_vars(shape, &ShapeVars::new(), settings)
+    }
+
+    /// Builds contours of the given shape, with user-provided variables
+    pub fn build_with_vars<F: Function>(
+        shape: &Shape<F>,
+        vars: &ShapeVars<f32>,
+        settings: ContourSettings,
+    ) -> Self {
+        // Transform the shape given our world-to-model matrix
+        let t = settings.view.world_to_model();
+        let mat = t.insert_row(2, 0.0).insert_column(2, 0.0);
+        let shape = shape.clone().apply_transform(mat);
+
+        let mut b = ContourBuilder::<F>::new(vars, settings.depth);
+        let mut rh = RenderHandle::new(shape);
+        let tiles = 1 << (b.depth - b.tile_depth);
+        b.recurse(&mut rh, 0, 0, tiles);
+        let mut out = b.polylines();
+
+        // Apply the transform from [-1, +1] back to model space
+        for p in out.iter_mut().flat_map(|p| p.iter_mut()) {
+            *p = t.transform_point(&Point2::from(*p)).coords;
+        }
+        Self { polylines: out }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Edge between two grid corners
+///
+/// `X(i, j)` goes from corner `(i, j)` to `(i + 1, j)`; `Y(i, j)` goes from
+/// corner `(i, j)` to `(i, j + 1)`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Edge {
+    X(u32, u32),
+    Y(u32, u32),
+}
+
+impl Edge {
+    fn start(&self) -> (u32, u32) {
+        match *self {
+            Edge::X(i, j) | Edge::Y(i, j) => (i, j),
+        }
+    }
+    fn end(&self) -> (u32, u32) {
+        match *self {
+            Edge::X(i, j) => (i + 1, j),
+            Edge::Y(i, j) => (i, j + 1),
+        }
+    }
+}
+
+/// Hermite data for an edge with a sign change
+struct EdgeData {
+    /// Position of the surface crossing
+    pos: Vector2<f32>,
+    /// Normalized gradient at the surface crossing
+    normal: Vector2<f32>,
+    /// Whether the start of the edge is inside the shape
+    start_inside: bool,
+    /// Vertices in the two cells adjoining this edge
+    ///
+    /// For an `X` edge, these are the cells below and above; for a `Y` edge,
+    /// these are the cells to the left and right.
+    verts: [Option<usize>; 2],
+}
+
+/// Quadratic error function for positioning a vertex within a cell
+#[derive(Default)]
+struct Qef2 {
+    ata: Matrix2<f32>,
+    atb: Vector2<f32>,
+    mass_point: Vector2<f32>,
+    count: usize,
+}
+
+impl Qef2 {
+    fn add_intersection(&mut self, pos: Vector2<f32>, norm: Vector2<f32>) {
+        self.mass_point += pos;
+        self.count += 1;
+        self.ata += norm * norm.transpose();
+        self.atb += norm * norm.dot(&pos);
+    }
+
+    /// Solves the QEF, minimizing towards the mass point
+    fn solve(&self) -> Vector2<f32> {
+        // Singular values below this threshold are discarded, so that nearly
+        // parallel normals don't produce a far-flung vertex.
+        const EPSILON: f32 = 0.1;
+        let center = self.mass_point / self.count as f32;
+        let atb = self.atb - self.ata * center;
+        let svd = nalgebra::linalg::SVD::new(self.ata, true, true);
+        match svd.solve(&atb, EPSILON) {
+            Ok(sol) if sol.iter().all(|v| v.is_finite()) => sol + center,
+            _ => center,
+        }
+    }
+}
+
+/// Number of bisection steps when searching for an edge's sign change
+const SEARCH_STEPS: usize = 12;
+
+struct ContourBuilder<'a, F: Function> {
+    vars: &'a ShapeVars<f32>,
+
+    /// Total quadtree depth
+    depth: u8,
+    /// Depth of a tile, which is evaluated in a single batch
+    tile_depth: u8,
+
+    /// Values at grid corners, populated as tiles are evaluated
+    corners: HashMap<(u32, u32), f32>,
+    /// Hermite data for edges with sign changes
+    edges: HashMap<Edge, EdgeData>,
+    /// Vertex positions, in world coordinates
+    verts: Vec<Vector2<f32>>,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+}
+
+impl<'a, F: Function> ContourBuilder<'a, F> {
+    fn new(vars: &'a ShapeVars<f32>, depth: u8) -> Self {
+        Self {
+            vars,
+            depth,
+            tile_depth: depth.min(4),
+            corners: HashMap::new(),
+            edges: HashMap::new(),
+            verts: vec![],
+            eval_float_slice: Default::default(),
+            eval_grad_slice: Default::default(),
+            eval_interval: Default::default(),
+            tape_storage: vec![],
+            shape_storage: vec![],
+            workspace: Default::default(),
+        }
+    }
+
+    /// Number of cells along each axis
+    fn cells(&self) -> u32 {
+        1 << self.depth
+    }
+
+    /// Converts a grid coordinate into world coordinates
+    fn world(&self, i: u32) -> f32 {
+        -1.0 + 2.0 * i as f32 / self.cells() as f32
+    }
+
+    /// Returns the position of a grid corner in world coordinates
+    fn pos(&self, (i, j): (u32, u32)) -> Vector2<f32> {
+        Vector2::new(self.world(i), self.world(j))
+    }
+
+    /// Checks whether a grid corner is on the boundary of the region
+    fn on_boundary(&self, (i, j): (u32, u32)) -> bool {
+        let n = self.cells();
+        i == 0 || j == 0 || i == n || j == n
+    }
+
+    /// Checks whether a corner is inside the shape
+    ///
+    /// Corners on the boundary are always outside, which clips the shape.
+    fn inside(&self, c: (u32, u32)) -> bool {
+        !self.on_boundary(c) && self.corners[&c] < 0.0
+    }
+
+    /// Recurses into a square region of tiles
+    fn recurse(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        ti: u32,
+        tj: u32,
+        size: u32,
+    ) {
+        let s = 1 << self.tile_depth;
+        let x = Interval::new(self.world(ti * s), self.world((ti + size) * s));
+        let y = Interval::new(self.world(tj * s), self.world((tj + size) * s));
+        let z = Interval::new(0.0, 0.0);
+        let (i, trace) = self
+            .eval_interval
+            .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, self.vars)
+            .unwrap();
+
+        // Filled regions on the boundary still need contouring, because the
+        // shape is clipped there.
+        let tiles = 1 << (self.depth - self.tile_depth);
+        let touches_boundary =
+            ti == 0 || tj == 0 || ti + size == tiles || tj + size == tiles;
+        if i.lower() > 0.0 || (i.upper() < 0.0 && !touches_boundary) {
+            return;
+        }
+
+        let sub_tape = if let Some(trace) = trace.as_ref() {
+            shape.simplify(
+                trace,
+                &mut self.workspace,
+                &mut self.shape_storage,
+                &mut self.tape_storage,
+            )
+        } else {
+            shape
+        };
+
+        if size > 1 {
+            let half = size / 2;
+            for dj in 0..2 {
+                for di in 0..2 {
+                    self.recurse(
+                        sub_tape,
+                        ti + di * half,
+                        tj + dj * half,
+                        half,
+                    );
+                }
+            }
+        } else {
+            self.tile(sub_tape, ti, tj);
+        }
+    }
+
+    /// Evaluates and contours a single tile
+    fn tile(&mut self, shape: &mut RenderHandle<F>, ti: u32, tj: u32) {
+        let s = 1 << self.tile_depth;
+        let (i0, j0) = (ti * s, tj * s);
+
+        // Evaluate every corner in the tile.  Corners which were already
+        // evaluated by a neighboring tile keep their existing value, so that
+        // all cells agree on signs.
+        let mut xs = vec![];
+        let mut ys = vec![];
+        for j in 0..=s {
+            for i in 0..=s {
+                xs.push(self.world(i0 + i));
+                ys.push(self.world(j0 + j));
+            }
+        }
+        let zs = vec![0.0; xs.len()];
+        let out = self
+            .eval_float_slice
+            .eval_v(
+                shape.f_tape(&mut self.tape_storage),
+                &xs,
+                &ys,
+                &zs,
+                self.vars,
+            )
+            .unwrap();
+        let mut index = 0;
+        for j in 0..=s {
+            for i in 0..=s {
+                self.corners.entry((i0 + i, j0 + j)).or_insert(out[index]);
+                index += 1;
+            }
+        }
+
+        // Find every edge with a sign change that doesn't yet have Hermite
+        // data, along with cells that need vertices.  Edges are collected in
+        // a `Vec` (so that evaluation order is deterministic), using a set to
+        // skip edges that are shared between cells.
+        let mut todo = vec![];
+        let mut seen = HashSet::new();
+        let mut cells = vec![];
+        for j in j0..j0 + s {
+            for i in i0..i0 + s {
+                let mask = self.cell_mask(i, j);
+                if mask == 0 || mask == 0b1111 {
+                    continue;
+                }
+                for (e, _side) in cell_edges(i, j) {
+                    if !self.edges.contains_key(&e)
+                        && self.inside(e.start()) != self.inside(e.end())
+                        && seen.insert(e)
+                    {
+                        todo.push(e);
+                    }
+                }
+                cells.push((i, j, mask));
+            }
+        }
+        self.find_crossings(shape, &todo);
+
+        // Disambiguate saddle cells by evaluating their centers
+        let saddles: Vec<_> = cells
+            .iter()
+            .filter(|(_, _, mask)| *mask == 0b1001 || *mask == 0b0110)
+            .map(|(i, j, _)| (*i, *j))
+            .collect();
+        let h = 1.0 / self.cells() as f32;
+        let xs: Vec<f32> =
+            saddles.iter().map(|(i, _)| self.world(*i) + h).collect();
+        let ys: Vec<f32> =
+            saddles.iter().map(|(_, j)| self.world(*j) + h).collect();
+        let zs = vec![0.0; xs.len()];
+        let centers: HashMap<(u32, u32), bool> = if saddles.is_empty() {
+            HashMap::new()
+        } else {
+            let out = self
+                .eval_float_slice
+                .eval_v(
+                    shape.f_tape(&mut self.tape_storage),
+                    &xs,
+                    &ys,
+                    &zs,
+                    self.vars,
+                )
+                .unwrap();
+            saddles
+                .into_iter()
+                .zip(out.iter().map(|v| *v < 0.0))
+                .collect()
+        };
+
+        for (i, j, mask) in cells {
+            self.cell_vertices(i, j, mask, centers.get(&(i, j)).cloned());
+        }
+    }
+
+    /// Returns a bitmask of which corners of a cell are inside the shape
+    ///
+    /// Bits are (from LSB) the lower-left, lower-right, upper-left, and
+    /// upper-right corners.
+    fn cell_mask(&self, i: u32, j: u32) -> u8 {
+        [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)]
+            .iter()
+            .enumerate()
+            .map(|(b, c)| (self.inside(*c) as u8) << b)
+            .sum()
+    }
+
+    /// Finds surface crossings and normals for the given edges
+    fn find_crossings(&mut self, shape: &mut RenderHandle<F>, edges: &[Edge]) {
+        // Edges which touch the boundary cross it exactly at the boundary,
+        // with an outward-facing normal.
+        let mut search = vec![];
+        for &e in edges {
+            let (a, b) = (e.start(), e.end());
+            let start_inside = self.inside(a);
+            let c = if self.on_boundary(a) {
+                Some(a)
+            } else if self.on_boundary(b) {
+                Some(b)
+            } else {
+                None
+            };
+            if let Some(c) = c {
+                let n = self.cells();
+                let normal = match e {
+                    Edge::X(..) if c.0 == 0 => Vector2::new(-1.0, 0.0),
+                    Edge::X(..) => Vector2::new(1.0, 0.0),
+                    Edge::Y(..) if c.1 == 0 => Vector2::new(0.0, -1.0),
+                    Edge::Y(..) => Vector2::new(0.0, 1.0),
+                };
+                debug_assert!(c.0 == 0 || c.1 == 0 || c.0 == n || c.1 == n);
+                let pos = self.pos(c);
+                self.edges.insert(
+                    e,
+                    EdgeData {
+                        pos,
+                        normal,
+                        start_inside,
+                        verts: [None; 2],
+                    },
+                );
+            } else {
+                search.push((e, self.pos(a), self.pos(b), start_inside));
+            }
+        }
+        if search.is_empty() {
+            return;
+        }
+
+        // Bisection search, with the start of each range on the same side as
+        // the start of the edge
+        let mut lo = vec![0.0f32; search.len()];
+        let mut hi = vec![1.0f32; search.len()];
+        let zs = vec![0.0; search.len()];
+        for _ in 0..SEARCH_STEPS {
+            let (xs, ys): (Vec<f32>, Vec<f32>) = search
+                .iter()
+                .zip(lo.iter().zip(&hi))
+                .map(|((_, a, b, _), (lo, hi))| {
+                    let p = a + (b - a) * (lo + hi) / 2.0;
+                    (p.x, p.y)
+                })
+                .unzip();
+            let out = self
+                .eval_float_slice
+                .eval_v(
+                    shape.f_tape(&mut self.tape_storage),
+                    &xs,
+                    &ys,
+                    &zs,
+                    self.vars,
+                )
+                .unwrap();
+            for (k, v) in out.iter().enumerate() {
+                let mid = (lo[k] + hi[k]) / 2.0;
+                if (*v < 0.0) == search[k].3 {
+                    lo[k] = mid;
+                } else {
+                    hi[k] = mid;
+                }
+            }
+        }
+
+        // Evaluate gradients at the crossings
+        let points: Vec<Vector2<f32>> = search
+            .iter()
+            .zip(lo.iter().zip(&hi))
+            .map(|((_, a, b, _), (lo, hi))| a + (b - a) * (lo + hi) / 2.0)
+            .collect();
+        let xs: Vec<_> = points
+            .iter()
+            .map(|p| Grad::new(p.x, 1.0, 0.0, 0.0))
+            .collect();
+        let ys: Vec<_> = points
+            .iter()
+            .map(|p| Grad::new(p.y, 0.0, 1.0, 0.0))
+            .collect();
+        let zs = vec![Grad::from(0.0); points.len()];
+        let out = self
+            .eval_grad_slice
+            .eval_v(
+                shape.g_tape(&mut self.tape_storage),
+                &xs,
+                &ys,
+                &zs,
+                self.vars,
+            )
+            .unwrap();
+        for ((e, a, b, start_inside), (p, g)) in
+            search.into_iter().zip(points.into_iter().zip(out))
+        {
+            let mut normal = Vector2::new(g.dx, g.dy);
+            let norm = normal.norm();
+            if norm > 0.0 && norm.is_finite() {
+                normal /= norm;
+            } else {
+                // Fall back to the edge direction, pointing outwards
+                normal = (b - a).normalize();
+                if !start_inside {
+                    normal = -normal;
+                }
+            }
+            self.edges.insert(
+                e,
+                EdgeData {
+                    pos: p,
+                    normal,
+                    start_inside,
+                    verts: [None; 2],
+                },
+            );
+        }
+    }
+
+    /// Positions vertices within a cell and attaches them to its edges
+    ///
+    /// `center` is whether the cell's center is inside the shape, which is
+    /// required for saddle cells.
+    fn cell_vertices(
+        &mut self,
+        i: u32,
+        j: u32,
+        mask: u8,
+        center: Option<bool>,
+    ) {
+        let [bottom, top, left, right] = cell_edges(i, j);
+
+        // Group crossed edges into pairs, each of which gets one vertex
+        let pairs = match (mask, center) {
+            // The lower-left and upper-right corners are connected through the
+            // center, so we cut off the other corners (or vice versa)
+            (0b1001, Some(true)) | (0b0110, Some(false)) => {
+                vec![[bottom, right], [top, left]]
+            }
+            (0b1001, _) | (0b0110, _) => vec![[bottom, left], [right, top]],
+            _ => {
+                let crossed: Vec<_> = [bottom, top, left, right]
+                    .into_iter()
+                    .filter(|(e, _)| self.edges.contains_key(e))
+                    .collect();
+                assert_eq!(crossed.len(), 2);
+                vec![[crossed[0], crossed[1]]]
+            }
+        };
+
+        let lo = Vector2::new(self.world(i), self.world(j));
+        let hi = Vector2::new(self.world(i + 1), self.world(j + 1));
+        for pair in pairs {
+            let mut qef = Qef2::default();
+            for (e, _) in pair {
+                let d = &self.edges[&e];
+                qef.add_intersection(d.pos, d.normal);
+            }
+            // Keep the vertex within the cell, so that polylines don't cross
+            let p = qef.solve().sup(&lo).inf(&hi);
+            let v = self.verts.len();
+            self.verts.push(p);
+            for (e, side) in pair {
+                self.edges.get_mut(&e).unwrap().verts[side] = Some(v);
+            }
+        }
+    }
+
+    /// Links vertices into closed polylines
+    fn polylines(&self) -> Vec<Vec<Vector2<f32>>> {
+        // Orient each edge so that the inside of the shape is on its left
+        let mut next = vec![None; self.verts.len()];
+        for (e, d) in &self.edges {
+            if let [Some(v0), Some(v1)] = d.verts {
+                let forward = match e {
+                    Edge::X(..) => d.start_inside,
+                    Edge::Y(..) => !d.start_inside,
+                };
+                let (a, b) = if forward { (v0, v1) } else { (v1, v0) };
+                next[a] = Some(b);
+            }
+        }
+
+        // Walk loops, starting from the lowest-numbered vertex so that the
+        // output is deterministic.
+        let mut out = vec![];
+        let mut seen = vec![false; self.verts.len()];
+        for start in 0..self.verts.len() {
+            if seen[start] {
+                continue;
+            }
+            let mut line = vec![];
+            let mut v = start;
+            while !seen[v] {
+                seen[v] = true;
+                line.push(self.verts[v]);
+                match next[v] {
+                    Some(n) => v = n,
+                    None => break,
+                }
+            }
+            if line.len() > 2 {
+                out.push(line);
+            }
+        }
+        out
+    }
+}
+
+/// Returns the edges of a cell, and the cell's side of each edge
+///
+/// Edges are returned in the order bottom, top, left, right.
+fn cell_edges(i: u32, j: u32) -> [(Edge, usize); 4] {
+    [
+        (Edge::X(i, j), 1),
+        (Edge::X(i, j + 1), 0),
+        (Edge::Y(i, j), 1),
+        (Edge::Y(i + 1, j), 0),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::Tree, vm::VmShape};
+
+    /// Returns the signed area of a closed polyline
+    fn area(line: &[Vector2<f32>]) -> f32 {
+        let mut out = 0.0;
+        for (i, a) in line.iter().enumerate() {
+            let b = line[(i + 1) % line.len()];
+            out += a.x * b.y - b.x * a.y;
+        }
+        out / 2.0
+    }
+
+    fn circle(x: f32, y: f32, r: f32) -> Tree {
+        let (tx, ty, _) = Tree::axes();
+        ((tx - x).square() + (ty - y).square()).sqrt() - r
+    }
+
+    fn rect(x: [f32; 2], y: [f32; 2]) -> Tree {
+        let (tx, ty, _) = Tree::axes();
+        (x[0] - tx.clone())
+            .max(tx - x[1])
+            .max(y[0] - ty.clone())
+            .max(ty - y[1])
+    }
+
+    #[test]
+    fn test_circle() {
+        let shape = VmShape::from(circle(0.1, -0.2, 0.5));
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 1);
+        let line = &c.polylines[0];
+        for p in line {
+            let r = (p - Vector2::new(0.1, -0.2)).norm();
+            assert!((r - 0.5).abs() < 1e-3, "bad radius {r} at {p:?}");
+        }
+        let a = area(line);
+        let expected = std::f32::consts::PI * 0.25;
+        assert!(a > 0.0, "outer contour should be counter-clockwise");
+        assert!((a - expected).abs() / expected < 1e-3, "{a} != {expected}");
+    }
+
+    #[test]
+    fn test_sharp_corners() {
+        let shape = VmShape::from(rect([-0.51, 0.33], [-0.27, 0.61]));
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 1);
+        let line = &c.polylines[0];
+        for corner in
+            [[-0.51, -0.27], [0.33, -0.27], [0.33, 0.61], [-0.51, 0.61]]
+        {
+            let corner = Vector2::from(corner);
+            assert!(
+                line.iter().any(|p| (p - corner).norm() < 1e-4),
+                "missing corner {corner:?}"
+            );
+        }
+        let a = area(line);
+        let expected = 0.84 * 0.88;
+        assert!((a - expected).abs() < 1e-4, "{a} != {expected}");
+    }
+
+    #[test]
+    fn test_hole() {
+        let (x, y, _) = Tree::axes();
+        let r = (x.square() + y.square()).sqrt();
+        let ring = (r.clone() - 0.7).max(0.3 - r);
+        let shape = VmShape::from(ring);
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 2);
+        let mut areas: Vec<f32> = c.polylines.iter().map(|l| area(l)).collect();
+        areas.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let pi = std::f32::consts::PI;
+        assert!((areas[0] + pi * 0.09).abs() < 2e-3, "bad hole: {areas:?}");
+        assert!((areas[1] - pi * 0.49).abs() < 2e-3, "bad outer: {areas:?}");
+    }
+
+    #[test]
+    fn test_clipped() {
+        // The left half-plane is clipped to the bounds
+        let (x, _, _) = Tree::axes();
+        let shape = VmShape::from(x);
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 1);
+        let a = area(&c.polylines[0]);
+        assert!((a - 2.0).abs() < 1e-4, "bad area {a}");
+    }
+
+    #[test]
+    fn test_saddle() {
+        // Two squares touching at a corner should produce two loops
+        let a = rect([-0.5, 0.0], [-0.5, 0.0]);
+        let b = rect([0.0, 0.5], [0.0, 0.5]);
+        let shape = VmShape::from(a.min(b));
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 2);
+        for line in &c.polylines {
+            let a = area(line);
+            assert!((a - 0.25).abs() < 1e-3, "bad area {a}");
+        }
+    }
+
+    /// Parses the `d` attribute of an SVG path written by `write_svg`
+    fn parse_svg(svg: &str) -> Vec<Vec<Vector2<f32>>> {
+        let d = svg
+            .split(" d=\"")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        let mut out = vec![];
+        let mut words = d.split_whitespace();
+        while let Some(w) = words.next() {
+            if w == "Z" {
+                continue;
+            }
+            if w.starts_with('M') {
+                out.push(vec![]);
+            }
+            let x: f32 = w[1..].parse().unwrap();
+            let y: f32 = words.next().unwrap().parse().unwrap();
+            out.last_mut().unwrap().push(Vector2::new(x, -y));
+        }
+        out
+    }
+
+    /// Parses the `LWPOLYLINE` entities of a DXF written by `write_dxf`
+    fn parse_dxf(dxf: &str) -> Vec<Vec<Vector2<f32>>> {
+        let lines: Vec<&str> = dxf.lines().collect();
+        assert_eq!(lines.len() % 2, 0);
+        let mut out: Vec<Vec<Vector2<f32>>> = vec![];
+        let mut x = None;
+        for pair in lines.chunks(2) {
+            match (pair[0], pair[1]) {
+                ("0", "LWPOLYLINE") => out.push(vec![]),
+                ("70", flags) => assert_eq!(flags, "1", "must be closed"),
+                ("10", v) => x = Some(v.parse::<f32>().unwrap()),
+                ("20", v) => out
+                    .last_mut()
+                    .unwrap()
+                    .push(Vector2::new(x.take().unwrap(), v.parse().unwrap())),
+                _ => (),
+            }
+        }
+        assert_eq!(lines.last(), Some(&"EOF"));
+        out
+    }
+
+    #[test]
+    fn test_write() {
+        let a = rect([-0.5, 0.0], [-0.5, 0.0]);
+        let b = circle(0.4, 0.3, 0.2);
+        let shape = VmShape::from(a.min(b));
+        let c = Contours::build(&shape, ContourSettings::default());
+        assert_eq!(c.polylines.len(), 2);
+
+        let mut svg = vec![];
+        c.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(parse_svg(&svg), c.polylines);
+
+        let mut dxf = vec![];
+        c.write_dxf(&mut dxf).unwrap();
+        let dxf = String::from_utf8(dxf).unwrap();
+        assert_eq!(parse_dxf(&dxf), c.polylines);
+    }
+
+    #[test]
+    fn test_view() {
+        let shape = VmShape::from(circle(2.0, 3.0, 1.5));
+        let settings = ContourSettings {
+            view: View2::from_center_and_scale(Vector2::new(2.0, 3.0), 2.0),
+            ..Default::default()
+        };
+        let c = Contours::build(&shape, settings);
+        assert_eq!(c.polylines.len(), 1);
+        for p in &c.polylines[0] {
+            let r = (p - Vector2::new(2.0, 3.0)).norm();
+            assert!((r - 1.5).abs() < 1e-2, "bad radius {r} at {p:?}");
+        }
+    }
+}
@@ -0,0 +1,1208 @@
+//! Multi-material octree construction and meshing
+use super::{
+    cell::{Cell, CellData, CellIndex, CellVertex, Leaf},
+    dc::{self, DcBuilder},
+    frame::Frame,
+    gen::CELL_TO_VERT_TO_EDGES,
+    octree::BuildSettings,
+    qef::QuadraticErrorSolver,
+    types::{Corner, Edge, X, Y, Z},
+    Mesh, MeshMaterials, Octree, Settings,
+};
+use crate::{
+    eval::{BulkEvaluator, Function, TracingEvaluator},
+    render::{RenderHints, ThreadCount},
+    shape::{Shape, ShapeBulkEval, ShapeTape, ShapeTracingEval, ShapeVars},
+    types::Grad,
+    Error,
+};
+
+/// A shape with an associated display color, for multi-material meshing
+#[derive(Clone)]
+pub struct Material<F> {
+    /// Shape which is filled with this material
+    pub shape: Shape<F>,
+    /// Display color (as RGB), used when exporting
+    pub color: [u8; 3],
+}
+
+/// Octree for meshing several materials into a single mesh
+///
+/// Each point in space is assigned to the **last** material whose shape
+/// contains it (so later materials take precedence, like drawing order), or
+/// to no material if no shape contains it.  Cells are subdivided to the
+/// maximum depth wherever this label changes; unlike [`Octree`], leaf cells
+/// are never simplified.
+///
+/// Meshing produces one closed, consistently oriented shell per material, so
+/// that each material can be exported (or [split](Mesh::split_materials)) as
+/// a standalone solid.  Shells share vertices wherever materials touch, so
+/// interfaces are conforming.  As a consequence, **every interface between
+/// two materials is written twice**: once in each material's shell, as
+/// coincident triangles with opposite windings.  Consumers which expect a
+/// single non-manifold mesh (with each interface written once) must
+/// deduplicate these triangles themselves.
+pub struct MaterialOctree {
+    /// Binary octree for each material, all sharing the same cell layout
+    octrees: Vec<Octree>,
+
+    /// Global vertex IDs for each material's octree vertices
+    ///
+    /// Vertices which are shared between materials (e.g. on an interface)
+    /// have the same global ID in each material's octree.
+    ids: Vec<Vec<usize>>,
+
+    /// Number of global vertex IDs
+    id_count: usize,
+
+    /// Color for each material
+    colors: Vec<[u8; 3]>,
+}
+
+/// Number of global vertex IDs reserved for each leaf
+///
+/// 8 vertex slots (see [`LabelLeaf::vertex_slots`]) are followed by 12 edge
+/// intersections.
+const LEAF_IDS: usize = 20;
+
+impl MaterialOctree {
+    /// Builds a multi-material octree, with user-provided variables
+    ///
+    /// The shapes are evaluated on the region specified by `settings.view` or
+    /// `settings.region`, subdividing to `settings.depth` (or the region's
+    /// depth) and using `settings.isovalue`.  If `settings.cap` is set, every
+    /// material is clipped by the meshing bounds, so each shell is closed.
+    /// Subtrees are built in parallel if `settings.threads` requests it; the
+    /// output is deterministic regardless of the thread count.
+    ///
+    /// Leaf cells are never simplified, so adaptive subdivision isn't
+    /// supported; this returns [`Error::UnsupportedMeshSetting`] if
+    /// `settings.min_depth` or `settings.max_error` is set.
+    ///
+    /// # Panics
+    /// If there are more than 255 materials
+    pub fn build_with_vars<F: Function + RenderHints + Clone>(
+        materials: &[Material<F>],
+        vars: &ShapeVars<f32>,
+        settings: Settings,
+    ) -> Result<Self, Error> {
+        assert!(materials.len() < 256, "too many materials");
+        if settings.min_depth.is_some() {
+            return Err(Error::UnsupportedMeshSetting("min_depth"));
+        } else if settings.max_error.is_some() {
+            return Err(Error::UnsupportedMeshSetting("max_error"));
+        }
+
+        // Transform the shapes given our world-to-model matrix
+        let t = settings.world_to_model();
+        let shapes: Vec<_> = materials
+            .iter()
+            .map(|m| {
+                if t == nalgebra::Matrix4::identity() {
+                    m.shape.clone()
+                } else {
+                    m.shape.clone().apply_transform(t)
+                }
+            })
+            .collect();
+
+        let max_depth = settings.max_depth();
+        let mut b = LabelBuilder::new(&shapes, vars, (&settings).into());
+        let mut subtrees = match settings.threads {
+            ThreadCount::One => Subtrees::none(),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            ThreadCount::Many(threads) => {
+                b.build_subtrees(&shapes, max_depth, threads)
+            }
+        };
+        b.recurse(CellIndex::default(), max_depth, &mut subtrees);
+
+        // Apply the transform from [-1, +1] back to model space
+        for leaf in &mut b.leafs {
+            for v in leaf.verts.iter_mut().chain(&mut leaf.intersections) {
+                let p: nalgebra::Point3<f32> = (*v).into();
+                *v = t.transform_point(&p).coords;
+            }
+        }
+
+        let (octrees, ids) = (0..materials.len())
+            .map(|m| b.material_octree(m as u8 + 1))
+            .unzip();
+        Ok(Self {
+            octrees,
+            ids,
+            id_count: b.leafs.len() * LEAF_IDS,
+            colors: materials.iter().map(|m| m.color).collect(),
+        })
+    }
+
+    /// Builds a multi-material octree
+    ///
+    /// If the shapes use variables other than `x`, `y`, `z`, then
+    /// [`build_with_vars`](Self::build_with_vars) should be used instead.
+    ///
+    /// # Panics
+    /// If there are more than 255 materials
+    pub fn build<F: Function + RenderHints + Clone>(
+        materials: &[Material<F>],
+        settings: Settings,
+    ) -> Result<Self, Error> {
+        Self::build_with_vars(materials, &ShapeVars::new(), settings)
+    }
+
+    /// Walks the dual of the octree, building a multi-material mesh
+    ///
+    /// Triangles are grouped by material, and the mesh's
+    /// [`materials`](Mesh::materials) are populated.  Each material's
+    /// triangles form a closed shell, so interfaces between materials appear
+    /// twice (see [`MaterialOctree`] for details).
+    pub fn walk_dual(&self) -> Mesh {
+        let mut b = MaterialBuilder {
+            ids: &[],
+            map: vec![usize::MAX; self.id_count],
+            material: 0,
+            out: Mesh {
+                materials: Some(MeshMaterials {
+                    triangles: vec![],
+                    colors: self.colors.clone(),
+                }),
+                ..Default::default()
+            },
+        };
+        for (m, (octree, ids)) in self.octrees.iter().zip(&self.ids).enumerate()
+        {
+            b.ids = ids;
+            b.material = m as u32;
+            b.cell(octree, CellIndex::default());
+        }
+        b.out
+    }
+}
+
+/// Cell in the label octree
+#[derive(Copy, Clone, Debug)]
+enum LabelCell {
+    Invalid,
+    /// Cell with a single label (0 for empty space)
+    Uniform(u8),
+    /// Branch, with children at the given index in `cells`
+    Branch(usize),
+    /// Leaf, stored at the given index in `leafs`
+    Leaf(usize),
+}
+
+/// Leaf cell in the label octree
+#[derive(Clone, Debug)]
+struct LabelLeaf {
+    /// Label at each corner (0 for empty space)
+    labels: [u8; 8],
+
+    /// Leaf vertices
+    ///
+    /// If the leaf only contains one material and empty space, these are the
+    /// vertices for each group in [`CELL_TO_VERT_TO_EDGES`]; otherwise, the
+    /// first vertex is shared by every material.
+    verts: arrayvec::ArrayVec<nalgebra::Vector3<f32>, 4>,
+
+    /// Edge intersections, indexed by undirected edge
+    ///
+    /// Edges without a label change are filled with NaN.
+    intersections: [nalgebra::Vector3<f32>; 12],
+}
+
+impl LabelLeaf {
+    /// Returns a binary mask of corners that are filled with the given label
+    fn mask(&self, label: u8) -> u8 {
+        (0..8).fold(0, |acc, i| acc | (((self.labels[i] == label) as u8) << i))
+    }
+
+    /// Returns a vertex slot (in `0..8`) for each of the label's groups
+    ///
+    /// Groups from different labels which share an edge (i.e. which meet at
+    /// an interface) are assigned the same slot where possible, so that
+    /// interfaces are conforming.  Groups of the same label never share a
+    /// slot, which would pinch that label's shell; if a group touches two
+    /// groups of another label, it only shares a slot with the first.
+    fn vertex_slots(&self, label: u8) -> arrayvec::ArrayVec<usize, 4> {
+        // Every group contains at least one corner, so there are at most 8,
+        // stored as (label bit, edge bitmask).  Each label is represented by
+        // the bit of its first corner.
+        let mut groups: arrayvec::ArrayVec<(u8, u16), 8> =
+            arrayvec::ArrayVec::new();
+        let mut start = 0;
+        for (i, &l) in self.labels.iter().enumerate() {
+            if l == 0 || self.labels[..i].contains(&l) {
+                continue;
+            }
+            if l == label {
+                start = groups.len();
+            }
+            for vs in CELL_TO_VERT_TO_EDGES[self.mask(l) as usize].iter() {
+                let edges = vs
+                    .iter()
+                    .fold(0, |acc, e| acc | (1 << e.to_undirected().index()));
+                groups.push((1 << i, edges));
+            }
+        }
+
+        // Union-find over groups, where each root is the lowest group index
+        // in its set and records every label in the set.
+        let mut parent: [usize; 8] = std::array::from_fn(|i| i);
+        let mut labels: [u8; 8] =
+            std::array::from_fn(|i| groups.get(i).map(|g| g.0).unwrap_or(0));
+        fn find(parent: &[usize; 8], mut i: usize) -> usize {
+            while parent[i] != i {
+                i = parent[i];
+            }
+            i
+        }
+        for i in 0..groups.len() {
+            for j in i + 1..groups.len() {
+                if groups[i].1 & groups[j].1 == 0 {
+                    continue;
+                }
+                let (a, b) = (find(&parent, i), find(&parent, j));
+                if a != b && labels[a] & labels[b] == 0 {
+                    parent[a.max(b)] = a.min(b);
+                    labels[a.min(b)] |= labels[a.max(b)];
+                }
+            }
+        }
+        let n = CELL_TO_VERT_TO_EDGES[self.mask(label) as usize].len();
+        (start..start + n).map(|i| find(&parent, i)).collect()
+    }
+
+    /// Checks whether this leaf only contains a single material
+    fn is_binary(&self) -> bool {
+        let mut labels = self.labels.iter().filter(|&&l| l != 0);
+        let first = labels.next();
+        labels.all(|l| Some(l) == first) && self.labels.contains(&0)
+    }
+}
+
+/// Builder for the label octree
+struct LabelBuilder<'a, F: Function> {
+    tapes: Vec<ShapeTapes<F>>,
+    vars: &'a ShapeVars<f32>,
+    settings: BuildSettings,
+
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+
+    /// Cells, in the same layout as [`Octree::cells`]
+    cells: Vec<LabelCell>,
+    leafs: Vec<LabelLeaf>,
+}
+
+/// Depth at which label octrees are split for multithreaded construction
+const SUBTREE_DEPTH: usize = 2;
+
+/// Part of a label octree, built by a separate [`LabelBuilder`]
+///
+/// The subtree's root is at index 0, and its children begin at index 8.
+struct Subtree {
+    cells: Vec<LabelCell>,
+    leafs: Vec<LabelLeaf>,
+}
+
+/// Subtrees at a particular depth, in the order that they're visited
+struct Subtrees {
+    depth: usize,
+    trees: std::vec::IntoIter<Subtree>,
+}
+
+impl Subtrees {
+    /// Returns an empty set, so that every cell is built in place
+    fn none() -> Self {
+        Self {
+            depth: usize::MAX,
+            trees: vec![].into_iter(),
+        }
+    }
+}
+
+/// Evaluation tapes for a single shape
+struct ShapeTapes<F: Function> {
+    interval: ShapeTape<<F::IntervalEval as TracingEvaluator>::Tape>,
+    float_slice: ShapeTape<<F::FloatSliceEval as BulkEvaluator>::Tape>,
+    grad_slice: ShapeTape<<F::GradSliceEval as BulkEvaluator>::Tape>,
+}
+
+impl<'a, F: Function + RenderHints + Clone> LabelBuilder<'a, F> {
+    fn new(
+        shapes: &[Shape<F>],
+        vars: &'a ShapeVars<f32>,
+        settings: BuildSettings,
+    ) -> Self {
+        let tapes = shapes
+            .iter()
+            .map(|s| ShapeTapes {
+                interval: s.interval_tape(Default::default()),
+                float_slice: s.float_slice_tape(Default::default()),
+                grad_slice: s.grad_slice_tape(Default::default()),
+            })
+            .collect();
+        Self {
+            tapes,
+            vars,
+            settings,
+            eval_interval: Shape::<F>::new_interval_eval(),
+            eval_float_slice: Shape::<F>::new_float_slice_eval(),
+            eval_grad_slice: Shape::<F>::new_grad_slice_eval(),
+            cells: vec![LabelCell::Invalid; 8],
+            leafs: vec![],
+        }
+    }
+
+    /// Recurse down the octree, building the given cell
+    ///
+    /// Cells at `subtrees.depth` are taken from `subtrees` (which must be in
+    /// the same order as they're visited here) instead of being built.
+    fn recurse(
+        &mut self,
+        cell: CellIndex,
+        max_depth: u8,
+        subtrees: &mut Subtrees,
+    ) {
+        self.cells[cell.index] = match self.eval_cell(cell) {
+            Some(label) => LabelCell::Uniform(label),
+            None if cell.depth == max_depth as usize => self.leaf(cell),
+            None if cell.depth == subtrees.depth => {
+                let t = subtrees.trees.next().unwrap();
+                self.splice(t)
+            }
+            None => {
+                let index = self.cells.len();
+                self.cells.resize(index + 8, LabelCell::Invalid);
+                for i in Corner::iter() {
+                    self.recurse(cell.child(index, i), max_depth, subtrees);
+                }
+
+                // Collapse children if they all have the same label, freeing
+                // them since they're at the end of the array.
+                let first = self.cells[index];
+                match first {
+                    LabelCell::Uniform(a)
+                        if self.cells[index..].iter().all(
+                            |c| matches!(c, LabelCell::Uniform(b) if *b == a),
+                        ) =>
+                    {
+                        self.cells.truncate(index);
+                        first
+                    }
+                    _ => LabelCell::Branch(index),
+                }
+            }
+        };
+    }
+
+    /// Builds subtrees below [`SUBTREE_DEPTH`] in parallel
+    ///
+    /// Returns an empty set if the octree is too shallow to be split.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn build_subtrees(
+        &mut self,
+        shapes: &[Shape<F>],
+        max_depth: u8,
+        threads: std::num::NonZeroUsize,
+    ) -> Subtrees {
+        if max_depth as usize <= SUBTREE_DEPTH {
+            return Subtrees::none();
+        }
+        let mut cells = vec![];
+        self.find_subtrees(CellIndex::default(), &mut cells);
+
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        let (vars, settings) = (self.vars, self.settings);
+        let mut trees: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..threads.get())
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut b = LabelBuilder::new(shapes, vars, settings);
+                        let mut out = vec![];
+                        loop {
+                            let i = next.fetch_add(
+                                1,
+                                std::sync::atomic::Ordering::Relaxed,
+                            );
+                            if i >= cells.len() {
+                                break out;
+                            }
+                            b.cells = vec![LabelCell::Invalid; 8];
+                            let mut cell = cells[i];
+                            cell.index = 0;
+                            b.recurse(cell, max_depth, &mut Subtrees::none());
+                            let t = Subtree {
+                                cells: std::mem::take(&mut b.cells),
+                                leafs: std::mem::take(&mut b.leafs),
+                            };
+                            out.push((i, t));
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        trees.sort_unstable_by_key(|(i, _)| *i);
+        Subtrees {
+            depth: SUBTREE_DEPTH,
+            trees: trees
+                .into_iter()
+                .map(|(_, t)| t)
+                .collect::<Vec<_>>()
+                .into_iter(),
+        }
+    }
+
+    /// Finds cells at [`SUBTREE_DEPTH`] which [`recurse`](Self::recurse)
+    /// would subdivide, in the order that it visits them
+    #[cfg(not(target_arch = "wasm32"))]
+    fn find_subtrees(&mut self, cell: CellIndex, out: &mut Vec<CellIndex>) {
+        if self.eval_cell(cell).is_some() {
+            // uniform cell, nothing to do here
+        } else if cell.depth == SUBTREE_DEPTH {
+            out.push(cell);
+        } else {
+            for i in Corner::iter() {
+                self.find_subtrees(cell.child(0, i), out);
+            }
+        }
+    }
+
+    /// Adds a subtree's cells and leafs, returning its (offset) root cell
+    ///
+    /// The subtree was built with its root at index 0, so that its children
+    /// begin at index 8; this is equivalent to building it in place.
+    fn splice(&mut self, t: Subtree) -> LabelCell {
+        let cells = self.cells.len() - 8;
+        let leafs = self.leafs.len();
+        let offset = |c: LabelCell| match c {
+            LabelCell::Branch(i) => LabelCell::Branch(i + cells),
+            LabelCell::Leaf(i) => LabelCell::Leaf(i + leafs),
+            c => c,
+        };
+        self.cells.extend(t.cells[8..].iter().map(|c| offset(*c)));
+        self.leafs.extend(t.leafs);
+        offset(t.cells[0])
+    }
+
+    /// Checks whether a cell has a single label, using interval arithmetic
+    fn eval_cell(&mut self, cell: CellIndex) -> Option<u8> {
+        if self.settings.limit.is_some_and(|limit| {
+            [X, Y, Z]
+                .iter()
+                .zip(&limit)
+                .any(|(&a, &v)| cell.bounds[a].lower() >= v)
+        }) {
+            return Some(0);
+        }
+        let iso = self.settings.isovalue;
+        for (i, t) in self.tapes.iter().enumerate().rev() {
+            let (r, _) = self
+                .eval_interval
+                .eval_v(
+                    &t.interval,
+                    cell.bounds.x,
+                    cell.bounds.y,
+                    cell.bounds.z,
+                    self.vars,
+                )
+                .unwrap();
+            let r = self.settings.cap_interval(r, cell);
+            // An interval that's entirely outside the shape means that we
+            // should check the next shape; otherwise, we're done.
+            if r.upper() < iso {
+                return Some(i as u8 + 1);
+            } else if r.lower() <= iso || r.lower().is_nan() {
+                return None;
+            }
+        }
+        Some(0)
+    }
+
+    /// Evaluates labels at the given positions
+    fn labels(&mut self, xs: &[f32], ys: &[f32], zs: &[f32]) -> Vec<u8> {
+        let iso = self.settings.isovalue;
+        let mut out = vec![0; xs.len()];
+        for (i, t) in self.tapes.iter().enumerate() {
+            let vs = self
+                .eval_float_slice
+                .eval_v(&t.float_slice, xs, ys, zs, self.vars)
+                .unwrap();
+            for (j, (o, v)) in out.iter_mut().zip(vs).enumerate() {
+                let pos = nalgebra::Vector3::new(xs[j], ys[j], zs[j]);
+                if self.settings.cap_float(*v, pos) < iso {
+                    *o = i as u8 + 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// Evaluates a leaf cell at the maximum depth
+    fn leaf(&mut self, cell: CellIndex) -> LabelCell {
+        let corners: [_; 8] =
+            std::array::from_fn(|i| cell.corner(Corner::new(i as u8)));
+        let xs = corners.map(|p| p.0);
+        let ys = corners.map(|p| p.1);
+        let zs = corners.map(|p| p.2);
+        let labels: [u8; 8] = self.labels(&xs, &ys, &zs).try_into().unwrap();
+        if labels.iter().all(|&l| l == labels[0]) {
+            return LabelCell::Uniform(labels[0]);
+        }
+
+        // Find active edges, as (start position, end position, start label)
+        let pos = |c: Corner| {
+            let (x, y, z) = cell.corner(c);
+            nalgebra::Vector3::new(x, y, z)
+        };
+        let edges: arrayvec::ArrayVec<_, 12> = (0..12)
+            .map(Edge::new)
+            .filter(|e| {
+                let (a, b) = e.corners();
+                labels[a.index()] != labels[b.index()]
+            })
+            .collect();
+        let mut start: arrayvec::ArrayVec<_, 12> =
+            edges.iter().map(|e| pos(e.corners().0)).collect();
+        let mut end: arrayvec::ArrayVec<_, 12> =
+            edges.iter().map(|e| pos(e.corners().1)).collect();
+        let mut end_labels: arrayvec::ArrayVec<_, 12> = edges
+            .iter()
+            .map(|e| labels[e.corners().1.index()])
+            .collect();
+
+        // Do an N-ary search along each edge to find the first label change
+        const EDGE_SEARCH_SIZE: usize = 16;
+        const EDGE_SEARCH_DEPTH: usize = 4;
+        for _ in 0..EDGE_SEARCH_DEPTH {
+            let mut xs = vec![];
+            let mut ys = vec![];
+            let mut zs = vec![];
+            for (a, b) in start.iter().zip(&end) {
+                for j in 0..EDGE_SEARCH_SIZE {
+                    let p = a.lerp(b, j as f32 / (EDGE_SEARCH_SIZE - 1) as f32);
+                    xs.push(p.x);
+                    ys.push(p.y);
+                    zs.push(p.z);
+                }
+            }
+            let out = self.labels(&xs, &ys, &zs);
+            for (i, (e, search)) in
+                edges.iter().zip(out.chunks(EDGE_SEARCH_SIZE)).enumerate()
+            {
+                let s = labels[e.corners().0.index()];
+                let frac = search
+                    .iter()
+                    .position(|&l| l != s)
+                    .unwrap_or(EDGE_SEARCH_SIZE - 1)
+                    .max(1);
+                let (a, b) = (start[i], end[i]);
+                let t = |j: usize| j as f32 / (EDGE_SEARCH_SIZE - 1) as f32;
+                start[i] = a.lerp(&b, t(frac - 1));
+                end[i] = a.lerp(&b, t(frac));
+                end_labels[i] = search[frac];
+            }
+        }
+
+        // Each intersection is defined by whichever shape has the higher
+        // label, so we evaluate its gradient there.
+        let mut intersections = [nalgebra::Vector3::repeat(f32::NAN); 12];
+        let mut grads = [nalgebra::Vector4::zeros(); 12];
+        for (i, e) in edges.iter().enumerate() {
+            intersections[e.index()] = (start[i] + end[i]) / 2.0;
+        }
+        for (k, t) in self.tapes.iter().enumerate() {
+            let active: arrayvec::ArrayVec<_, 12> = edges
+                .iter()
+                .zip(&end_labels)
+                .filter(|(e, &l)| {
+                    l.max(labels[e.corners().0.index()]) == k as u8 + 1
+                })
+                .map(|(e, _)| e.index())
+                .collect();
+            if active.is_empty() {
+                continue;
+            }
+            let p = active.iter().map(|&e| intersections[e]);
+            let xs: Vec<_> =
+                p.clone().map(|p| Grad::new(p.x, 1.0, 0.0, 0.0)).collect();
+            let ys: Vec<_> =
+                p.clone().map(|p| Grad::new(p.y, 0.0, 1.0, 0.0)).collect();
+            let zs: Vec<_> = p.map(|p| Grad::new(p.z, 0.0, 0.0, 1.0)).collect();
+            let out = self
+                .eval_grad_slice
+                .eval_v(&t.grad_slice, &xs, &ys, &zs, self.vars)
+                .unwrap();
+            for (&e, g) in active.iter().zip(out) {
+                let g = self.settings.cap_grad(*g, intersections[e]);
+                grads[e] = g.into();
+            }
+        }
+
+        let mut leaf = LabelLeaf {
+            labels,
+            verts: arrayvec::ArrayVec::new(),
+            intersections,
+        };
+        if leaf.is_binary() {
+            let mask = !leaf.mask(0);
+            for vs in CELL_TO_VERT_TO_EDGES[mask as usize].iter() {
+                let mut qef = QuadraticErrorSolver::new();
+                for e in vs.iter() {
+                    let i = e.to_undirected().index();
+                    qef.add_intersection(intersections[i], grads[i]);
+                }
+                leaf.verts.push(qef.solve().0.pos);
+            }
+        } else {
+            let mut qef = QuadraticErrorSolver::new();
+            for e in &edges {
+                qef.add_intersection(
+                    intersections[e.index()],
+                    grads[e.index()],
+                );
+            }
+            leaf.verts.push(qef.solve().0.pos);
+        }
+
+        self.leafs.push(leaf);
+        LabelCell::Leaf(self.leafs.len() - 1)
+    }
+
+    /// Builds a binary octree for the given label
+    ///
+    /// Returns the octree and the global ID of each of its vertices
+    fn material_octree(&self, label: u8) -> (Octree, Vec<usize>) {
+        let mut verts = vec![];
+        let mut ids = vec![];
+        let cells = self
+            .cells
+            .iter()
+            .map(|c| -> CellData {
+                match *c {
+                    LabelCell::Invalid => Cell::Invalid,
+                    LabelCell::Uniform(l) if l == label => Cell::Full,
+                    LabelCell::Uniform(..) => Cell::Empty,
+                    LabelCell::Branch(index) => {
+                        Cell::Branch { index, thread: 0 }
+                    }
+                    LabelCell::Leaf(i) => {
+                        let leaf = &self.leafs[i];
+                        let mask = leaf.mask(label);
+                        if mask == 0 {
+                            return Cell::Empty.into();
+                        } else if mask == 255 {
+                            return Cell::Full.into();
+                        }
+
+                        // Lay out vertices and intersections in the same
+                        // order as `OctreeBuilder::leaf`
+                        let index = verts.len();
+                        let groups = &CELL_TO_VERT_TO_EDGES[mask as usize];
+                        let slots = leaf.vertex_slots(label);
+                        for (j, slot) in slots.into_iter().enumerate() {
+                            let j = if leaf.is_binary() { j } else { 0 };
+                            verts.push(CellVertex { pos: leaf.verts[j] });
+                            ids.push(i * LEAF_IDS + slot);
+                        }
+                        for e in groups.iter().flat_map(|vs| vs.iter()) {
+                            let e = e.to_undirected().index();
+                            verts.push(CellVertex {
+                                pos: leaf.intersections[e],
+                            });
+                            ids.push(i * LEAF_IDS + 8 + e);
+                        }
+                        Cell::Leaf(Leaf { mask, index })
+                    }
+                }
+                .into()
+            })
+            .collect();
+        (Octree { cells, verts }, ids)
+    }
+}
+
+/// Dual contouring builder which merges per-material meshes
+struct MaterialBuilder<'a> {
+    /// Global vertex IDs for the current material's octree
+    ids: &'a [usize],
+
+    /// Map from global vertex ID to `out.vertices`
+    ///
+    /// `usize::MAX` is used as a marker for an unmapped vertex
+    map: Vec<usize>,
+
+    /// Index of the current material
+    material: u32,
+    out: Mesh,
+}
+
+impl DcBuilder for MaterialBuilder<'_> {
+    type VertexIndex = usize;
+
+    fn cell(&mut self, octree: &Octree, cell: CellIndex) {
+        dc::dc_cell(octree, cell, self);
+    }
+    fn face<F: Frame>(&mut self, octree: &Octree, a: CellIndex, b: CellIndex) {
+        dc::dc_face::<F, _>(octree, a, b, self)
+    }
+    fn edge<F: Frame>(
+        &mut self,
+        octree: &Octree,
+        a: CellIndex,
+        b: CellIndex,
+        c: CellIndex,
+        d: CellIndex,
+    ) {
+        dc::dc_edge::<F, _>(octree, a, b, c, d, self)
+    }
+    fn triangle(&mut self, a: usize, b: usize, c: usize) {
+        self.out.triangles.push(nalgebra::Vector3::new(a, b, c));
+        if let Some(m) = &mut self.out.materials {
+            m.triangles.push(self.material);
+        }
+    }
+    fn vertex(
+        &mut self,
+        v: usize,
+        _cell: CellIndex,
+        verts: &[CellVertex],
+    ) -> usize {
+        let id = self.ids[v];
+        match self.map[id] {
+            usize::MAX => {
+                let next_vert = self.out.vertices.len();
+                self.out.vertices.push(verts[v].pos);
+                self.map[id] = next_vert;
+                next_vert
+            }
+            u => u,
+        }
+    }
+}
+
+impl Mesh {
+    /// Splits a multi-material mesh into one mesh per material
+    ///
+    /// Each returned mesh contains only the triangles (and vertices, and
+    /// normals if present) of a single material, in the order of
+    /// [`MeshMaterials::colors`].  Returns an empty list if the mesh doesn't
+    /// have [materials](Self::materials).
+    pub fn split_materials(&self) -> Result<Vec<Mesh>, Error> {
+        let Some(m) = self.checked_materials()? else {
+            return Ok(vec![]);
+        };
+        let mut map = vec![usize::MAX; self.vertices.len()];
+        let mut out = vec![];
+        for k in 0..m.colors.len() {
+            map.fill(usize::MAX);
+            let mut mesh = Mesh {
+                normals: self.normals.as_ref().map(|_| vec![]),
+                ..Default::default()
+            };
+            for (t, _) in self
+                .triangles
+                .iter()
+                .zip(&m.triangles)
+                .filter(|(_, i)| **i as usize == k)
+            {
+                let t = t.map(|v| {
+                    if map[v] == usize::MAX {
+                        map[v] = mesh.vertices.len();
+                        mesh.vertices.push(self.vertices[v]);
+                        if let Some(n) = &mut mesh.normals {
+                            n.push(self.normals.as_ref().unwrap()[v]);
+                        }
+                    }
+                    map[v]
+                });
+                mesh.triangles.push(t);
+            }
+            out.push(mesh);
+        }
+        Ok(out)
+    }
+
+    /// Returns the mesh's materials, checking their counts and indices
+    pub(crate) fn checked_materials(
+        &self,
+    ) -> Result<Option<&MeshMaterials>, Error> {
+        let Some(m) = &self.materials else {
+            return Ok(None);
+        };
+        if m.triangles.len() != self.triangles.len() {
+            return Err(Error::BadMaterialCount(
+                m.triangles.len(),
+                self.triangles.len(),
+            ));
+        }
+        if let Some(i) =
+            m.triangles.iter().find(|i| **i as usize >= m.colors.len())
+        {
+            return Err(Error::BadMaterialIndex(*i, m.colors.len()));
+        }
+        Ok(Some(m))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        mesh::octree::test::sphere,
+        vm::{VmFunction, VmShape},
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn material(
+        center: [f32; 3],
+        radius: f32,
+        color: [u8; 3],
+    ) -> Material<VmFunction> {
+        Material {
+            shape: VmShape::from(sphere(center, radius)),
+            color,
+        }
+    }
+
+    fn vertex_set(mesh: &Mesh, k: u32) -> BTreeSet<usize> {
+        let m = mesh.materials.as_ref().unwrap();
+        mesh.triangles
+            .iter()
+            .zip(&m.triangles)
+            .filter(|(_, i)| **i == k)
+            .flat_map(|(t, _)| t.iter().cloned())
+            .collect()
+    }
+
+    fn check_shells(mesh: &Mesh) {
+        for m in mesh.split_materials().unwrap() {
+            assert!(!m.triangles.is_empty());
+            let r = m.validate();
+            assert!(r.is_watertight(), "{r:?}");
+            assert!(r.is_oriented(), "{r:?}");
+        }
+    }
+
+    #[test]
+    fn test_single_material() {
+        let settings = Settings {
+            depth: 5,
+            ..Default::default()
+        };
+        let mats = [material([0.0; 3], 0.6, [255, 0, 0])];
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+        let m = mesh.materials.as_ref().unwrap();
+        assert_eq!(m.colors, [[255, 0, 0]]);
+        assert_eq!(m.triangles.len(), mesh.triangles.len());
+        assert!(m.triangles.iter().all(|i| *i == 0));
+        let r = mesh.validate();
+        assert!(r.is_watertight(), "{r:?}");
+        assert!(r.is_oriented(), "{r:?}");
+        for v in &mesh.vertices {
+            assert!((v.norm() - 0.6).abs() < 0.05, "bad vertex {v:?}");
+        }
+    }
+
+    #[test]
+    fn test_two_materials() {
+        let settings = Settings {
+            depth: 5,
+            ..Default::default()
+        };
+        let mats = [
+            material([-0.25, 0.0, 0.0], 0.5, [255, 0, 0]),
+            material([0.25, 0.0, 0.0], 0.5, [0, 0, 255]),
+        ];
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+        check_shells(&mesh);
+
+        // The materials share vertices along their interface
+        let a = vertex_set(&mesh, 0);
+        let b = vertex_set(&mesh, 1);
+        let shared: Vec<_> = a.intersection(&b).collect();
+        assert!(!shared.is_empty());
+        for &&v in &shared {
+            // The later material takes precedence, so the interface is the
+            // boundary of the second sphere.
+            let p = mesh.vertices[v];
+            let d = (p - nalgebra::Vector3::new(0.25, 0.0, 0.0)).norm();
+            assert!((d - 0.5).abs() < 0.05, "bad interface vertex {p:?}");
+            assert!(p.x < 0.05, "bad interface vertex {p:?}");
+        }
+
+        // Interface triangles appear once in each material, with opposite
+        // windings.
+        let m = mesh.materials.as_ref().unwrap();
+        let canonical = |t: &nalgebra::Vector3<usize>| {
+            let i = (0..3).min_by_key(|&i| t[i]).unwrap();
+            [t[i], t[(i + 1) % 3], t[(i + 2) % 3]]
+        };
+        let tris = |k: u32| -> BTreeSet<_> {
+            mesh.triangles
+                .iter()
+                .zip(&m.triangles)
+                .filter(|(_, i)| **i == k)
+                .map(|(t, _)| canonical(t))
+                .collect()
+        };
+        let (ta, tb) = (tris(0), tris(1));
+        let mut interface = 0;
+        for t in &ta {
+            let [a, b, c] = *t;
+            if shared.contains(&&a)
+                && shared.contains(&&b)
+                && shared.contains(&&c)
+            {
+                let flipped = canonical(&nalgebra::Vector3::new(a, c, b));
+                assert!(tb.contains(&flipped), "missing interface triangle");
+                interface += 1;
+            }
+        }
+        assert!(interface > 0);
+    }
+
+    #[test]
+    fn test_nested_materials() {
+        // A small sphere embedded in a larger one, on a transformed view
+        let settings = Settings {
+            depth: 5,
+            view: crate::render::View3::from_center_and_scale(
+                nalgebra::Vector3::new(1.0, 2.0, 3.0),
+                2.0,
+            ),
+            ..Default::default()
+        };
+        let mats = [
+            material([1.0, 2.0, 3.0], 1.2, [255, 255, 255]),
+            material([1.3, 2.0, 3.0], 0.5, [0, 255, 0]),
+        ];
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+        check_shells(&mesh);
+
+        // Every vertex of the inner material is also on the outer material,
+        // which has a matching cavity.
+        let a = vertex_set(&mesh, 0);
+        let b = vertex_set(&mesh, 1);
+        assert!(b.is_subset(&a));
+        assert!(a.len() > b.len());
+        for &v in &b {
+            let d = (mesh.vertices[v] - nalgebra::Vector3::new(1.3, 2.0, 3.0))
+                .norm();
+            assert!((d - 0.5).abs() < 0.1, "bad vertex at distance {d}");
+        }
+    }
+
+    #[test]
+    fn test_shell_contract() {
+        // Three overlapping spheres, meeting along a triple junction
+        let settings = Settings {
+            depth: 5,
+            ..Default::default()
+        };
+        let mats = [
+            material([-0.3, -0.2, 0.0], 0.5, [255, 0, 0]),
+            material([0.3, -0.2, 0.0], 0.5, [0, 255, 0]),
+            material([0.0, 0.3, 0.0], 0.5, [0, 0, 255]),
+        ];
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+
+        // Each material is a closed shell
+        check_shells(&mesh);
+
+        // Every triangle appears either once, or twice (as an interface
+        // between two different materials, with opposite windings).
+        let m = mesh.materials.as_ref().unwrap();
+        let mut seen: BTreeMap<[usize; 3], Vec<_>> = BTreeMap::new();
+        for (t, &k) in mesh.triangles.iter().zip(&m.triangles) {
+            let mut key: [usize; 3] = (*t).into();
+            key.sort_unstable();
+            seen.entry(key).or_default().push((k, *t));
+        }
+        let mut pairs = BTreeSet::new();
+        for v in seen.values() {
+            match v.as_slice() {
+                [_] => (),
+                [(ka, ta), (kb, tb)] => {
+                    assert_ne!(ka, kb, "duplicate triangle in one material");
+                    let flipped = nalgebra::Vector3::new(tb.x, tb.z, tb.y);
+                    assert!(
+                        (0..3).any(|i| {
+                            let r = [0, 1, 2].map(|j| flipped[(i + j) % 3]);
+                            ta.as_slice() == r
+                        }),
+                        "interface triangles must have opposite windings"
+                    );
+                    pairs.insert((*ka.min(kb), *ka.max(kb)));
+                }
+                _ => panic!("triangle appears {} times", v.len()),
+            }
+        }
+        assert_eq!(pairs, BTreeSet::from([(0, 1), (0, 2), (1, 2)]));
+    }
+
+    #[test]
+    fn test_material_threads() {
+        let mats = [
+            material([-0.3, -0.2, 0.0], 0.5, [255, 0, 0]),
+            material([0.3, -0.2, 0.0], 0.5, [0, 255, 0]),
+            material([0.0, 0.3, 0.0], 0.5, [0, 0, 255]),
+        ];
+        let mut prev: Option<Mesh> = None;
+        for threads in [
+            ThreadCount::One,
+            ThreadCount::Many(1.try_into().unwrap()),
+            ThreadCount::Many(4.try_into().unwrap()),
+        ] {
+            let settings = Settings {
+                depth: 5,
+                threads,
+                ..Default::default()
+            };
+            let mesh =
+                MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+            check_shells(&mesh);
+
+            // The output doesn't depend on the thread count
+            if let Some(prev) = &prev {
+                assert_eq!(prev.vertices, mesh.vertices);
+                assert_eq!(prev.triangles, mesh.triangles);
+                assert_eq!(
+                    prev.materials.as_ref().unwrap().triangles,
+                    mesh.materials.as_ref().unwrap().triangles
+                );
+            }
+            prev = Some(mesh);
+        }
+    }
+
+    #[test]
+    fn test_material_settings() {
+        // Adaptive subdivision isn't supported
+        let mats = [material([0.0; 3], 0.6, [255, 0, 0])];
+        let settings = Settings {
+            depth: 5,
+            min_depth: Some(2),
+            ..Default::default()
+        };
+        let err = MaterialOctree::build(&mats, settings);
+        assert!(matches!(
+            err,
+            Err(Error::UnsupportedMeshSetting("min_depth"))
+        ));
+        let settings = Settings {
+            depth: 5,
+            max_error: Some(1e-3),
+            ..Default::default()
+        };
+        let err = MaterialOctree::build(&mats, settings);
+        assert!(matches!(
+            err,
+            Err(Error::UnsupportedMeshSetting("max_error"))
+        ));
+    }
+
+    #[test]
+    fn test_capped_materials() {
+        // Both spheres extend past the meshing bounds (and the outer sphere
+        // contains them entirely), so they're clipped
+        let settings = Settings {
+            depth: 4,
+            cap: true,
+            ..Default::default()
+        };
+        let mats = [
+            material([0.0; 3], 1.8, [255, 0, 0]),
+            material([0.8, 0.0, 0.0], 0.5, [0, 0, 255]),
+        ];
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+        check_shells(&mesh);
+        for v in &mesh.vertices {
+            // Vertices on the crease between a cap and the sphere may be
+            // slightly outside of the bounds (as with `Octree`)
+            assert!(v.amax() <= 1.01, "vertex outside bounds: {v:?}");
+        }
+
+        // Both materials have vertices on the cap at x = 1
+        for k in 0..2 {
+            let on_cap = vertex_set(&mesh, k)
+                .iter()
+                .any(|&v| (mesh.vertices[v].x - 1.0).abs() < 1e-3);
+            assert!(on_cap, "material {k} is not capped");
+        }
+
+        // Without capping, only the inner sphere's interface is meshed
+        let settings = Settings {
+            cap: false,
+            ..settings
+        };
+        let mesh = MaterialOctree::build(&mats, settings).unwrap().walk_dual();
+        assert!(!mesh.triangles.is_empty());
+        assert!(mesh.vertices.iter().all(|v| v.x > 0.25));
+    }
+
+    #[test]
+    fn test_vertex_slots() {
+        let leaf = |labels| LabelLeaf {
+            labels,
+            verts: arrayvec::ArrayVec::new(),
+            intersections: [nalgebra::Vector3::zeros(); 12],
+        };
+
+        // Material 1 has two disconnected corners, one of which touches
+        // material 2; material 2 shares that group's vertex, but the other
+        // group gets its own.
+        let l = leaf([1, 2, 0, 0, 0, 0, 0, 1]);
+        let (a, b) = (l.vertex_slots(1), l.vertex_slots(2));
+        assert_eq!(a.len(), 2);
+        assert_ne!(a[0], a[1]);
+        assert_eq!(b.as_slice(), [a[0]]);
+
+        // Material 2 touches both of material 1's groups, but can only share
+        // a vertex with one of them (otherwise, material 1 would be pinched)
+        let l = leaf([1, 2, 0, 2, 0, 2, 0, 1]);
+        let (a, b) = (l.vertex_slots(1), l.vertex_slots(2));
+        assert_eq!(a.len(), 2);
+        assert_ne!(a[0], a[1]);
+        assert_eq!(b.len(), 1);
+        assert!(a.contains(&b[0]));
+    }
+
+    #[test]
+    fn test_split_materials() {
+        let mut mesh = Mesh {
+            vertices: vec![nalgebra::Vector3::zeros(); 4],
+            triangles: vec![
+                nalgebra::Vector3::new(0, 1, 2),
+                nalgebra::Vector3::new(1, 3, 2),
+            ],
+            materials: Some(MeshMaterials {
+                triangles: vec![1, 1],
+                colors: vec![[0; 3], [1; 3]],
+            }),
+            ..Default::default()
+        };
+        let split = mesh.split_materials().unwrap();
+        assert_eq!(split.len(), 2);
+        assert!(split[0].triangles.is_empty());
+        assert_eq!(split[1].vertices.len(), 4);
+        assert_eq!(split[1].triangles, mesh.triangles);
+
+        mesh.materials.as_mut().unwrap().triangles[0] = 2;
+        assert!(matches!(
+            mesh.split_materials(),
+            Err(Error::BadMaterialIndex(2, 2))
+        ));
+        mesh.materials.as_mut().unwrap().triangles.pop();
+        assert!(matches!(
+            mesh.split_materials(),
+            Err(Error::BadMaterialCount(1, 2))
+        ));
+    }
+}
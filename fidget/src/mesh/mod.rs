@@ -3,7 +3,7 @@
 //! This module implements
 //! [Manifold Dual Contouring](https://people.engr.tamu.edu/schaefer/research/dualsimp_tvcg.pdf),
 //! to generate a triangle mesh from an implicit surface (or anything
-//! implementing [`Shape`](crate::shape::Shape)).
+//! implementing [`Shape`]).
 //!
 //! The resulting meshes should be
 //! - Manifold
@@ -11,9 +11,20 @@
 //! - Preserving sharp features (corners / edges)
 //!
 //! However, they may contain self-intersections, and are not guaranteed to
-//! catch thin features (below the sampling grid resolution).
+//! catch thin features (below the sampling grid resolution).  Use
+//! [`Mesh::validate`] to check a particular mesh.
 //!
-//! The resulting [`Mesh`] objects can be written out as STL files.
+//! The resulting [`Mesh`] objects can be written out as STL files.  For very
+//! large meshes, [`Octree::build_to`] streams triangles to a [`MeshSink`]
+//! (e.g. an [`StlWriter`]) while building the octree, without storing either
+//! the octree or a [`Mesh`] in memory.
+//!
+//! To mesh several shapes at once, [`MaterialOctree`] builds a single mesh
+//! with conforming interfaces between [`Material`]s, tagging each triangle
+//! with its material for export to 3MF or PLY.
+//!
+//! For 2D shapes, [`Contours`] uses the same strategy on a quadtree to build
+//! closed polylines, which can be written out as SVG or DXF files.
 //!
 //! Here's a full example:
 //!
@@ -41,14 +52,24 @@
 
 mod builder;
 mod cell;
+mod check;
+mod contour;
 mod dc;
 mod frame;
 mod gen;
+mod material;
 mod octree;
 mod output;
 mod qef;
+mod sink;
 
-use crate::render::{ThreadCount, View3};
+use crate::{
+    eval::Function,
+    render::{ThreadCount, View3},
+    shape::{Shape, ShapeBulkEval, ShapeVars},
+    types::Grad,
+    Error,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod mt;
@@ -57,7 +78,11 @@ mod mt;
 pub mod types;
 
 // Re-export the main Octree type as public
+pub use check::MeshReport;
+pub use contour::{ContourSettings, Contours};
+pub use material::{Material, MaterialOctree};
 pub use octree::Octree;
+pub use sink::{MeshSink, PlyWriter, StlWriter};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -68,6 +93,26 @@ pub struct Mesh {
     pub triangles: Vec<nalgebra::Vector3<usize>>,
     /// Vertex positions
     pub vertices: Vec<nalgebra::Vector3<f32>>,
+    /// Optional per-vertex normals, with one (unit-length) entry per vertex
+    ///
+    /// These are populated by [`compute_normals`](Self::compute_normals) and
+    /// used by the mesh exporters when present.
+    pub normals: Option<Vec<nalgebra::Vector3<f32>>>,
+    /// Optional per-triangle material data
+    ///
+    /// This is populated by [`MaterialOctree::walk_dual`] and used by the
+    /// 3MF and PLY exporters when present.
+    pub materials: Option<MeshMaterials>,
+}
+
+/// Per-triangle material assignments for a [`Mesh`]
+#[derive(Clone, Default, Debug)]
+pub struct MeshMaterials {
+    /// Material index for each triangle, as an index into
+    /// [`self.colors`](Self::colors)
+    pub triangles: Vec<u32>,
+    /// Display color (as RGB) for each material
+    pub colors: Vec<[u8; 3]>,
 }
 
 impl Mesh {
@@ -75,30 +120,267 @@ impl Mesh {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Computes per-vertex normals from the shape's gradient
+    ///
+    /// If the shape uses variables other than `x`, `y`, `z`, then
+    /// [`compute_normals_with_vars`](Self::compute_normals_with_vars) should
+    /// be used instead (and this function will return an error).
+    pub fn compute_normals<F: Function>(
+        &mut self,
+        shape: &Shape<F>,
+    ) -> Result<(), Error> {
+        self.compute_normals_with_vars(shape, &ShapeVars::new())
+    }
+
+    /// Computes per-vertex normals from the shape's gradient
+    ///
+    /// The shape and variables should be the same as those used to build the
+    /// mesh, since the gradient is evaluated at each (model-space) vertex
+    /// position.  Vertices where the gradient is zero or non-finite fall back
+    /// to the area-weighted average of their adjacent faces' normals, then to
+    /// +Z if that is also degenerate, so every normal has unit length.
+    pub fn compute_normals_with_vars<F: Function>(
+        &mut self,
+        shape: &Shape<F>,
+        vars: &ShapeVars<f32>,
+    ) -> Result<(), Error> {
+        let xs: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|v| Grad::new(v.x, 1.0, 0.0, 0.0))
+            .collect();
+        let ys: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|v| Grad::new(v.y, 0.0, 1.0, 0.0))
+            .collect();
+        let zs: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|v| Grad::new(v.z, 0.0, 0.0, 1.0))
+            .collect();
+        let tape = shape.grad_slice_tape(Default::default());
+        let mut eval = ShapeBulkEval::<F::GradSliceEval>::default();
+        let out = eval.eval_v(&tape, &xs, &ys, &zs, vars)?;
+
+        let mut normals: Vec<_> = out
+            .iter()
+            .map(|g| {
+                nalgebra::Vector3::new(g.dx, g.dy, g.dz)
+                    .try_normalize(0.0)
+                    .filter(|n| n.iter().all(|v| v.is_finite()))
+            })
+            .collect();
+
+        // Accumulate face normals for vertices with a degenerate gradient
+        if normals.iter().any(Option::is_none) {
+            let mut acc = vec![nalgebra::Vector3::zeros(); normals.len()];
+            for t in &self.triangles {
+                let a = self.vertices[t.x];
+                let n =
+                    (self.vertices[t.y] - a).cross(&(self.vertices[t.z] - a));
+                for v in t {
+                    acc[*v] += n;
+                }
+            }
+            for (n, a) in normals.iter_mut().zip(acc) {
+                if n.is_none() {
+                    *n = a
+                        .try_normalize(0.0)
+                        .filter(|n| n.iter().all(|v| v.is_finite()));
+                }
+            }
+        }
+        self.normals = Some(
+            normals
+                .into_iter()
+                .map(|n| n.unwrap_or_else(nalgebra::Vector3::z))
+                .collect(),
+        );
+        Ok(())
+    }
 }
 
 /// Settings when building an octree and mesh
 #[derive(Copy, Clone, Debug)]
 pub struct Settings {
     /// Depth to recurse in the octree
+    ///
+    /// When [`min_depth`](Self::min_depth) is set, this is the maximum depth.
     pub depth: u8,
 
+    /// Minimum depth for adaptive subdivision
+    ///
+    /// If this is `None`, every cell containing the surface is subdivided to
+    /// [`depth`](Self::depth).  Otherwise, cells which are at least this deep
+    /// stop subdividing once the surface within them is smooth (see
+    /// [`max_angle`](Self::max_angle)) and simple enough to be represented by
+    /// a single vertex, so only curved regions and sharp features are meshed
+    /// at full depth.
+    pub min_depth: Option<u8>,
+
+    /// Maximum angle (in radians) between surface normals within a cell
+    ///
+    /// This is only used for adaptive subdivision (i.e. when
+    /// [`min_depth`](Self::min_depth) is set); cells with more variation than
+    /// this are subdivided further.
+    pub max_angle: f32,
+
     /// Viewport to provide a world-to-model transform
     pub view: View3,
 
+    /// Axis-aligned region to mesh, with independent per-axis resolution
+    ///
+    /// If this is set, it overrides [`view`](Self::view) and
+    /// [`depth`](Self::depth).
+    pub region: Option<MeshRegion>,
+
+    /// Isovalue of the surface to mesh
+    ///
+    /// By default, meshing extracts the `f(x, y, z) = 0` level set; for a
+    /// distance field, a positive isovalue extracts an outward offset shell.
+    pub isovalue: f32,
+
     /// Number of threads to use
     ///
     /// 1 indicates to use the single-threaded evaluator; other values will
     /// spin up _N_ threads to perform octree construction in parallel.
     pub threads: ThreadCount,
+
+    /// Maximum QEF error when collapsing cells
+    ///
+    /// Octree construction merges groups of 8 leaf cells (bottom-up) when the
+    /// merged cell is topologically safe to collapse and its vertex is a good
+    /// fit for the surface.  If this is `None`, a vertex is a good fit if its
+    /// QEF error is at most twice that of the best child; otherwise, it is a
+    /// good fit if its QEF error (a sum of squared distances to the surface's
+    /// tangent planes, in the ±1 meshing region) is at most this value.
+    ///
+    /// Larger values produce coarser meshes on flat or gently curved surfaces.
+    pub max_error: Option<f32>,
+
+    /// Close the mesh where the surface is clipped by the meshing bounds
+    ///
+    /// If this is `true`, the shape is intersected with the meshing region
+    /// (the ±1 cube of the [`view`](Self::view), or the [`region`](Self::region)
+    /// if present), so the output is closed with flat caps wherever the shape
+    /// extends past the bounds.  The surface within the bounds is unchanged.
+    pub cap: bool,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             depth: 3,
+            min_depth: None,
+            max_angle: std::f32::consts::PI / 16.0,
             view: Default::default(),
+            region: None,
+            isovalue: 0.0,
             threads: ThreadCount::default(),
+            max_error: None,
+            cap: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Returns the transform from the ±1 octree cube to model space
+    pub(crate) fn world_to_model(&self) -> nalgebra::Matrix4<f32> {
+        match &self.region {
+            Some(r) => r.world_to_model(),
+            None => self.view.world_to_model(),
+        }
+    }
+
+    /// Returns the maximum octree depth
+    pub(crate) fn max_depth(&self) -> u8 {
+        match &self.region {
+            Some(r) => r.depth(),
+            None => self.depth,
         }
     }
+
+    /// Returns the octree-space limit of the meshing region on each axis
+    ///
+    /// Cells beyond this limit are outside of the region and are never meshed.
+    pub(crate) fn limit(&self) -> Option<nalgebra::Vector3<f32>> {
+        self.region.map(|r| {
+            let scale = 2.0 / (1u32 << r.depth()) as f32;
+            r.cells.map(|c| c as f32 * scale - 1.0)
+        })
+    }
+}
+
+/// Axis-aligned region for meshing
+///
+/// The region is divided into `cells[i]` cells along each axis, so cells need
+/// not be cubes, and the region need not be a cube.  Internally, it's padded
+/// to a power-of-two octree; cells in the padding are skipped without being
+/// evaluated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshRegion {
+    /// Lower corner of the region, in model coordinates
+    pub lower: nalgebra::Vector3<f32>,
+    /// Upper corner of the region, in model coordinates
+    pub upper: nalgebra::Vector3<f32>,
+    /// Number of cells along each axis
+    pub cells: nalgebra::Vector3<u32>,
+}
+
+impl MeshRegion {
+    /// Builds a new region with the given number of cells on each axis
+    ///
+    /// # Panics
+    /// If `upper` is not strictly greater than `lower` on every axis, or if
+    /// any cell count is zero.
+    pub fn new(
+        lower: nalgebra::Vector3<f32>,
+        upper: nalgebra::Vector3<f32>,
+        cells: nalgebra::Vector3<u32>,
+    ) -> Self {
+        assert!(
+            (0..3).all(|i| upper[i] > lower[i]),
+            "region must have positive size"
+        );
+        assert!(cells.iter().all(|c| *c > 0), "cell counts must be non-zero");
+        Self {
+            lower,
+            upper,
+            cells,
+        }
+    }
+
+    /// Builds a new region with (approximately) cubic cells of the given size
+    ///
+    /// The cell count on each axis is rounded up, so the `upper` corner may
+    /// move outwards to fit a whole number of cells.
+    pub fn with_cell_size(
+        lower: nalgebra::Vector3<f32>,
+        upper: nalgebra::Vector3<f32>,
+        size: f32,
+    ) -> Self {
+        let cells = (upper - lower).map(|d| ((d / size).ceil() as u32).max(1));
+        let upper = lower + cells.map(|c| c as f32 * size);
+        Self::new(lower, upper, cells)
+    }
+
+    /// Returns the octree depth necessary to cover every cell
+    fn depth(&self) -> u8 {
+        self.cells.max().next_power_of_two().trailing_zeros() as u8
+    }
+
+    /// Returns the transform from the ±1 octree cube to model space
+    ///
+    /// Leaf cells in the octree are mapped to this region's cells, starting
+    /// from the lower corner.
+    fn world_to_model(&self) -> nalgebra::Matrix4<f32> {
+        let n = (1u32 << self.depth()) as f32;
+        let size = (self.upper - self.lower)
+            .component_div(&self.cells.map(|c| c as f32));
+        let scale = size * n / 2.0;
+        nalgebra::Matrix4::new_translation(&(self.lower + scale))
+            * nalgebra::Matrix4::new_nonuniform_scaling(&scale)
+    }
 }
@@ -1,6 +1,7 @@
 //! Multithreaded dual contouring
 use super::pool::{QueuePool, ThreadPool};
 use crate::mesh::{
+    builder::canonicalize,
     cell::{CellIndex, CellVertex},
     dc::{dc_cell, dc_edge, dc_face, DcBuilder},
     frame::{Frame, XYZ, YZX, ZXY},
@@ -105,6 +106,8 @@ impl<'a> DcWorker<'a> {
                 *vert_offsets.last().unwrap()
             ],
             triangles: vec![nalgebra::Vector3::zeros(); tri_count],
+            normals: None,
+            materials: None,
         };
 
         let mut slice = mesh.vertices.as_mut_slice();
@@ -126,30 +129,37 @@ impl<'a> DcWorker<'a> {
         // Multi-thread copying!
         let vert_offsets_ref = &vert_offsets;
         std::thread::scope(|s| {
-            for ((tris, verts), (out_t, out_v)) in out
-                .into_iter()
-                .zip(out_tris.into_iter().zip(out_verts.into_iter()))
+            for ((tris, verts), (out_t, out_v)) in
+                out.into_iter().zip(out_tris.into_iter().zip(out_verts))
             {
                 s.spawn(move || {
                     out_t
                         .iter_mut()
                         .zip(tris.iter().map(|t| {
                             t.map(|v| {
-                                let thread = ((v >> 55) & 0xFF) as usize;
-                                let i: usize =
-                                    (v & ((1 << 55) - 1)).try_into().unwrap();
+                                let (thread, i) = decode(v);
                                 vert_offsets_ref[thread] + i
                             })
                         }))
                         .for_each(|(o, i)| *o = i);
-                    out_v
-                        .iter_mut()
-                        .zip(verts.into_iter())
-                        .for_each(|(o, i)| *o = i);
+                    out_v.iter_mut().zip(verts).for_each(|(o, i)| *o = i);
                 });
             }
         });
 
+        // Vertices were claimed by whichever thread got to them first, so put
+        // them back into octree order (and sort triangles) to make the output
+        // independent of thread scheduling.
+        let order =
+            map.iter().filter_map(|v| match v.load(Ordering::Relaxed) {
+                0 => None,
+                v => {
+                    let (thread, i) = decode(v);
+                    Some(vert_offsets[thread] + i)
+                }
+            });
+        canonicalize(&mut mesh, order);
+
         mesh
     }
 
@@ -275,4 +285,13 @@ impl DcBuilder for DcWorker<'_> {
     }
 }
 
+/// Splits a claimed vertex into its thread and per-thread vertex index
+///
+/// See [`DcWorker::map`] for the bit layout.
+fn decode(v: u64) -> (usize, usize) {
+    let thread = ((v >> 55) & 0xFF) as usize;
+    let i: usize = (v & ((1 << 55) - 1)).try_into().unwrap();
+    (thread, i)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
@@ -6,8 +6,11 @@ mod pool;
 pub use dc::DcWorker;
 pub use octree::OctreeWorker;
 
+use super::octree::BuildSettings;
+
 /// Strong type for multithreaded settings
 pub(crate) struct MultithreadedSettings {
     pub depth: u8,
+    pub build: BuildSettings,
     pub threads: std::num::NonZeroUsize,
 }
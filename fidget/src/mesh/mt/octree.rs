@@ -145,7 +145,8 @@ impl<F: Function + RenderHints> OctreeWorker<F> {
                     OctreeBuilder::new()
                 } else {
                     OctreeBuilder::empty()
-                },
+                }
+                .with_settings(settings.build),
                 queue,
                 done,
                 friend_done: friend_done.clone(),
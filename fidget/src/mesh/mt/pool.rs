@@ -31,7 +31,7 @@ impl ThreadPool {
     /// # Panics
     /// If `index` is greater than the `n` used in the constructor, or if this
     /// is called from the same thread used to build the thread pool.
-    pub fn start(&self, index: usize) -> ThreadContext {
+    pub fn start(&self, index: usize) -> ThreadContext<'_> {
         // Record our current index
         let mut w = self.threads.write().unwrap();
         let thread_count = w.len();
@@ -7,14 +7,14 @@ use super::{
     frame::Frame,
     gen::CELL_TO_VERT_TO_EDGES,
     qef::QuadraticErrorSolver,
-    types::{Axis, Corner, Edge},
+    types::{Axis, Corner, Edge, X, Y, Z},
     Mesh, Settings,
 };
 use crate::{
     eval::{BulkEvaluator, Function, TracingEvaluator},
     render::{RenderHints, ThreadCount},
     shape::{Shape, ShapeBulkEval, ShapeTape, ShapeTracingEval, ShapeVars},
-    types::Grad,
+    types::{Grad, Interval},
 };
 use std::{num::NonZeroUsize, sync::Arc, sync::OnceLock};
 
@@ -40,7 +40,7 @@ pub struct EvalGroup<F: Function> {
 }
 
 impl<F: Function> EvalGroup<F> {
-    fn new(shape: Shape<F>) -> Self {
+    pub(crate) fn new(shape: Shape<F>) -> Self {
         Self {
             shape,
             interval: OnceLock::new(),
@@ -102,7 +102,7 @@ impl Octree {
         settings: Settings,
     ) -> Self {
         // Transform the shape given our world-to-model matrix
-        let t = settings.view.world_to_model();
+        let t = settings.world_to_model();
         if t == nalgebra::Matrix4::identity() {
             Self::build_inner(shape, vars, settings)
         } else {
@@ -142,8 +142,17 @@ impl Octree {
 
         match settings.threads {
             ThreadCount::One => {
-                let mut out = OctreeBuilder::new();
-                out.recurse(&eval, vars, CellIndex::default(), settings.depth);
+                let mut out =
+                    OctreeBuilder::new().with_settings((&settings).into());
+                out.recurse(
+                    &eval,
+                    vars,
+                    CellIndex::default(),
+                    settings.max_depth(),
+                );
+                // Recursion allocates cells and vertices in depth-first
+                // order, so this is already in canonical order (aside from
+                // orphaned cells and vertices, which are never visited).
                 out.into()
             }
 
@@ -152,7 +161,8 @@ impl Octree {
                 eval.clone(),
                 vars,
                 MultithreadedSettings {
-                    depth: settings.depth,
+                    depth: settings.max_depth(),
+                    build: (&settings).into(),
                     threads,
                 },
             ),
@@ -160,6 +170,11 @@ impl Octree {
     }
 
     /// Recursively walks the dual of the octree, building a mesh
+    ///
+    /// The mesh is deterministic: vertices and triangles are stored in a
+    /// canonical order (derived from the octree's cells), so the output does
+    /// not depend on `settings.threads`, either here or when building the
+    /// octree.
     pub fn walk_dual(&self, settings: Settings) -> Mesh {
         let mut mesh = MeshBuilder::default();
 
@@ -204,43 +219,64 @@ impl Octree {
 impl Octree {
     /// Merges a set of octrees constructed across multiple workers
     ///
+    /// The merged octree has a canonical layout: cells are stored in
+    /// depth-first order (visiting children in [`Corner`] order), and leaf
+    /// vertices are stored in the order in which their leafs are visited, i.e.
+    /// in Z-order by [`CellIndex`].  This matches the layout of a
+    /// single-threaded build (minus cells and vertices which were orphaned when
+    /// collapsing cells), so the layout doesn't depend on the number of
+    /// threads.
+    ///
     /// # Panics
     /// All cross-octree references must be valid
     pub(crate) fn merge(os: &[Octree]) -> Octree {
-        // Calculate offsets within the global merged Octree
-        let mut cell_offsets = vec![0];
-        let mut vert_offsets = vec![0];
-        for o in os {
-            let i = cell_offsets.last().unwrap();
-            cell_offsets.push(i + o.cells.len());
-
-            let i = vert_offsets.last().unwrap();
-            vert_offsets.push(i + o.verts.len());
-        }
-
         let mut out = Octree {
-            cells: Vec::with_capacity(*cell_offsets.last().unwrap()),
-            verts: Vec::with_capacity(*vert_offsets.last().unwrap()),
+            cells: vec![Cell::Invalid.into(); 8],
+            verts: vec![],
         };
+        for i in 0..8 {
+            Self::merge_cell(os, 0, i, i, &mut out);
+        }
+        out
+    }
 
-        for (t, o) in os.iter().enumerate() {
-            for c in &o.cells {
-                let c: Cell = match (*c).into() {
-                    c @ (Cell::Empty | Cell::Full | Cell::Invalid) => c,
-                    Cell::Branch { index, thread } => Cell::Branch {
-                        index: cell_offsets[thread as usize] + index,
-                        thread: 0,
-                    },
-                    Cell::Leaf(Leaf { mask, index }) => Cell::Leaf(Leaf {
-                        index: vert_offsets[t] + index,
-                        mask,
-                    }),
-                };
-                out.cells.push(c.into());
+    /// Copies cell `from` of octree `t` (and its children) into `out[to]`
+    fn merge_cell(
+        os: &[Octree],
+        t: usize,
+        from: usize,
+        to: usize,
+        out: &mut Octree,
+    ) {
+        out.cells[to] = match os[t].cells[from].into() {
+            c @ (Cell::Empty | Cell::Full | Cell::Invalid) => c,
+            Cell::Branch { index, thread } => {
+                let next = out.cells.len();
+                out.cells.resize(next + 8, Cell::Invalid.into());
+                for i in 0..8 {
+                    Self::merge_cell(
+                        os,
+                        thread as usize,
+                        index + i,
+                        next + i,
+                        out,
+                    );
+                }
+                Cell::Branch {
+                    index: next,
+                    thread: 0,
+                }
+            }
+            Cell::Leaf(Leaf { mask, index }) => {
+                // Each leaf stores its vertices, then its edge intersections
+                let vs = &CELL_TO_VERT_TO_EDGES[mask as usize];
+                let n = vs.len() + vs.iter().map(|e| e.len()).sum::<usize>();
+                let next = out.verts.len();
+                out.verts.extend_from_slice(&os[t].verts[index..index + n]);
+                Cell::Leaf(Leaf { mask, index: next })
             }
-            out.verts.extend(o.verts.iter().cloned());
         }
-        out
+        .into();
     }
 }
 
@@ -284,6 +320,9 @@ pub(crate) struct OctreeBuilder<F: Function + RenderHints> {
     /// Available slots in the `hermite` array
     hermite_slots: Vec<usize>,
 
+    /// Settings which control cell evaluation
+    settings: BuildSettings,
+
     eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
     eval_interval: ShapeTracingEval<F::IntervalEval>,
     eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
@@ -336,6 +375,7 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             leafs: vec![],
             hermite: vec![LeafHermiteData::default()],
             hermite_slots: vec![],
+            settings: BuildSettings::default(),
             eval_float_slice: Shape::<F>::new_float_slice_eval(),
             eval_grad_slice: Shape::<F>::new_grad_slice_eval(),
             eval_interval: Shape::<F>::new_interval_eval(),
@@ -345,6 +385,12 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
         }
     }
 
+    /// Sets the settings which control cell evaluation
+    pub(crate) fn with_settings(mut self, settings: BuildSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
     /// Stores hermite data in the local array
     fn push_hermite(&mut self, d: LeafHermiteData) -> usize {
         if let Some(s) = self.hermite_slots.pop() {
@@ -374,6 +420,10 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
         cell: CellIndex,
         max_depth: u8,
     ) -> CellResult<F> {
+        if self.outside(cell) {
+            return CellResult::Done(Cell::Empty);
+        }
+        let iso = self.settings.isovalue;
         let (i, r) = self
             .eval_interval
             .eval_v(
@@ -384,9 +434,10 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
                 vars,
             )
             .unwrap();
-        if i.upper() < 0.0 {
+        let i = self.settings.cap_interval(i, cell);
+        if i.upper() < iso {
             CellResult::Done(Cell::Full)
-        } else if i.lower() > 0.0 {
+        } else if i.lower() > iso {
             CellResult::Done(Cell::Empty)
         } else {
             let sub_tape = if F::simplify_tree_during_meshing(cell.depth) {
@@ -399,17 +450,157 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             } else {
                 None
             };
-            if cell.depth == max_depth as usize {
-                let eval = sub_tape.unwrap_or_else(|| eval.clone());
+            let eval = sub_tape.unwrap_or_else(|| eval.clone());
+            if cell.depth == max_depth as usize
+                || self.is_smooth(&eval, vars, cell)
+            {
                 let out = CellResult::Done(self.leaf(&eval, vars, cell));
                 if let Ok(t) = Arc::try_unwrap(eval) {
                     self.reclaim(t);
                 }
                 out
             } else {
-                CellResult::Recurse(sub_tape.unwrap_or_else(|| eval.clone()))
+                CellResult::Recurse(eval)
+            }
+        }
+    }
+
+    /// Checks whether an ambiguous cell can become a leaf before `max_depth`
+    ///
+    /// This is only true during adaptive subdivision, for cells at or below
+    /// the minimum depth.  We sample a 3×3×3 grid within the cell, then check
+    /// that
+    ///
+    /// - The cell's corners describe a single-vertex (manifold) leaf, because
+    ///   only manifold leafs may neighbor smaller cells during meshing
+    /// - The sign of every sample agrees with at least one of the corners that
+    ///   it lies between (extending the predicates in
+    ///   [`collapsible`](Self::collapsible)), so that smaller neighbors see
+    ///   consistent topology
+    /// - Every gradient is within [`Settings::max_angle`] of the average
+    ///   direction, i.e. the cell doesn't contain curvature or features
+    fn is_smooth(
+        &mut self,
+        eval: &EvalGroup<F>,
+        vars: &ShapeVars<f32>,
+        cell: CellIndex,
+    ) -> bool {
+        if self
+            .settings
+            .min_depth
+            .is_none_or(|d| cell.depth < d as usize)
+            || self.straddles(cell)
+        {
+            return false;
+        }
+
+        // Sample positions, with `i = x + y * 3 + z * 9`
+        let mut xs = [0.0; 27];
+        let mut ys = [0.0; 27];
+        let mut zs = [0.0; 27];
+        for i in 0..27 {
+            xs[i] = cell.bounds.x.lerp((i % 3) as f32 / 2.0);
+            ys[i] = cell.bounds.y.lerp((i / 3 % 3) as f32 / 2.0);
+            zs[i] = cell.bounds.z.lerp((i / 9) as f32 / 2.0);
+        }
+
+        // Signs are taken from the float evaluator, so that they match the
+        // corner values computed in `leaf`
+        let out = self
+            .eval_float_slice
+            .eval_v(
+                eval.float_slice_tape(&mut self.tape_storage),
+                &xs,
+                &ys,
+                &zs,
+                vars,
+            )
+            .unwrap();
+        let iso = self.settings.isovalue;
+        let inside: [bool; 27] = std::array::from_fn(|i| {
+            let pos = nalgebra::Vector3::new(xs[i], ys[i], zs[i]);
+            self.settings.cap_float(out[i], pos) < iso
+        });
+
+        let mut mask = 0u8;
+        for c in Corner::iter() {
+            let i = c.index();
+            let j = (i & 1) * 2 + (i >> 1 & 1) * 6 + (i >> 2 & 1) * 18;
+            mask |= (inside[j] as u8) << i;
+        }
+        if mask == 0
+            || mask == 255
+            || CELL_TO_VERT_TO_EDGES[mask as usize].len() != 1
+        {
+            return false;
+        }
+
+        for (i, &s) in inside.iter().enumerate() {
+            let p = [i % 3, i / 3 % 3, i / 9];
+            let agrees = Corner::iter().any(|c| {
+                let adjacent = (0..3)
+                    .all(|k| p[k] == 1 || p[k] == 2 * (c.index() >> k & 1));
+                adjacent && ((mask & (1 << c.index()) != 0) == s)
+            });
+            if !agrees {
+                return false;
+            }
+        }
+
+        let xs = xs.map(|x| Grad::new(x, 1.0, 0.0, 0.0));
+        let ys = ys.map(|y| Grad::new(y, 0.0, 1.0, 0.0));
+        let zs = zs.map(|z| Grad::new(z, 0.0, 0.0, 1.0));
+        let grads = self
+            .eval_grad_slice
+            .eval_v(
+                eval.grad_slice_tape(&mut self.tape_storage),
+                &xs,
+                &ys,
+                &zs,
+                vars,
+            )
+            .unwrap();
+        let mut normals = [nalgebra::Vector3::zeros(); 27];
+        for (i, (n, g)) in normals.iter_mut().zip(grads).enumerate() {
+            let pos = nalgebra::Vector3::new(xs[i].v, ys[i].v, zs[i].v);
+            let g = self.settings.cap_grad(*g, pos);
+            match nalgebra::Vector3::new(g.dx, g.dy, g.dz).try_normalize(0.0) {
+                Some(v) if v.iter().all(|f| f.is_finite()) => *n = v,
+                _ => return false,
             }
         }
+        let Some(mean) = normals
+            .iter()
+            .sum::<nalgebra::Vector3<f32>>()
+            .try_normalize(0.0)
+        else {
+            return false;
+        };
+        let min_dot = self.settings.max_angle.cos();
+        normals.iter().all(|n| n.dot(&mean) >= min_dot)
+    }
+
+    /// Checks whether a cell is entirely outside of the meshing region
+    fn outside(&self, cell: CellIndex) -> bool {
+        self.settings.limit.is_some_and(|limit| {
+            [X, Y, Z]
+                .iter()
+                .zip(&limit)
+                .any(|(&a, &v)| cell.bounds[a].lower() >= v)
+        })
+    }
+
+    /// Checks whether a cell is partially outside of the meshing region
+    ///
+    /// Such a cell must be subdivided, because its corners aren't all sampled
+    /// within the region.
+    fn straddles(&self, cell: CellIndex) -> bool {
+        self.settings.limit.is_some_and(|limit| {
+            [X, Y, Z]
+                .iter()
+                .zip(&limit)
+                .any(|(&a, &v)| cell.bounds[a].upper() > v)
+        })
     }
 
     /// Records the vertex and hermite data for the given leaf
@@ -448,7 +639,7 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
     }
 
     /// Recurse down the octree, building the given cell
-    fn recurse(
+    pub(crate) fn recurse(
         &mut self,
         eval: &Arc<EvalGroup<F>>,
         vars: &ShapeVars<f32>,
@@ -526,7 +717,10 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
         let mask = out
             .iter()
             .enumerate()
-            .filter(|(_i, &v)| v < 0.0)
+            .filter(|(i, &v)| {
+                let pos = nalgebra::Vector3::new(xs[*i], ys[*i], zs[*i]);
+                self.settings.cap_float(v, pos) < self.settings.isovalue
+            })
             .fold(0, |acc, (i, _v)| acc | (1 << i));
 
         // Early exit if the cell is completely empty or full
@@ -628,18 +822,27 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
                 .unwrap();
 
             // Update start and end positions based on evaluation
-            for ((start, end), search) in start
+            for (n, ((start, end), search)) in start
                 .iter_mut()
                 .zip(end.iter_mut())
                 .zip(out.chunks(EDGE_SEARCH_SIZE))
+                .enumerate()
             {
+                let search: [f32; EDGE_SEARCH_SIZE] =
+                    std::array::from_fn(|j| {
+                        let k = n * EDGE_SEARCH_SIZE + j;
+                        let pos = nalgebra::Vector3::new(xs[k], ys[k], zs[k]);
+                        self.settings.cap_float(search[j], pos)
+                    });
+
                 // The search must be inside-to-outside
-                debug_assert!(search[0] < 0.0);
-                debug_assert!(search[EDGE_SEARCH_SIZE - 1] >= 0.0);
+                let iso = self.settings.isovalue;
+                debug_assert!(search[0] < iso);
+                debug_assert!(search[EDGE_SEARCH_SIZE - 1] >= iso);
                 let frac = search
                     .iter()
                     .enumerate()
-                    .find(|(_i, v)| **v >= 0.0)
+                    .find(|(_i, v)| **v >= iso)
                     .unwrap()
                     .0;
                 debug_assert!(frac > 0);
@@ -707,7 +910,8 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             let mut qef = QuadraticErrorSolver::new();
             for e in vs.iter() {
                 let pos = nalgebra::Vector3::new(xs[i].v, ys[i].v, zs[i].v);
-                let grad: nalgebra::Vector4<f32> = grads[i].into();
+                let grad: nalgebra::Vector4<f32> =
+                    self.settings.cap_grad(grads[i], pos).into();
 
                 qef.add_intersection(pos, grad);
 
@@ -807,7 +1011,10 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             BranchResult::Full
         } else if empty_count == 8 {
             BranchResult::Empty
-        } else if !has_branch && self.collapsible(index) {
+        } else if !has_branch
+            && !self.straddles(cell)
+            && self.collapsible(index)
+        {
             let mut hermite = LeafHermiteData::merge(hermite_data);
 
             // Empty / full cells should never be produced here.  The only way to
@@ -821,7 +1028,11 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             debug_assert!(hermite.mask != 0);
             debug_assert!(hermite.mask != 255);
             let (pos, new_err) = hermite.solve();
-            if new_err < hermite.qef_err * 2.0 && cell.bounds.contains(pos) {
+            let good_fit = match self.settings.max_error {
+                Some(max_error) => new_err <= max_error,
+                None => new_err < hermite.qef_err * 2.0,
+            };
+            if good_fit && cell.bounds.contains(pos) {
                 hermite.qef_err = new_err;
                 BranchResult::Leaf(pos, hermite)
             } else {
@@ -974,6 +1185,7 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
             leafs: vec![],
             hermite: vec![LeafHermiteData::default()],
             hermite_slots: vec![],
+            settings: BuildSettings::default(),
 
             eval_float_slice: Shape::<F>::new_float_slice_eval(),
             eval_grad_slice: Shape::<F>::new_grad_slice_eval(),
@@ -998,6 +1210,112 @@ impl<F: Function + RenderHints> OctreeBuilder<F> {
     }
 }
 
+/// Settings which are used during octree construction
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct BuildSettings {
+    /// See [`Settings::isovalue`]
+    pub isovalue: f32,
+    /// Octree-space limit of the meshing region (see [`Settings::region`])
+    pub limit: Option<nalgebra::Vector3<f32>>,
+    /// See [`Settings::max_error`]
+    pub max_error: Option<f32>,
+    /// See [`Settings::min_depth`]
+    pub min_depth: Option<u8>,
+    /// See [`Settings::max_angle`]
+    pub max_angle: f32,
+    /// See [`Settings::cap`]
+    pub cap: bool,
+}
+
+impl From<&Settings> for BuildSettings {
+    fn from(s: &Settings) -> Self {
+        Self {
+            isovalue: s.isovalue,
+            limit: s.limit(),
+            max_error: s.max_error,
+            min_depth: s.min_depth,
+            max_angle: s.max_angle,
+            cap: s.cap,
+        }
+    }
+}
+
+impl BuildSettings {
+    /// Returns the center and half-size of the capping box, if enabled
+    ///
+    /// The box spans from -1 to the region limit (or 1) on each axis.
+    fn cap_box(
+        &self,
+    ) -> Option<(nalgebra::Vector3<f32>, nalgebra::Vector3<f32>)> {
+        self.cap.then(|| {
+            let upper = self.limit.unwrap_or(nalgebra::Vector3::repeat(1.0));
+            (upper.add_scalar(-1.0) / 2.0, upper.add_scalar(1.0) / 2.0)
+        })
+    }
+
+    /// Applies the capping box to an interval result over the given cell
+    pub(crate) fn cap_interval(
+        &self,
+        i: Interval,
+        cell: CellIndex,
+    ) -> Interval {
+        let Some((center, half)) = self.cap_box() else {
+            return i;
+        };
+        let b = [X, Y, Z]
+            .iter()
+            .enumerate()
+            .map(|(k, &a)| {
+                (cell.bounds[a] - Interval::from(center[k])).abs()
+                    - Interval::from(half[k])
+            })
+            .reduce(|a, b| {
+                Interval::new(
+                    a.lower().max(b.lower()),
+                    a.upper().max(b.upper()),
+                )
+            })
+            .unwrap();
+        let iso = self.isovalue;
+        Interval::new(
+            i.lower().max(b.lower() + iso),
+            i.upper().max(b.upper() + iso),
+        )
+    }
+
+    /// Applies the capping box to a gradient result at the given position
+    ///
+    /// Values are offset by the isovalue, so that the walls of the box are
+    /// at the same level set as the surface.  Float results use the value of
+    /// the returned [`Grad`].
+    pub(crate) fn cap_grad(
+        &self,
+        g: Grad,
+        pos: nalgebra::Vector3<f32>,
+    ) -> Grad {
+        let Some((center, half)) = self.cap_box() else {
+            return g;
+        };
+        let d = pos - center;
+        let (k, v) = (0..3)
+            .map(|k| (k, d[k].abs() - half[k]))
+            .reduce(|a, b| if b.1 > a.1 { b } else { a })
+            .unwrap();
+        let mut n = nalgebra::Vector3::zeros();
+        n[k] = d[k].signum();
+        g.max(Grad::new(v + self.isovalue, n.x, n.y, n.z))
+    }
+
+    /// Applies the capping box to a float result at the given position
+    pub(crate) fn cap_float(&self, v: f32, pos: nalgebra::Vector3<f32>) -> f32 {
+        if self.cap {
+            self.cap_grad(Grad::from(v), pos).v
+        } else {
+            v
+        }
+    }
+}
+
 /// Result of a single cell evaluation
 pub enum CellResult<F: Function> {
     Done(Cell),
@@ -1096,7 +1414,6 @@ impl LeafHermiteData {
     /// Merges an octree subdivision of leaf hermite data
     fn merge(leafs: [LeafHermiteData; 8]) -> Self {
         let mut out = Self::default();
-        use super::types::{X, Y, Z};
 
         // Accumulate intersections along edges
         for t in [X, Y, Z] {
@@ -1213,11 +1530,14 @@ impl LeafHermiteData {
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use crate::{
         context::Tree,
-        mesh::types::{Edge, X, Y, Z},
+        mesh::{
+            types::{Edge, X, Y, Z},
+            MeshRegion,
+        },
         render::{ThreadCount, View3},
         shape::EzShape,
         var::Var,
@@ -1242,7 +1562,8 @@ mod test {
         }
     }
 
-    fn sphere(center: [f32; 3], radius: f32) -> Tree {
+    /// Sphere fixture, shared with other meshing tests
+    pub(crate) fn sphere(center: [f32; 3], radius: f32) -> Tree {
         let (x, y, z) = Tree::axes();
         ((x - center[0]).square()
             + (y - center[1]).square()
@@ -1764,6 +2085,7 @@ mod test {
             depth: 4,
             threads: ThreadCount::One,
             view: View3::from_center_and_scale(center, 0.5),
+            ..Default::default()
         };
 
         let octree = Octree::build(&shape, settings).walk_dual(settings);
@@ -1787,7 +2109,7 @@ mod test {
             let settings = Settings {
                 depth: 4,
                 threads,
-                view: View3::default(),
+                ..Default::default()
             };
 
             for r in [0.5, 0.75] {
@@ -1805,4 +2127,390 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_max_error() {
+        let shape = VmShape::from(sphere([0.0; 3], 0.6));
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let mut prev = None;
+            for max_error in [Some(0.0), None, Some(1e-4), Some(1e-3)] {
+                let settings = Settings {
+                    depth: 6,
+                    threads,
+                    max_error,
+                    ..Default::default()
+                };
+                let octree = Octree::build(&shape, settings);
+                let mesh = octree.walk_dual(settings);
+                if let Err(e) = check_for_vertex_dupes(&mesh) {
+                    panic!("{e} (with {max_error:?}, {threads} threads)");
+                }
+                if let Err(e) = check_for_edge_matching(&mesh) {
+                    panic!("{e} (with {max_error:?}, {threads} threads)");
+                }
+                for v in &mesh.vertices {
+                    let r = v.norm();
+                    assert!((r - 0.6).abs() < 0.05, "bad vertex radius {r}");
+                }
+
+                // Larger error thresholds produce smaller meshes
+                let n = mesh.triangles.len();
+                if let Some(prev) = prev {
+                    assert!(n < prev, "{n} >= {prev} with {max_error:?}");
+                }
+                prev = Some(n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_depth() {
+        const COLONNADE: &str = include_str!("../../../models/colonnade.vm");
+        let (ctx, root) =
+            crate::Context::from_text(COLONNADE.as_bytes()).unwrap();
+        let shapes = [
+            VmShape::from(sphere([0.1, 0.0, -0.1], 0.6)),
+            VmShape::from(cube([-0.5, 0.6], [-0.4, 0.3], [-0.3, 0.6])),
+            VmShape::new(&ctx, root).unwrap(),
+        ];
+        for (i, shape) in shapes.into_iter().enumerate() {
+            for threads in
+                [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+            {
+                let settings = Settings {
+                    depth: 6,
+                    min_depth: Some(3),
+                    threads,
+                    ..Default::default()
+                };
+                let octree = Octree::build(&shape, settings);
+                let mesh = octree.walk_dual(settings);
+                if let Err(e) = check_for_edge_matching(&mesh) {
+                    panic!("shape {i} has {e} (with {threads} threads)");
+                }
+            }
+        }
+
+        // Smooth surfaces should be meshed at a lower depth
+        let center = Vector3::new(0.1, 0.0, -0.1);
+        let shape = VmShape::from(sphere(center.into(), 0.6));
+        let uniform = Settings {
+            depth: 6,
+            threads: ThreadCount::One,
+            ..Default::default()
+        };
+        let adaptive = Settings {
+            min_depth: Some(3),
+            ..uniform
+        };
+        let a = Octree::build(&shape, uniform).walk_dual(uniform);
+        let b = Octree::build(&shape, adaptive).walk_dual(adaptive);
+        assert!(b.triangles.len() * 4 < a.triangles.len());
+        for v in &b.vertices {
+            let r = (v - center).norm();
+            assert!((r - 0.6).abs() < 0.02, "bad vertex radius {r}");
+        }
+
+        // A smaller angle threshold subdivides further
+        let strict = Settings {
+            max_angle: adaptive.max_angle / 4.0,
+            ..adaptive
+        };
+        let c = Octree::build(&shape, strict).walk_dual(strict);
+        assert!(c.triangles.len() > b.triangles.len());
+    }
+
+    #[test]
+    fn test_mesh_region() {
+        // A long, thin cylinder along the X axis, with flat end caps
+        let (x, y, z) = Tree::axes();
+        let r = (y.square() + z.square()).sqrt() - 0.1;
+        let shape = VmShape::from(r.max(x.abs() - 2.0));
+
+        let lower = Vector3::new(-2.2, -0.15, -0.15);
+        let upper = Vector3::new(2.2, 0.15, 0.15);
+        let region = MeshRegion::with_cell_size(lower, upper, 0.025);
+        assert_eq!(region.cells, Vector3::new(176, 12, 12));
+
+        let mut triangle_count = None;
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let settings = Settings {
+                region: Some(region),
+                threads,
+                ..Default::default()
+            };
+            let octree = Octree::build(&shape, settings);
+            let mesh = octree.walk_dual(settings);
+            if let Err(e) = check_for_edge_matching(&mesh) {
+                panic!("{e} (with {threads} threads)");
+            }
+            for v in &mesh.vertices {
+                let r = (v.y.powi(2) + v.z.powi(2)).sqrt();
+                assert!(
+                    (r - 0.1).abs() < 5e-3 || (v.x.abs() - 2.0).abs() < 1e-3,
+                    "bad vertex at {v:?}"
+                );
+                assert!(r < 0.1 + 5e-3 && v.x.abs() < 2.0 + 1e-3);
+            }
+            let n = mesh.triangles.len();
+            assert_eq!(*triangle_count.get_or_insert(n), n);
+        }
+
+        // Cells can be stretched on one axis
+        let region = MeshRegion::new(lower, upper, Vector3::new(32, 16, 16));
+        let settings = Settings {
+            region: Some(region),
+            ..Default::default()
+        };
+        let mesh = Octree::build(&shape, settings).walk_dual(settings);
+        check_for_edge_matching(&mesh).unwrap();
+        for v in &mesh.vertices {
+            assert!(v.x.abs() < 2.0 + 1e-3, "bad vertex at {v:?}");
+        }
+    }
+
+    #[test]
+    fn test_mesh_region_clipped() {
+        // This sphere extends past the region, so the mesh is open
+        let shape = VmShape::from(sphere([0.0; 3], 0.8));
+        let lower = Vector3::new(-1.0, -1.0, -0.5);
+        let upper = Vector3::new(1.0, 0.5, 0.5);
+        let region = MeshRegion::new(lower, upper, Vector3::new(32, 24, 16));
+        let settings = Settings {
+            region: Some(region),
+            ..Default::default()
+        };
+        let mesh = Octree::build(&shape, settings).walk_dual(settings);
+        assert!(!mesh.triangles.is_empty());
+        for v in &mesh.vertices {
+            assert!(
+                (0..3).all(|i| v[i] >= lower[i] && v[i] <= upper[i]),
+                "vertex {v:?} is outside of the region"
+            );
+            assert!((v.norm() - 0.8).abs() < 5e-3, "bad vertex at {v:?}");
+        }
+        let r = mesh.validate();
+        assert!(r.is_manifold());
+        assert!(!r.is_watertight());
+    }
+
+    #[test]
+    fn test_mesh_cap() {
+        // This sphere extends past the ±1 bounds on every axis
+        let shape = VmShape::from(sphere([0.0; 3], 1.2));
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let settings = Settings {
+                depth: 5,
+                threads,
+                ..Default::default()
+            };
+            let mesh = Octree::build(&shape, settings).walk_dual(settings);
+            assert!(!mesh.validate().is_watertight());
+
+            let settings = Settings {
+                cap: true,
+                ..settings
+            };
+            let capped = Octree::build(&shape, settings).walk_dual(settings);
+            let r = capped.validate();
+            assert!(r.is_watertight(), "{r:?}");
+            assert!(r.is_oriented(), "{r:?}");
+            for v in &capped.vertices {
+                assert!(
+                    v.norm() <= 1.2 + 5e-3,
+                    "vertex {v:?} is outside the sphere"
+                );
+                assert!(
+                    v.iter().all(|c| c.abs() <= 1.0 + 1e-5),
+                    "vertex {v:?} is outside the bounds"
+                );
+            }
+        }
+
+        // Surfaces which are entirely within the bounds are unchanged
+        let shape = VmShape::from(sphere([0.0; 3], 0.7));
+        let settings = Settings {
+            depth: 5,
+            cap: true,
+            threads: ThreadCount::One,
+            ..Default::default()
+        };
+        let capped = Octree::build(&shape, settings).walk_dual(settings);
+        let settings = Settings {
+            cap: false,
+            ..settings
+        };
+        let mesh = Octree::build(&shape, settings).walk_dual(settings);
+        assert_eq!(capped.vertices, mesh.vertices);
+        assert_eq!(capped.triangles, mesh.triangles);
+    }
+
+    #[test]
+    fn test_mesh_region_cap() {
+        let shape = VmShape::from(sphere([0.0; 3], 0.8));
+        let lower = Vector3::new(-1.0, -1.0, -0.5);
+        let upper = Vector3::new(1.0, 0.5, 0.5);
+        let region = MeshRegion::new(lower, upper, Vector3::new(32, 24, 16));
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let settings = Settings {
+                region: Some(region),
+                cap: true,
+                threads,
+                ..Default::default()
+            };
+            let mesh = Octree::build(&shape, settings).walk_dual(settings);
+            let r = mesh.validate();
+            assert!(r.is_watertight(), "{r:?}");
+            assert!(r.is_oriented(), "{r:?}");
+            for v in &mesh.vertices {
+                assert!(
+                    (0..3)
+                        .all(|i| v[i] >= lower[i] - 1e-5
+                            && v[i] <= upper[i] + 1e-5),
+                    "vertex {v:?} is outside of the region"
+                );
+                // Vertices on the rim of the caps may be up to a cell away
+                assert!(v.norm() <= 0.8 + 0.0625, "bad vertex at {v:?}");
+            }
+        }
+    }
+
+    /// Returns the vertex index of each leaf, in depth-first order
+    fn leaf_order(octree: &Octree, cell: usize, out: &mut Vec<usize>) {
+        match octree.cells[cell].into() {
+            Cell::Branch { index, .. } => {
+                for i in 0..8 {
+                    leaf_order(octree, index + i, out);
+                }
+            }
+            Cell::Leaf(Leaf { index, .. }) => out.push(index),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn test_canonical_layout() {
+        let shape = VmShape::from(
+            sphere([-0.3, 0.0, 0.0], 0.4).min(sphere([0.3, 0.1, 0.0], 0.45)),
+        );
+        let settings = Settings {
+            depth: 5,
+            min_depth: Some(3),
+            max_error: Some(1e-3),
+            ..Default::default()
+        };
+        let mut layouts = vec![];
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let octree = Octree::build(
+                &shape,
+                Settings {
+                    threads,
+                    ..settings
+                },
+            );
+            let mut order = vec![];
+            for i in 0..8 {
+                leaf_order(&octree, i, &mut order);
+            }
+            // Leaf vertices are stored in depth-first order, which is all that
+            // meshing needs to produce a canonical mesh.
+            assert!(order.windows(2).all(|w| w[0] < w[1]));
+            let verts: Vec<_> =
+                order.iter().map(|i| octree.verts[*i].pos).collect();
+            layouts.push(verts);
+        }
+        // Collapsing cells leaves orphaned vertices in single-threaded builds,
+        // but the depth-first vertices are the same.
+        assert_eq!(layouts[0], layouts[1]);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let (ctx, root) = crate::Context::from_text(
+            include_str!("../../../models/bear.vm").as_bytes(),
+        )
+        .unwrap();
+        let bear = VmShape::new(&ctx, root).unwrap();
+        let spheres = VmShape::from(
+            sphere([-0.3, 0.0, 0.0], 0.4).min(sphere([0.3, 0.1, 0.0], 0.45)),
+        );
+        for shape in [bear, spheres] {
+            for settings in [
+                Settings {
+                    depth: 6,
+                    ..Default::default()
+                },
+                Settings {
+                    depth: 6,
+                    min_depth: Some(3),
+                    max_error: Some(1e-3),
+                    ..Default::default()
+                },
+            ] {
+                let mut expected: Option<(Vec<u8>, Vec<u8>)> = None;
+                for threads in [1, 2, 8] {
+                    let settings = Settings {
+                        threads: match threads {
+                            1 => ThreadCount::One,
+                            n => ThreadCount::Many(n.try_into().unwrap()),
+                        },
+                        ..settings
+                    };
+                    let octree = Octree::build(&shape, settings);
+                    let mesh = octree.walk_dual(settings);
+                    let mut ply = vec![];
+                    mesh.write_ply(&mut ply).unwrap();
+                    let mut stl = vec![];
+                    mesh.write_stl(&mut stl).unwrap();
+                    match &expected {
+                        Some(e) => {
+                            assert!(
+                                e.0 == ply,
+                                "PLY differs ({threads} threads)"
+                            );
+                            assert!(
+                                e.1 == stl,
+                                "STL differs ({threads} threads)"
+                            );
+                        }
+                        None => expected = Some((ply, stl)),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mesh_isovalue() {
+        let shape = VmShape::from(sphere([0.0; 3], 0.5));
+        for (iso, radius) in [(0.0, 0.5), (0.2, 0.7), (-0.25, 0.25)] {
+            for threads in
+                [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+            {
+                let settings = Settings {
+                    depth: 5,
+                    isovalue: iso,
+                    threads,
+                    ..Default::default()
+                };
+                let octree = Octree::build(&shape, settings);
+                let mesh = octree.walk_dual(settings);
+                check_for_edge_matching(&mesh).unwrap();
+                for v in &mesh.vertices {
+                    let r = v.norm();
+                    assert!((r - radius).abs() < 0.01, "bad radius {r}");
+                }
+            }
+        }
+    }
 }
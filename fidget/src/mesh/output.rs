@@ -1,26 +1,24 @@
-//! Mesh output implementation
-use super::Mesh;
+//! Mesh and contour output implementation
+use super::{Contours, Mesh};
 
 impl Mesh {
     /// Writes a binary STL to the given output
+    ///
+    /// If the mesh has [per-vertex normals](Self::normals), each facet's
+    /// normal is the average of its vertex normals.
     pub fn write_stl<F: std::io::Write>(
         &self,
         out: &mut F,
     ) -> Result<(), crate::Error> {
+        let normals = self.checked_normals()?;
+        let count: u32 = checked_count(self.triangles.len(), "STL")?;
         const HEADER: &[u8] = b"This is a binary STL file exported by Fidget";
         static_assertions::const_assert!(HEADER.len() <= 80);
         out.write_all(HEADER)?;
         out.write_all(&[0u8; 80 - HEADER.len()])?;
-        out.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
         for t in &self.triangles {
-            // Not the _best_ way to calculate a normal, but good enough
-            let a = self.vertices[t.x];
-            let b = self.vertices[t.y];
-            let c = self.vertices[t.z];
-            let ab = b - a;
-            let ac = c - a;
-            let normal = ab.cross(&ac);
-            for p in &normal {
+            for p in &self.facet_normal(t, normals) {
                 out.write_all(&p.to_le_bytes())?;
             }
             for v in t {
@@ -32,4 +30,1002 @@ impl Mesh {
         }
         Ok(())
     }
+
+    /// Writes an ASCII STL to the given output
+    ///
+    /// ASCII STL files are much larger than binary STLs, but are occasionally
+    /// required by other tools.  Facet normals are computed as in
+    /// [`write_stl`](Self::write_stl).
+    pub fn write_stl_ascii<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let normals = self.checked_normals()?;
+        writeln!(out, "solid fidget")?;
+        for t in &self.triangles {
+            let n = self
+                .facet_normal(t, normals)
+                .try_normalize(0.0)
+                .unwrap_or_default();
+            writeln!(out, "facet normal {} {} {}", n.x, n.y, n.z)?;
+            writeln!(out, "outer loop")?;
+            for v in t {
+                let p = self.vertices[*v];
+                writeln!(out, "vertex {} {} {}", p.x, p.y, p.z)?;
+            }
+            writeln!(out, "endloop")?;
+            writeln!(out, "endfacet")?;
+        }
+        writeln!(out, "endsolid fidget")?;
+        Ok(())
+    }
+
+    /// Writes a Wavefront OBJ file to the given output
+    ///
+    /// Unlike STL, this preserves shared vertices.  If the mesh has
+    /// [per-vertex normals](Self::normals), they are written as `vn` entries.
+    pub fn write_obj<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let normals = self.checked_normals()?;
+        writeln!(out, "# OBJ file exported by Fidget")?;
+        for v in &self.vertices {
+            writeln!(out, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for n in normals.into_iter().flatten() {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        // OBJ indices are 1-based
+        for t in &self.triangles {
+            let (a, b, c) = (t.x + 1, t.y + 1, t.z + 1);
+            if normals.is_some() {
+                writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+            } else {
+                writeln!(out, "f {a} {b} {c}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a binary (little-endian) PLY file to the given output
+    ///
+    /// If the mesh has [per-vertex normals](Self::normals), they are included
+    /// as `nx`, `ny`, `nz` properties.  If the mesh has
+    /// [materials](Self::materials), each face includes a `material` index and
+    /// its `red`, `green`, `blue` color.
+    pub fn write_ply<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        self.write_ply_with(out, self.normals.as_deref(), None)
+    }
+
+    /// Writes a binary (little-endian) PLY file with per-vertex attributes
+    ///
+    /// `normals` and `colors`, if present, must have one entry per vertex;
+    /// otherwise, this returns [`Error::BadAttributeCount`] before writing
+    /// anything.  The mesh's own [`normals`](Self::normals) are ignored, but
+    /// its [`materials`](Self::materials) are written as face properties.
+    ///
+    /// [`Error::BadAttributeCount`]: crate::Error::BadAttributeCount
+    pub fn write_ply_with<F: std::io::Write>(
+        &self,
+        out: &mut F,
+        normals: Option<&[nalgebra::Vector3<f32>]>,
+        colors: Option<&[[u8; 3]]>,
+    ) -> Result<(), crate::Error> {
+        let n = self.vertices.len();
+        for len in [normals.map(|v| v.len()), colors.map(|v| v.len())]
+            .into_iter()
+            .flatten()
+        {
+            if len != n {
+                return Err(crate::Error::BadAttributeCount(len, n));
+            }
+        }
+        let materials = self.checked_materials()?;
+        checked_count::<u32>(n, "PLY")?; // vertex indices are written as u32
+
+        writeln!(out, "ply")?;
+        writeln!(out, "format binary_little_endian 1.0")?;
+        writeln!(out, "comment exported by Fidget")?;
+        writeln!(out, "element vertex {n}")?;
+        for p in ["x", "y", "z"] {
+            writeln!(out, "property float {p}")?;
+        }
+        if normals.is_some() {
+            for p in ["nx", "ny", "nz"] {
+                writeln!(out, "property float {p}")?;
+            }
+        }
+        if colors.is_some() {
+            for p in ["red", "green", "blue"] {
+                writeln!(out, "property uchar {p}")?;
+            }
+        }
+        writeln!(out, "element face {}", self.triangles.len())?;
+        writeln!(out, "property list uchar uint vertex_indices")?;
+        if materials.is_some() {
+            writeln!(out, "property uint material")?;
+            for p in ["red", "green", "blue"] {
+                writeln!(out, "property uchar {p}")?;
+            }
+        }
+        writeln!(out, "end_header")?;
+
+        for (i, v) in self.vertices.iter().enumerate() {
+            for p in v {
+                out.write_all(&p.to_le_bytes())?;
+            }
+            if let Some(normals) = normals {
+                for p in &normals[i] {
+                    out.write_all(&p.to_le_bytes())?;
+                }
+            }
+            if let Some(colors) = colors {
+                out.write_all(&colors[i])?;
+            }
+        }
+        for (i, t) in self.triangles.iter().enumerate() {
+            out.write_all(&[3])?;
+            for v in t {
+                out.write_all(&(*v as u32).to_le_bytes())?;
+            }
+            if let Some(m) = materials {
+                let k = m.triangles[i];
+                out.write_all(&k.to_le_bytes())?;
+                out.write_all(&m.colors[k as usize])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a 3D Manufacturing Format (3MF) package to the given output
+    ///
+    /// The package is a ZIP archive containing a single mesh object, with
+    /// units of millimeters.  The archive is assembled in memory, then copied
+    /// to the output.
+    ///
+    /// If the mesh has [materials](Self::materials), they're written as a
+    /// base material group (using each material's color), and the mesh is
+    /// [split](Self::split_materials) into one object per material.  Materials
+    /// without any triangles are skipped; object IDs are still assigned by
+    /// material index.
+    ///
+    /// This requires the `3mf` feature.
+    #[cfg(feature = "3mf")]
+    pub fn write_3mf<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        use std::io::Write;
+
+        const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+        const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+        let mut model = vec![];
+        writeln!(model, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            model,
+            r#"<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">"#
+        )?;
+        match self.checked_materials()? {
+            None => {
+                writeln!(model, "<resources>")?;
+                write_3mf_object(&mut model, self, 1, None)?;
+                writeln!(model, "</resources>")?;
+                writeln!(model, r#"<build><item objectid="1"/></build>"#)?;
+            }
+            Some(m) => {
+                writeln!(model, "<resources>")?;
+                writeln!(model, r#"<basematerials id="1">"#)?;
+                for (k, [r, g, b]) in m.colors.iter().enumerate() {
+                    writeln!(
+                        model,
+                        r##"<base name="material {k}" displaycolor="#{r:02X}{g:02X}{b:02X}"/>"##
+                    )?;
+                }
+                writeln!(model, "</basematerials>")?;
+                // Materials without triangles don't get an object, since
+                // 3MF requires every mesh to have at least one triangle.
+                let meshes = self.split_materials()?;
+                let used = || {
+                    meshes
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, m)| !m.triangles.is_empty())
+                };
+                for (k, mesh) in used() {
+                    write_3mf_object(&mut model, mesh, k + 2, Some(k))?;
+                }
+                writeln!(model, "</resources>")?;
+                writeln!(model, "<build>")?;
+                for (k, _) in used() {
+                    writeln!(model, r#"<item objectid="{}"/>"#, k + 2)?;
+                }
+                writeln!(model, "</build>")?;
+            }
+        }
+        writeln!(model, "</model>")?;
+
+        // Fixed timestamp (the default of 1980-01-01), for reproducible output
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(zip::DateTime::default())
+            .large_file(model.len() >= u32::MAX as usize);
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        for (name, data) in [
+            ("[Content_Types].xml", CONTENT_TYPES.as_bytes()),
+            ("_rels/.rels", RELS.as_bytes()),
+            ("3D/3dmodel.model", &model),
+        ] {
+            zip.start_file(name, options)?;
+            zip.write_all(data)?;
+        }
+        out.write_all(&zip.finish()?.into_inner())?;
+        Ok(())
+    }
+
+    /// Writes a binary glTF 2.0 (GLB) file to the given output
+    ///
+    /// The file contains a single mesh with indexed triangles, and a `NORMAL`
+    /// attribute if the mesh has [per-vertex normals](Self::normals).
+    ///
+    /// glTF forbids empty buffer views, so this returns [`Error::EmptyMesh`]
+    /// if the mesh has no triangles.
+    ///
+    /// [`Error::EmptyMesh`]: crate::Error::EmptyMesh
+    pub fn write_glb<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let normals = self.checked_normals()?;
+        if self.triangles.is_empty() {
+            return Err(crate::Error::EmptyMesh);
+        }
+        checked_count::<u32>(self.vertices.len(), "glTF")?;
+
+        // Binary buffer, containing positions, normals, then indices
+        let mut bin = vec![];
+        let mut min = nalgebra::Vector3::repeat(f32::INFINITY);
+        let mut max = nalgebra::Vector3::repeat(f32::NEG_INFINITY);
+        for v in &self.vertices {
+            min = min.inf(v);
+            max = max.sup(v);
+            for p in v {
+                bin.extend(p.to_le_bytes());
+            }
+        }
+        let positions_len = bin.len();
+        for n in normals.into_iter().flatten() {
+            for p in n {
+                bin.extend(p.to_le_bytes());
+            }
+        }
+        let normals_len = bin.len() - positions_len;
+        for t in &self.triangles {
+            for v in t {
+                bin.extend((*v as u32).to_le_bytes());
+            }
+        }
+        let indices_len = bin.len() - positions_len - normals_len;
+
+        // glTF requires min and max bounds for positions
+        let bounds = if self.vertices.is_empty() {
+            String::new()
+        } else {
+            format!(
+                r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                min.x, min.y, min.z, max.x, max.y, max.z
+            )
+        };
+        // Accessors and buffer views share indices; the normal accessor is
+        // only present if the mesh has normals.
+        let mut attributes = r#""POSITION":0"#.to_owned();
+        let mut accessors = format!(
+            r#"{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3"{}}}"#,
+            self.vertices.len(),
+            bounds,
+        );
+        let mut views = format!(
+            r#"{{"buffer":0,"byteOffset":0,"byteLength":{positions_len},"target":34962}}"#,
+        );
+        if normals.is_some() {
+            attributes += r#","NORMAL":1"#;
+            accessors += &format!(
+                r#",{{"bufferView":1,"componentType":5126,"count":{},"type":"VEC3"}}"#,
+                self.vertices.len(),
+            );
+            views += &format!(
+                r#",{{"buffer":0,"byteOffset":{positions_len},"byteLength":{normals_len},"target":34962}}"#,
+            );
+        }
+        let index = if normals.is_some() { 2 } else { 1 };
+        accessors += &format!(
+            r#",{{"bufferView":{index},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            self.triangles.len() * 3,
+        );
+        views += &format!(
+            r#",{{"buffer":0,"byteOffset":{},"byteLength":{indices_len},"target":34963}}"#,
+            positions_len + normals_len,
+        );
+        let json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"Fidget"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"#,
+                r#""nodes":[{{"mesh":0}}],"#,
+                r#""meshes":[{{"primitives":[{{"#,
+                r#""attributes":{{{}}},"indices":{},"mode":4"#,
+                r#"}}]}}],"#,
+                r#""accessors":[{}],"bufferViews":[{}],"#,
+                r#""buffers":[{{"byteLength":{}}}]}}"#,
+            ),
+            attributes,
+            index,
+            accessors,
+            views,
+            bin.len(),
+        );
+
+        // Chunks must be 4-byte aligned, padded with spaces (JSON) or zeros
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        // The total length bounds both chunk lengths
+        let total: u32 =
+            checked_count(12 + 8 + json.len() + 8 + bin.len(), "glTF")?;
+        out.write_all(b"glTF")?;
+        out.write_all(&2u32.to_le_bytes())?;
+        out.write_all(&total.to_le_bytes())?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(b"JSON")?;
+        out.write_all(&json)?;
+        out.write_all(&(bin.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&bin)?;
+        Ok(())
+    }
+
+    /// Returns the mesh's normals, checking that there's one per vertex
+    fn checked_normals(
+        &self,
+    ) -> Result<Option<&[nalgebra::Vector3<f32>]>, crate::Error> {
+        match &self.normals {
+            Some(n) if n.len() != self.vertices.len() => Err(
+                crate::Error::BadAttributeCount(n.len(), self.vertices.len()),
+            ),
+            n => Ok(n.as_deref()),
+        }
+    }
+
+    /// Returns the normal of a triangle for facet-based formats
+    ///
+    /// This is the average of the vertex normals (if present), falling back
+    /// to the triangle's geometric normal otherwise.
+    fn facet_normal(
+        &self,
+        t: &nalgebra::Vector3<usize>,
+        normals: Option<&[nalgebra::Vector3<f32>]>,
+    ) -> nalgebra::Vector3<f32> {
+        let face = self.face_normal(t);
+        match normals {
+            Some(n) => (n[t.x] + n[t.y] + n[t.z])
+                .try_normalize(0.0)
+                .unwrap_or(face),
+            None => face,
+        }
+    }
+
+    /// Returns the (unnormalized) normal of a triangle
+    ///
+    /// Not the _best_ way to calculate a normal, but good enough
+    fn face_normal(
+        &self,
+        t: &nalgebra::Vector3<usize>,
+    ) -> nalgebra::Vector3<f32> {
+        let a = self.vertices[t.x];
+        let b = self.vertices[t.y];
+        let c = self.vertices[t.z];
+        (b - a).cross(&(c - a))
+    }
+}
+
+/// Writes a single 3MF mesh object to the given model
+///
+/// If `material` is present, the object uses that index into the base
+/// material group with id 1.
+#[cfg(feature = "3mf")]
+fn write_3mf_object(
+    model: &mut Vec<u8>,
+    mesh: &Mesh,
+    id: usize,
+    material: Option<usize>,
+) -> Result<(), crate::Error> {
+    use std::io::Write;
+    match material {
+        Some(k) => writeln!(
+            model,
+            r#"<object id="{id}" type="model" pid="1" pindex="{k}"><mesh>"#
+        )?,
+        None => writeln!(model, r#"<object id="{id}" type="model"><mesh>"#)?,
+    }
+    writeln!(model, "<vertices>")?;
+    for v in &mesh.vertices {
+        writeln!(model, r#"<vertex x="{}" y="{}" z="{}"/>"#, v.x, v.y, v.z)?;
+    }
+    writeln!(model, "</vertices>")?;
+    writeln!(model, "<triangles>")?;
+    for t in &mesh.triangles {
+        writeln!(
+            model,
+            r#"<triangle v1="{}" v2="{}" v3="{}"/>"#,
+            t.x, t.y, t.z
+        )?;
+    }
+    writeln!(model, "</triangles>")?;
+    writeln!(model, "</mesh></object>")?;
+    Ok(())
+}
+
+/// Converts a count or size for an output format, checking for overflow
+fn checked_count<T: TryFrom<usize>>(
+    n: usize,
+    format: &'static str,
+) -> Result<T, crate::Error> {
+    T::try_from(n).map_err(|_| crate::Error::MeshTooLarge(format))
+}
+
+impl Contours {
+    /// Writes an SVG file to the given output
+    ///
+    /// All polylines are written as a single filled path (with the `nonzero`
+    /// fill rule, so holes are preserved).  The Y axis is flipped, so that the
+    /// image isn't upside-down.
+    pub fn write_svg<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let mut min = nalgebra::Vector2::repeat(f32::INFINITY);
+        let mut max = nalgebra::Vector2::repeat(f32::NEG_INFINITY);
+        for p in self.polylines.iter().flatten() {
+            min = min.inf(p);
+            max = max.sup(p);
+        }
+        if self.polylines.is_empty() {
+            min = nalgebra::Vector2::zeros();
+            max = nalgebra::Vector2::zeros();
+        }
+        let size = max - min;
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+            min.x, -max.y, size.x, size.y
+        )?;
+        write!(out, r#"<path fill-rule="nonzero" d=""#)?;
+        for line in &self.polylines {
+            for (i, p) in line.iter().enumerate() {
+                let c = if i == 0 { 'M' } else { 'L' };
+                write!(out, "{c}{} {} ", p.x, -p.y)?;
+            }
+            write!(out, "Z ")?;
+        }
+        writeln!(out, r#""/>"#)?;
+        writeln!(out, "</svg>")?;
+        Ok(())
+    }
+
+    /// Writes an ASCII DXF file to the given output
+    ///
+    /// Each polyline is written as a closed `LWPOLYLINE` entity on layer `0`.
+    pub fn write_dxf<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        // LWPOLYLINE entities require AutoCAD 2000 (AC1015) or later
+        write!(out, "0\nSECTION\n2\nHEADER\n")?;
+        write!(out, "9\n$ACADVER\n1\nAC1015\n")?;
+        write!(out, "0\nENDSEC\n")?;
+        write!(out, "0\nSECTION\n2\nENTITIES\n")?;
+        for line in &self.polylines {
+            write!(out, "0\nLWPOLYLINE\n")?;
+            write!(out, "100\nAcDbEntity\n8\n0\n100\nAcDbPolyline\n")?;
+            write!(out, "90\n{}\n70\n1\n", line.len())?;
+            for p in line {
+                write!(out, "10\n{}\n20\n{}\n", p.x, p.y)?;
+            }
+        }
+        write!(out, "0\nENDSEC\n0\nEOF\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        mesh::{octree::test::sphere, Octree, Settings},
+        vm::VmShape,
+    };
+    use nalgebra::Vector3;
+
+    fn sphere_mesh() -> Mesh {
+        let shape = VmShape::from(sphere([0.0; 3], 0.6));
+        let settings = Settings {
+            depth: 4,
+            ..Default::default()
+        };
+        let mesh = Octree::build(&shape, settings).walk_dual(settings);
+        assert!(!mesh.triangles.is_empty());
+        mesh
+    }
+
+    fn u32_at(data: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes(data[i..i + 4].try_into().unwrap())
+    }
+
+    fn f32_at(data: &[u8], i: usize) -> f32 {
+        f32::from_le_bytes(data[i..i + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_stl() {
+        let mesh = sphere_mesh();
+        let mut out = vec![];
+        mesh.write_stl(&mut out).unwrap();
+        let n = u32_at(&out, 80) as usize;
+        assert_eq!(n, mesh.triangles.len());
+        assert_eq!(out.len(), 84 + n * 50);
+        let t = mesh.triangles[0];
+        assert_eq!(f32_at(&out, 84 + 12), mesh.vertices[t.x].x);
+
+        let mut out = vec![];
+        mesh.write_stl_ascii(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut facets = 0;
+        let mut verts = vec![];
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("facet") => {
+                    let n: Vec<f32> =
+                        words.skip(1).map(|w| w.parse().unwrap()).collect();
+                    let n = Vector3::new(n[0], n[1], n[2]);
+                    assert!((n.norm() - 1.0).abs() < 1e-3);
+                    facets += 1;
+                }
+                Some("vertex") => {
+                    let v: Vec<f32> =
+                        words.map(|w| w.parse().unwrap()).collect();
+                    verts.push(Vector3::new(v[0], v[1], v[2]));
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(facets, mesh.triangles.len());
+        let expected: Vec<_> = mesh
+            .triangles
+            .iter()
+            .flat_map(|t| t.iter().map(|i| mesh.vertices[*i]))
+            .collect();
+        assert_eq!(verts, expected);
+        assert!(text.starts_with("solid"));
+        assert!(text.trim_end().ends_with("endsolid fidget"));
+    }
+
+    #[test]
+    fn test_obj() {
+        let mesh = sphere_mesh();
+        let mut out = vec![];
+        mesh.write_obj(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut vertices = vec![];
+        let mut triangles = vec![];
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => {
+                    let v: Vec<f32> =
+                        words.map(|w| w.parse().unwrap()).collect();
+                    vertices.push(Vector3::new(v[0], v[1], v[2]));
+                }
+                Some("f") => {
+                    let t: Vec<usize> = words
+                        .map(|w| w.parse::<usize>().unwrap() - 1)
+                        .collect();
+                    triangles.push(Vector3::new(t[0], t[1], t[2]));
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(vertices, mesh.vertices);
+        assert_eq!(triangles, mesh.triangles);
+    }
+
+    /// Parses a binary PLY file, returning its header and vertex / face data
+    fn parse_ply(data: &[u8]) -> (Vec<String>, Vec<Vec<f32>>, Vec<Vec<u32>>) {
+        let end = b"end_header\n";
+        let split =
+            data.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header: Vec<String> = std::str::from_utf8(&data[..split])
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(header[0], "ply");
+        assert_eq!(header[1], "format binary_little_endian 1.0");
+
+        let count = |name: &str| -> usize {
+            let prefix = format!("element {name} ");
+            header
+                .iter()
+                .find_map(|h| h.strip_prefix(&prefix))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let floats = header.iter().filter(|h| h.contains(" float ")).count();
+        let bytes = header.iter().filter(|h| h.contains(" uchar ")).count() - 1; // the face list count is also a uchar
+
+        let mut pos = split;
+        let mut verts = vec![];
+        for _ in 0..count("vertex") {
+            let mut v = vec![];
+            for _ in 0..floats {
+                v.push(f32_at(data, pos));
+                pos += 4;
+            }
+            for _ in 0..bytes {
+                v.push(data[pos] as f32);
+                pos += 1;
+            }
+            verts.push(v);
+        }
+        let mut faces = vec![];
+        for _ in 0..count("face") {
+            let n = data[pos] as usize;
+            pos += 1;
+            faces.push((0..n).map(|i| u32_at(data, pos + i * 4)).collect());
+            pos += n * 4;
+        }
+        assert_eq!(pos, data.len());
+        (header, verts, faces)
+    }
+
+    #[test]
+    fn test_ply() {
+        let mesh = sphere_mesh();
+        let mut out = vec![];
+        mesh.write_ply(&mut out).unwrap();
+        let (_, verts, faces) = parse_ply(&out);
+        assert_eq!(verts.len(), mesh.vertices.len());
+        assert_eq!(faces.len(), mesh.triangles.len());
+        for (v, m) in verts.iter().zip(&mesh.vertices) {
+            assert_eq!(v.as_slice(), m.as_slice());
+        }
+        for (f, t) in faces.iter().zip(&mesh.triangles) {
+            let t: Vec<u32> = t.iter().map(|i| *i as u32).collect();
+            assert_eq!(*f, t);
+        }
+
+        // With normals and colors
+        let normals: Vec<_> =
+            mesh.vertices.iter().map(|v| v.normalize()).collect();
+        let colors: Vec<_> =
+            (0..mesh.vertices.len()).map(|i| [i as u8, 1, 2]).collect();
+        let mut out = vec![];
+        mesh.write_ply_with(&mut out, Some(&normals), Some(&colors))
+            .unwrap();
+        let (header, verts, faces) = parse_ply(&out);
+        assert!(header.iter().any(|h| h == "property float nx"));
+        assert!(header.iter().any(|h| h == "property uchar red"));
+        assert_eq!(faces.len(), mesh.triangles.len());
+        for (i, v) in verts.iter().enumerate() {
+            assert_eq!(v.len(), 9);
+            assert_eq!(v[3..6], *normals[i].as_slice());
+            assert_eq!(v[6..], [(i as u8) as f32, 1.0, 2.0]);
+        }
+
+        // Mismatched attributes are rejected
+        let mut out = vec![];
+        let err = mesh.write_ply_with(&mut out, Some(&normals[1..]), None);
+        assert!(matches!(err, Err(crate::Error::BadAttributeCount(..))));
+        assert!(out.is_empty());
+    }
+
+    /// Parses a ZIP archive, returning names and data
+    #[cfg(feature = "3mf")]
+    fn parse_zip(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        use std::io::Read;
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut f = zip.by_index(i).unwrap();
+                let mut body = vec![];
+                f.read_to_end(&mut body).unwrap();
+                (f.name().to_owned(), body)
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "3mf")]
+    #[test]
+    fn test_3mf() {
+        let mesh = sphere_mesh();
+        let mut out = vec![];
+        mesh.write_3mf(&mut out).unwrap();
+
+        // Output is reproducible, i.e. without a current timestamp
+        let mut again = vec![];
+        mesh.write_3mf(&mut again).unwrap();
+        assert_eq!(out, again);
+
+        let files = parse_zip(&out);
+        let names: Vec<_> = files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            ["[Content_Types].xml", "_rels/.rels", "3D/3dmodel.model"]
+        );
+        let model = std::str::from_utf8(&files[2].1).unwrap();
+        assert_eq!(model.matches("<vertex ").count(), mesh.vertices.len());
+        assert_eq!(model.matches("<triangle ").count(), mesh.triangles.len());
+        let t = mesh.triangles[0];
+        assert!(model.contains(&format!(
+            r#"<triangle v1="{}" v2="{}" v3="{}"/>"#,
+            t.x, t.y, t.z
+        )));
+    }
+
+    /// Returns the sphere mesh, with alternating materials on its triangles
+    fn material_mesh() -> Mesh {
+        let mut mesh = sphere_mesh();
+        mesh.materials = Some(crate::mesh::MeshMaterials {
+            triangles: (0..mesh.triangles.len() as u32)
+                .map(|i| i % 2)
+                .collect(),
+            colors: vec![[255, 0, 16], [0, 128, 255]],
+        });
+        mesh
+    }
+
+    #[test]
+    fn test_ply_materials() {
+        let mut mesh = material_mesh();
+        let mut out = vec![];
+        mesh.write_ply(&mut out).unwrap();
+
+        let end = b"end_header\n";
+        let split =
+            out.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&out[..split]).unwrap();
+        assert!(header.contains(
+            "property list uchar uint vertex_indices\n\
+             property uint material\n\
+             property uchar red\n\
+             property uchar green\n\
+             property uchar blue\n"
+        ));
+
+        // Each face is a count, three indices, a material, and a color
+        let faces = &out[split + mesh.vertices.len() * 12..];
+        assert_eq!(faces.len(), mesh.triangles.len() * 20);
+        for (i, f) in faces.chunks(20).enumerate() {
+            assert_eq!(f[0], 3);
+            assert_eq!(u32_at(f, 1) as usize, mesh.triangles[i].x);
+            let k = u32_at(f, 13);
+            assert_eq!(k, i as u32 % 2);
+            assert_eq!(f[17..], [[255, 0, 16], [0, 128, 255]][k as usize]);
+        }
+
+        // Bad materials are rejected
+        mesh.materials.as_mut().unwrap().triangles.pop();
+        let mut out = vec![];
+        let err = mesh.write_ply(&mut out);
+        assert!(matches!(err, Err(crate::Error::BadMaterialCount(..))));
+        assert!(out.is_empty());
+    }
+
+    #[cfg(feature = "3mf")]
+    #[test]
+    fn test_3mf_materials() {
+        let mut mesh = material_mesh();
+        let mut out = vec![];
+        mesh.write_3mf(&mut out).unwrap();
+        let files = parse_zip(&out);
+        let model = std::str::from_utf8(&files[2].1).unwrap();
+        assert!(model.contains(r#"<basematerials id="1">"#));
+        assert!(model.contains(r##"displaycolor="#FF0010""##));
+        assert!(model.contains(r##"displaycolor="#0080FF""##));
+        assert!(model
+            .contains(r#"<object id="2" type="model" pid="1" pindex="0">"#));
+        assert!(model
+            .contains(r#"<object id="3" type="model" pid="1" pindex="1">"#));
+        assert!(model.contains(r#"<item objectid="2"/>"#));
+        assert!(model.contains(r#"<item objectid="3"/>"#));
+        assert_eq!(model.matches("<triangle ").count(), mesh.triangles.len());
+
+        // Unused materials are listed, but don't get an (empty) object
+        mesh.materials.as_mut().unwrap().colors.push([0; 3]);
+        let mut out = vec![];
+        mesh.write_3mf(&mut out).unwrap();
+        let files = parse_zip(&out);
+        let model = std::str::from_utf8(&files[2].1).unwrap();
+        assert!(model.contains(r##"displaycolor="#000000""##));
+        assert_eq!(model.matches("<object ").count(), 2);
+        assert_eq!(model.matches("<item ").count(), 2);
+        assert!(!model.contains(r#"objectid="4""#));
+        mesh.materials.as_mut().unwrap().colors.pop();
+
+        // Bad material indices are rejected
+        mesh.materials.as_mut().unwrap().triangles[0] = 2;
+        let mut out = vec![];
+        let err = mesh.write_3mf(&mut out);
+        assert!(matches!(err, Err(crate::Error::BadMaterialIndex(2, 2))));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_glb() {
+        let mesh = sphere_mesh();
+        let mut out = vec![];
+        mesh.write_glb(&mut out).unwrap();
+        assert_eq!(&out[0..4], b"glTF");
+        assert_eq!(u32_at(&out, 4), 2);
+        assert_eq!(u32_at(&out, 8) as usize, out.len());
+
+        let json_len = u32_at(&out, 12) as usize;
+        assert_eq!(&out[16..20], b"JSON");
+        let json = std::str::from_utf8(&out[20..20 + json_len]).unwrap();
+        let counts: Vec<usize> = json
+            .split(r#""count":"#)
+            .skip(1)
+            .map(|s| s.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(counts, [mesh.vertices.len(), mesh.triangles.len() * 3]);
+
+        let bin = &out[20 + json_len..];
+        let bin_len = u32_at(bin, 0) as usize;
+        assert_eq!(&bin[4..8], b"BIN\0");
+        let bin = &bin[8..8 + bin_len];
+        for (i, v) in mesh.vertices.iter().enumerate() {
+            for j in 0..3 {
+                assert_eq!(f32_at(bin, (i * 3 + j) * 4), v[j]);
+            }
+        }
+        let offset = mesh.vertices.len() * 12;
+        for (i, t) in mesh.triangles.iter().enumerate() {
+            for j in 0..3 {
+                let v = u32_at(bin, offset + (i * 3 + j) * 4);
+                assert_eq!(v as usize, t[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_glb_empty() {
+        let mut out = vec![];
+        let err = Mesh::default().write_glb(&mut out);
+        assert!(matches!(err, Err(crate::Error::EmptyMesh)));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_normals() {
+        let mut mesh = sphere_mesh();
+        let shape = VmShape::from(sphere([0.0; 3], 0.6));
+        mesh.compute_normals(&shape).unwrap();
+        let normals = mesh.normals.clone().unwrap();
+        assert_eq!(normals.len(), mesh.vertices.len());
+        for (n, v) in normals.iter().zip(&mesh.vertices) {
+            assert!((n.norm() - 1.0).abs() < 1e-4);
+            assert!(n.dot(&v.normalize()) > 0.999, "bad normal {n} at {v}");
+        }
+
+        // Facet normals are the average of vertex normals
+        let mut out = vec![];
+        mesh.write_stl(&mut out).unwrap();
+        let t = mesh.triangles[0];
+        let expected = (normals[t.x] + normals[t.y] + normals[t.z]).normalize();
+        for j in 0..3 {
+            assert_eq!(f32_at(&out, 84 + j * 4), expected[j]);
+        }
+
+        let mut out = vec![];
+        mesh.write_obj(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.matches("\nvn ").count(), mesh.vertices.len());
+        let (a, b, c) = (t.x + 1, t.y + 1, t.z + 1);
+        assert!(text.contains(&format!("f {a}//{a} {b}//{b} {c}//{c}\n")));
+
+        let mut out = vec![];
+        mesh.write_ply(&mut out).unwrap();
+        let (header, verts, _) = parse_ply(&out);
+        assert!(header.iter().any(|h| h == "property float nx"));
+        for (v, n) in verts.iter().zip(&normals) {
+            assert_eq!(v[3..6], *n.as_slice());
+        }
+
+        let mut out = vec![];
+        mesh.write_glb(&mut out).unwrap();
+        let json_len = u32_at(&out, 12) as usize;
+        let json = std::str::from_utf8(&out[20..20 + json_len]).unwrap();
+        assert!(json.contains(r#""NORMAL":1"#));
+        assert!(json.contains(r#""indices":2"#));
+        let bin = &out[20 + json_len + 8..];
+        let offset = mesh.vertices.len() * 12;
+        for (i, n) in normals.iter().enumerate() {
+            for j in 0..3 {
+                assert_eq!(f32_at(bin, offset + (i * 3 + j) * 4), n[j]);
+            }
+        }
+
+        // Normals must match the vertex count
+        mesh.normals.as_mut().unwrap().pop();
+        let mut out = vec![];
+        let err = mesh.write_obj(&mut out);
+        assert!(matches!(err, Err(crate::Error::BadAttributeCount(..))));
+    }
+
+    #[test]
+    fn test_normals_with_vars() {
+        use crate::{shape::ShapeVars, var::Var};
+        let (x, y, z) = Tree::axes();
+        let r = Var::new();
+        let sphere =
+            (x.square() + y.square() + z.square()).sqrt() - Tree::from(r);
+        let shape = VmShape::from(sphere);
+        let mut vars = ShapeVars::new();
+        vars.insert(r.index().unwrap(), 0.6);
+
+        let mut mesh = sphere_mesh();
+        assert!(mesh.compute_normals(&shape).is_err());
+        assert!(mesh.normals.is_none());
+        mesh.compute_normals_with_vars(&shape, &vars).unwrap();
+        for (n, v) in mesh.normals.unwrap().iter().zip(&mesh.vertices) {
+            assert!(n.dot(&v.normalize()) > 0.999);
+        }
+    }
+
+    #[test]
+    fn test_normals_degenerate() {
+        // A stepped field has no gradient anywhere
+        let (x, y, z) = Tree::axes();
+        let shape = VmShape::from((x + y + z).floor());
+        let mut mesh = Mesh {
+            vertices: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(2.0, 2.0, 2.0),
+                Vector3::new(3.0, 3.0, 3.0),
+                Vector3::new(4.0, 4.0, 4.0),
+                Vector3::new(5.0, 5.0, 5.0),
+            ],
+            triangles: vec![
+                Vector3::new(0, 1, 2),
+                Vector3::new(3, 4, 5), // zero area
+            ],
+            ..Default::default()
+        };
+        mesh.compute_normals(&shape).unwrap();
+        let normals = mesh.normals.unwrap();
+        for n in &normals[..3] {
+            assert_eq!(*n, Vector3::y());
+        }
+        // Vertices with no usable faces fall back to +Z
+        for n in &normals[3..] {
+            assert_eq!(*n, Vector3::z());
+        }
+    }
 }
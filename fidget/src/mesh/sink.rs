@@ -0,0 +1,825 @@
+//! Streaming mesh output
+use super::{
+    cell::{Cell, CellData, CellIndex, CellVertex, Leaf},
+    dc::{self, DcBuilder},
+    frame::{Frame, XYZ, YZX, ZXY},
+    octree::{EvalGroup, OctreeBuilder},
+    types::{Corner, X, Y, Z},
+    Mesh, Octree, Settings,
+};
+use crate::{
+    eval::Function,
+    render::{RenderHints, ThreadCount},
+    shape::{Shape, ShapeVars},
+    Error,
+};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+/// Destination for a mesh which is streamed during octree construction
+///
+/// See [`Octree::build_with_vars_to`] for details on the order of callbacks.
+pub trait MeshSink {
+    /// Called once before any vertices or triangles are recorded
+    ///
+    /// The default implementation does nothing.
+    fn begin(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Records a vertex
+    ///
+    /// Vertices are numbered sequentially, starting from 0.  Each vertex is
+    /// recorded before the first triangle which uses it.
+    fn vertex(&mut self, pos: nalgebra::Vector3<f32>) -> Result<(), Error>;
+
+    /// Records a triangle
+    ///
+    /// `t` contains indices of vertices which have already been recorded;
+    /// `pos` contains their positions, for sinks which don't keep vertices.
+    fn triangle(
+        &mut self,
+        t: nalgebra::Vector3<usize>,
+        pos: [nalgebra::Vector3<f32>; 3],
+    ) -> Result<(), Error>;
+
+    /// Called once after all vertices and triangles are recorded
+    ///
+    /// The default implementation does nothing.
+    fn finish(
+        &mut self,
+        vertex_count: usize,
+        triangle_count: usize,
+    ) -> Result<(), Error> {
+        let _ = (vertex_count, triangle_count);
+        Ok(())
+    }
+}
+
+impl MeshSink for Mesh {
+    fn vertex(&mut self, pos: nalgebra::Vector3<f32>) -> Result<(), Error> {
+        self.vertices.push(pos);
+        Ok(())
+    }
+    fn triangle(
+        &mut self,
+        t: nalgebra::Vector3<usize>,
+        _pos: [nalgebra::Vector3<f32>; 3],
+    ) -> Result<(), Error> {
+        self.triangles.push(t);
+        Ok(())
+    }
+}
+
+/// Streaming writer for binary STL files
+///
+/// The output uses the same format as [`Mesh::write_stl`] (for a mesh without
+/// normals), but vertices are never stored.  The header's triangle count isn't
+/// known until the mesh is finished, so it's written as a placeholder and
+/// patched at the end, which is why `W` must be seekable.  Output is written
+/// in many small pieces, so `W` should typically be buffered.
+pub struct StlWriter<W> {
+    out: W,
+
+    /// Position of the header within `out`
+    start: u64,
+}
+
+impl<W: Write + Seek> StlWriter<W> {
+    /// Builds a new STL writer
+    pub fn new(out: W) -> Self {
+        Self { out, start: 0 }
+    }
+
+    /// Returns the inner writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> MeshSink for StlWriter<W> {
+    fn begin(&mut self) -> Result<(), Error> {
+        const HEADER: &[u8] = b"This is a binary STL file exported by Fidget";
+        static_assertions::const_assert!(HEADER.len() <= 80);
+        self.start = self.out.stream_position()?;
+        self.out.write_all(HEADER)?;
+        self.out.write_all(&[0u8; 80 - HEADER.len()])?;
+        self.out.write_all(&0u32.to_le_bytes())?; // patched in `finish`
+        Ok(())
+    }
+    fn vertex(&mut self, _pos: nalgebra::Vector3<f32>) -> Result<(), Error> {
+        Ok(())
+    }
+    fn triangle(
+        &mut self,
+        _t: nalgebra::Vector3<usize>,
+        [a, b, c]: [nalgebra::Vector3<f32>; 3],
+    ) -> Result<(), Error> {
+        let n = (b - a).cross(&(c - a));
+        for v in [n, a, b, c] {
+            for p in &v {
+                self.out.write_all(&p.to_le_bytes())?;
+            }
+        }
+        self.out.write_all(&[0u8; std::mem::size_of::<u16>()])?; // attributes
+        Ok(())
+    }
+    fn finish(&mut self, _verts: usize, tris: usize) -> Result<(), Error> {
+        let tris =
+            u32::try_from(tris).map_err(|_| Error::MeshTooLarge("STL"))?;
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.start + 80))?;
+        self.out.write_all(&tris.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Streaming writer for binary (little-endian) PLY files
+///
+/// The output uses the same format as [`Mesh::write_ply`] (for a mesh without
+/// normals), except that element counts in the header are padded with zeros
+/// to a fixed width.  The header is written with placeholder counts and
+/// patched at the end, so `W` must be seekable.
+///
+/// PLY stores every vertex before any face, but vertices and triangles are
+/// produced together; vertices are written to `W` as they arrive, and faces
+/// are spooled to a second stream `T` (e.g. a temporary file), then copied to
+/// the end of the output once the mesh is finished.  Faces are buffered
+/// before being written to `T`, but vertices are written to `W` in many small
+/// pieces, so `W` should typically be buffered.
+pub struct PlyWriter<W, T: Write> {
+    out: W,
+    spool: std::io::BufWriter<T>,
+
+    /// Positions of the header within `out` and of the faces within `spool`
+    start: (u64, u64),
+}
+
+impl<W, T> PlyWriter<W, T>
+where
+    W: Write + Seek,
+    T: std::io::Read + Write + Seek,
+{
+    /// Builds a new PLY writer, which spools faces to `spool`
+    pub fn new(out: W, spool: T) -> Self {
+        Self {
+            out,
+            spool: std::io::BufWriter::new(spool),
+            start: (0, 0),
+        }
+    }
+
+    /// Returns the inner writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Writes the header, with counts padded to a fixed width
+    fn header(&mut self, verts: usize, tris: usize) -> Result<(), Error> {
+        let out = &mut self.out;
+        writeln!(out, "ply")?;
+        writeln!(out, "format binary_little_endian 1.0")?;
+        writeln!(out, "comment exported by Fidget")?;
+        writeln!(out, "element vertex {verts:020}")?;
+        for p in ["x", "y", "z"] {
+            writeln!(out, "property float {p}")?;
+        }
+        writeln!(out, "element face {tris:020}")?;
+        writeln!(out, "property list uchar uint vertex_indices")?;
+        writeln!(out, "end_header")?;
+        Ok(())
+    }
+}
+
+impl<W, T> MeshSink for PlyWriter<W, T>
+where
+    W: Write + Seek,
+    T: std::io::Read + Write + Seek,
+{
+    fn begin(&mut self) -> Result<(), Error> {
+        self.start =
+            (self.out.stream_position()?, self.spool.stream_position()?);
+        self.header(0, 0) // patched in `finish`
+    }
+    fn vertex(&mut self, pos: nalgebra::Vector3<f32>) -> Result<(), Error> {
+        for p in &pos {
+            self.out.write_all(&p.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    fn triangle(
+        &mut self,
+        t: nalgebra::Vector3<usize>,
+        _pos: [nalgebra::Vector3<f32>; 3],
+    ) -> Result<(), Error> {
+        self.spool.write_all(&[3])?;
+        for v in &t {
+            // Vertex indices are written as u32
+            let v =
+                u32::try_from(*v).map_err(|_| Error::MeshTooLarge("PLY"))?;
+            self.spool.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self, verts: usize, tris: usize) -> Result<(), Error> {
+        let (start, spool_start) = self.start;
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(start))?;
+        self.header(verts, tris)?;
+        self.out.seek(SeekFrom::Start(end))?;
+
+        // Each face is a count byte and three u32 indices
+        self.spool.flush()?;
+        let spool = self.spool.get_mut();
+        spool.seek(SeekFrom::Start(spool_start))?;
+        let len = tris as u64 * 13;
+        let copied = std::io::copy(&mut spool.take(len), &mut self.out)?;
+        if copied != len {
+            return Err(std::io::Error::from(
+                std::io::ErrorKind::UnexpectedEof,
+            )
+            .into());
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Octree {
+    /// Builds a mesh and streams it to a sink, without storing the octree
+    ///
+    /// See [`build_with_vars_to`](Self::build_with_vars_to) for details.
+    pub fn build_to<F: Function + RenderHints + Clone, S: MeshSink>(
+        shape: &Shape<F>,
+        settings: Settings,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        Self::build_with_vars_to(shape, &ShapeVars::new(), settings, sink)
+    }
+
+    /// Builds a mesh with user-provided variables and streams it to a sink
+    ///
+    /// Unlike [`build_with_vars`](Self::build_with_vars) followed by
+    /// [`walk_dual`](Self::walk_dual), this never stores the whole octree or
+    /// a [`Mesh`] in memory.  The octree is built in blocks (subtrees rooted
+    /// at half of the maximum depth), one slab of blocks at a time along the Z
+    /// axis; triangles are emitted as soon as the blocks around them are
+    /// built, and each slab is dropped once the next slab is finished.  Vertex
+    /// deduplication only tracks vertices within those two slabs, so memory
+    /// use is bounded by the surface area of a slab rather than the size of
+    /// the mesh.
+    ///
+    /// Because blocks are built independently, cells are never collapsed
+    /// across block boundaries, so the mesh may be slightly finer than the
+    /// output of [`walk_dual`](Self::walk_dual).  Blocks within a slab are
+    /// built in parallel if `settings.threads` requests it; the output is
+    /// deterministic regardless of the thread count.
+    ///
+    /// The octree is only built once: the sink sees one call to
+    /// [`MeshSink::begin`], then every vertex (in order of first use) and
+    /// triangle as they're found, then one call to [`MeshSink::finish`] with
+    /// the total counts.  Each vertex is recorded before any triangle which
+    /// uses it.  If the sink returns an error, no further callbacks are made
+    /// and the error is returned.
+    pub fn build_with_vars_to<
+        F: Function + RenderHints + Clone,
+        S: MeshSink,
+    >(
+        shape: &Shape<F>,
+        vars: &ShapeVars<f32>,
+        settings: Settings,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        let block_depth = settings.max_depth() / 2;
+        let t = settings.world_to_model();
+        let shape = if t == nalgebra::Matrix4::identity() {
+            shape.clone()
+        } else {
+            shape.clone().apply_transform(t)
+        };
+        let eval = Arc::new(EvalGroup::new(shape));
+
+        sink.begin()?;
+        let (verts, tris) =
+            stream_blocks(&eval, vars, settings, block_depth, sink)?;
+        sink.finish(verts, tris)
+    }
+}
+
+/// Builds an octree block-by-block, walking its dual as blocks are built
+///
+/// Blocks are subtrees rooted at `block_depth`, so there are `n = 2^depth`
+/// blocks along each axis.  They're visited in order of `(z, y, x)` index;
+/// when a block is visited, every neighbor with a smaller index has already
+/// been built, so we can emit every triangle shared between them.  This
+/// decomposes the usual recursive walk over the top levels of the octree into
+/// the block's own cell, the faces between the block and its three lower
+/// neighbors, and the edges shared with the lower neighbors on each axis.
+///
+/// Returns the number of vertices and triangles in the mesh.
+fn stream_blocks<F: Function + RenderHints, S: MeshSink>(
+    eval: &Arc<EvalGroup<F>>,
+    vars: &ShapeVars<f32>,
+    settings: Settings,
+    block_depth: u8,
+    sink: &mut S,
+) -> Result<(usize, usize), Error> {
+    let n = 1usize << block_depth;
+    let t = settings.world_to_model();
+    let mut window = Window::new();
+    let mut builder = StreamBuilder {
+        sink,
+        map: vec![],
+        verts: 0,
+        triangles: 0,
+        result: Ok(()),
+    };
+
+    for z in 0..n {
+        let slab = build_slab(eval, vars, &settings, block_depth, z);
+        for mut block in slab {
+            if t != nalgebra::Matrix4::identity() {
+                for v in &mut block.verts {
+                    let p: nalgebra::Point3<f32> = v.pos.into();
+                    v.pos = t.transform_point(&p).coords;
+                }
+            }
+            window.push(block, &mut builder.map);
+        }
+
+        for y in 0..n {
+            for x in 0..n {
+                // Child `c` of a virtual parent cell, where this block is the
+                // last child (with neighbors on the lower side of each axis)
+                let child = |c: Corner| {
+                    let dx = !(c & X) as usize;
+                    let dy = !(c & Y) as usize;
+                    let dz = !(c & Z) as usize;
+                    let mut cell = block_cell(block_depth, x, y, z);
+                    cell.index = if x < dx || y < dy || z < dz {
+                        0 // empty placeholder
+                    } else {
+                        window.root(n, x - dx, y - dy, dz)
+                    };
+                    cell
+                };
+                let o = &window.octree;
+                let b = &mut builder;
+                let last = X | Y | Z;
+                b.cell(o, child(last));
+
+                // Faces with each lower neighbor
+                b.face::<XYZ>(o, child(Y | Z), child(last));
+                b.face::<YZX>(o, child(Z | X), child(last));
+                b.face::<ZXY>(o, child(X | Y), child(last));
+
+                // Edges along each axis, which meet at this block's lower
+                // corner (ordered as in `dc_cell`)
+                b.edge::<XYZ>(
+                    o,
+                    child(X.into()),
+                    child(X | Y),
+                    child(last),
+                    child(X | Z),
+                );
+                b.edge::<YZX>(
+                    o,
+                    child(Y.into()),
+                    child(Y | Z),
+                    child(last),
+                    child(Y | X),
+                );
+                b.edge::<ZXY>(
+                    o,
+                    child(Z.into()),
+                    child(Z | X),
+                    child(last),
+                    child(Z | Y),
+                );
+                std::mem::replace(&mut b.result, Ok(()))?;
+            }
+        }
+        window.next_slab(&mut builder.map);
+    }
+    Ok((builder.verts, builder.triangles))
+}
+
+/// Returns the cell index (with an arbitrary `index`) of the given block
+fn block_cell(depth: u8, x: usize, y: usize, z: usize) -> CellIndex {
+    let mut cell = CellIndex::default();
+    for i in (0..depth).rev() {
+        let bit = |v: usize| ((v >> i) & 1) as u8;
+        let c = Corner::new(bit(x) | (bit(y) << 1) | (bit(z) << 2));
+        cell = cell.child(0, c);
+    }
+    cell
+}
+
+/// Builds every block in a slab, returning them in `(y, x)` order
+fn build_slab<F: Function + RenderHints>(
+    eval: &Arc<EvalGroup<F>>,
+    vars: &ShapeVars<f32>,
+    settings: &Settings,
+    block_depth: u8,
+    z: usize,
+) -> Vec<Octree> {
+    let n = 1usize << block_depth;
+    let build = |i: usize| {
+        let mut cell = block_cell(block_depth, i % n, i / n, z);
+        cell.index = 0;
+        let mut out = OctreeBuilder::new().with_settings(settings.into());
+        out.recurse(eval, vars, cell, settings.max_depth());
+        Octree::from(out)
+    };
+    match settings.threads {
+        ThreadCount::One => (0..n * n).map(build).collect(),
+
+        #[cfg(not(target_arch = "wasm32"))]
+        ThreadCount::Many(threads) => {
+            let next = std::sync::atomic::AtomicUsize::new(0);
+            let mut blocks: Vec<_> = std::thread::scope(|s| {
+                let handles: Vec<_> = (0..threads.get())
+                    .map(|_| {
+                        s.spawn(|| {
+                            let mut out = vec![];
+                            loop {
+                                let i = next.fetch_add(
+                                    1,
+                                    std::sync::atomic::Ordering::Relaxed,
+                                );
+                                if i >= n * n {
+                                    break out;
+                                }
+                                out.push((i, build(i)));
+                            }
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|h| h.join().unwrap())
+                    .collect()
+            });
+            blocks.sort_unstable_by_key(|(i, _)| *i);
+            blocks.into_iter().map(|(_, b)| b).collect()
+        }
+    }
+}
+
+/// Octree containing blocks from the current and previous slabs
+///
+/// Block roots are stored in the same octree, so that the usual dual
+/// contouring functions can walk between them; cell 0 is an empty placeholder
+/// for blocks outside of the meshing region.
+struct Window {
+    octree: Octree,
+
+    /// Root cell index of each block, for the previous and current slabs
+    roots: [Vec<usize>; 2],
+
+    /// Index of the first cell and vertex of the current slab
+    start: (usize, usize),
+}
+
+impl Window {
+    fn new() -> Self {
+        let mut cells = vec![Cell::Invalid.into(); 8];
+        cells[0] = Cell::Empty.into();
+        Self {
+            octree: Octree {
+                cells,
+                verts: vec![],
+            },
+            roots: [vec![], vec![]],
+            start: (8, 0),
+        }
+    }
+
+    /// Returns the root cell index of a block in the previous (`dz = 1`) or
+    /// current (`dz = 0`) slab
+    fn root(&self, n: usize, x: usize, y: usize, dz: usize) -> usize {
+        self.roots[1 - dz][x + y * n]
+    }
+
+    /// Adds a block to the current slab
+    ///
+    /// `map` is extended in parallel with the octree's vertices.
+    fn push(&mut self, block: Octree, map: &mut Vec<usize>) {
+        let base = self.octree.cells.len();
+        let vbase = self.octree.verts.len();
+        self.octree
+            .cells
+            .extend(block.cells.into_iter().map(|c| offset(c, base, vbase)));
+        map.resize(vbase + block.verts.len(), usize::MAX);
+        self.octree.verts.extend(block.verts);
+        self.roots[1].push(base);
+    }
+
+    /// Drops the previous slab, making the current slab the previous one
+    fn next_slab(&mut self, map: &mut Vec<usize>) {
+        let (start, vstart) = self.start;
+        let shift = start - 8;
+        self.octree.cells.drain(8..start);
+        self.octree.verts.drain(..vstart);
+        map.drain(..vstart);
+        for c in &mut self.octree.cells[8..] {
+            *c = unshift(*c, shift, vstart);
+        }
+        let [prev, cur] = &mut self.roots;
+        *prev = std::mem::take(cur);
+        for r in prev.iter_mut() {
+            *r -= shift;
+        }
+        self.start = (self.octree.cells.len(), self.octree.verts.len());
+    }
+}
+
+/// Offsets the indices in a cell by the given amounts
+fn offset(c: CellData, cells: usize, verts: usize) -> CellData {
+    match c.into() {
+        Cell::Branch { index, thread } => Cell::Branch {
+            index: index + cells,
+            thread,
+        },
+        Cell::Leaf(Leaf { mask, index }) => Cell::Leaf(Leaf {
+            mask,
+            index: index + verts,
+        }),
+        c => c,
+    }
+    .into()
+}
+
+/// Reverses [`offset`]
+fn unshift(c: CellData, cells: usize, verts: usize) -> CellData {
+    match c.into() {
+        Cell::Branch { index, thread } => Cell::Branch {
+            index: index - cells,
+            thread,
+        },
+        Cell::Leaf(Leaf { mask, index }) => Cell::Leaf(Leaf {
+            mask,
+            index: index - verts,
+        }),
+        c => c,
+    }
+    .into()
+}
+
+/// Dual contouring builder for [`stream_blocks`]
+struct StreamBuilder<'a, S> {
+    sink: &'a mut S,
+
+    /// Map from window vertex indices to output vertex indices
+    ///
+    /// `usize::MAX` is used as a marker for an unmapped vertex
+    map: Vec<usize>,
+
+    /// Number of vertices and triangles found so far
+    verts: usize,
+    triangles: usize,
+
+    /// First error returned by the sink (if any)
+    result: Result<(), Error>,
+}
+
+impl<S: MeshSink> DcBuilder for StreamBuilder<'_, S> {
+    type VertexIndex = (usize, nalgebra::Vector3<f32>);
+
+    fn cell(&mut self, octree: &Octree, cell: CellIndex) {
+        dc::dc_cell(octree, cell, self);
+    }
+    fn face<F: Frame>(&mut self, octree: &Octree, a: CellIndex, b: CellIndex) {
+        dc::dc_face::<F, _>(octree, a, b, self)
+    }
+    fn edge<F: Frame>(
+        &mut self,
+        octree: &Octree,
+        a: CellIndex,
+        b: CellIndex,
+        c: CellIndex,
+        d: CellIndex,
+    ) {
+        dc::dc_edge::<F, _>(octree, a, b, c, d, self)
+    }
+    fn triangle(
+        &mut self,
+        (a, pa): Self::VertexIndex,
+        (b, pb): Self::VertexIndex,
+        (c, pc): Self::VertexIndex,
+    ) {
+        self.triangles += 1;
+        if self.result.is_ok() {
+            let t = nalgebra::Vector3::new(a, b, c);
+            self.result = self.sink.triangle(t, [pa, pb, pc]);
+        }
+    }
+    fn vertex(
+        &mut self,
+        v: usize,
+        _cell: CellIndex,
+        verts: &[CellVertex],
+    ) -> Self::VertexIndex {
+        let pos = verts[v].pos;
+        if self.map[v] == usize::MAX {
+            self.map[v] = self.verts;
+            self.verts += 1;
+            if self.result.is_ok() {
+                self.result = self.sink.vertex(pos);
+            }
+        }
+        (self.map[v], pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mesh::octree::test::sphere, render::View3, vm::VmShape};
+    use std::io::Cursor;
+
+    fn sphere_settings(threads: ThreadCount) -> (VmShape, Settings) {
+        let shape = VmShape::from(sphere([0.0; 3], 0.6));
+        let settings = Settings {
+            depth: 6,
+            threads,
+            view: View3::from_center_and_scale(
+                nalgebra::Vector3::new(0.1, 0.2, 0.3),
+                0.9,
+            ),
+            ..Default::default()
+        };
+        (shape, settings)
+    }
+
+    /// Returns a mesh's triangles as sorted position triples
+    fn triangle_positions(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+        let mut out: Vec<_> = mesh
+            .triangles
+            .iter()
+            .map(|t| {
+                let p = t.map(|v| mesh.vertices[v].map(f32::to_bits).into());
+                // Rotate so that the smallest vertex is first
+                let k = (0..3).min_by_key(|&k| p[k]).unwrap();
+                [p[k], p[(k + 1) % 3], p[(k + 2) % 3]]
+            })
+            .collect();
+        out.sort_unstable();
+        out
+    }
+
+    #[test]
+    fn stream_to_mesh() {
+        let mut prev: Option<Mesh> = None;
+        for threads in
+            [ThreadCount::One, ThreadCount::Many(4.try_into().unwrap())]
+        {
+            let (shape, settings) = sphere_settings(threads);
+            let mut streamed = Mesh::default();
+            Octree::build_to(&shape, settings, &mut streamed).unwrap();
+            let r = streamed.validate();
+            assert!(r.is_watertight() && r.is_oriented(), "{r:?}");
+
+            // Cells in this model don't collapse above the block depth, so
+            // we get the same triangles as a full build
+            let mesh = Octree::build(&shape, settings).walk_dual(settings);
+            assert_eq!(streamed.vertices.len(), mesh.vertices.len());
+            assert_eq!(
+                triangle_positions(&streamed),
+                triangle_positions(&mesh)
+            );
+
+            // The output doesn't depend on the thread count
+            if let Some(prev) = &prev {
+                assert_eq!(prev.vertices, streamed.vertices);
+                assert_eq!(prev.triangles, streamed.triangles);
+            }
+            prev = Some(streamed);
+        }
+    }
+
+    #[test]
+    fn stream_adaptive() {
+        let (ctx, root) = crate::Context::from_text(
+            include_str!("../../../models/bear.vm").as_bytes(),
+        )
+        .unwrap();
+        let bear = VmShape::new(&ctx, root).unwrap();
+
+        // Blocks limit cell collapsing, but the mesh is still watertight
+        let settings = Settings {
+            depth: 6,
+            min_depth: Some(2),
+            max_error: Some(1e-3),
+            ..Default::default()
+        };
+        let mut mesh = Mesh::default();
+        Octree::build_to(&bear, settings, &mut mesh).unwrap();
+        let r = mesh.validate();
+        assert!(r.is_watertight() && r.is_oriented(), "{r:?}");
+
+        // Regions (and their caps) match a full build
+        let settings = Settings {
+            region: Some(crate::mesh::MeshRegion::new(
+                nalgebra::Vector3::new(-1.0, -1.0, -0.5),
+                nalgebra::Vector3::new(1.0, 0.5, 0.5),
+                nalgebra::Vector3::new(64, 48, 32),
+            )),
+            cap: true,
+            ..Default::default()
+        };
+        let mut mesh = Mesh::default();
+        Octree::build_to(&bear, settings, &mut mesh).unwrap();
+        assert!(!mesh.triangles.is_empty());
+        let full = Octree::build(&bear, settings).walk_dual(settings);
+        assert_eq!(triangle_positions(&mesh), triangle_positions(&full));
+    }
+
+    #[test]
+    fn stream_stl() {
+        let (shape, settings) = sphere_settings(ThreadCount::One);
+        let mut mesh = Mesh::default();
+        Octree::build_to(&shape, settings, &mut mesh).unwrap();
+        let mut expected = vec![];
+        mesh.write_stl(&mut expected).unwrap();
+
+        let mut w = StlWriter::new(Cursor::new(vec![]));
+        Octree::build_to(&shape, settings, &mut w).unwrap();
+        assert_eq!(w.into_inner().into_inner(), expected);
+
+        // Triangle counts must fit in the header
+        let mut w = StlWriter::new(Cursor::new(vec![]));
+        w.begin().unwrap();
+        let err = w.finish(0, u32::MAX as usize + 1);
+        assert!(matches!(err, Err(Error::MeshTooLarge("STL"))));
+    }
+
+    #[test]
+    fn stream_ply() {
+        let (shape, settings) = sphere_settings(ThreadCount::One);
+        let mut mesh = Mesh::default();
+        Octree::build_to(&shape, settings, &mut mesh).unwrap();
+        let mut expected = vec![];
+        mesh.write_ply(&mut expected).unwrap();
+
+        // Writing after existing data leaves that data alone
+        let mut out = Cursor::new(b"prefix".to_vec());
+        out.set_position(6);
+        let mut spool = Cursor::new(b"spool".to_vec());
+        spool.set_position(5);
+        let mut w = PlyWriter::new(out, spool);
+        Octree::build_to(&shape, settings, &mut w).unwrap();
+        let out = w.into_inner().into_inner();
+        assert_eq!(&out[..6], b"prefix");
+
+        // The output matches `write_ply`, except for padded counts
+        let end = b"end_header\n";
+        let split = expected.windows(end.len()).position(|w| w == end).unwrap()
+            + end.len();
+        let (v, t) = (mesh.vertices.len(), mesh.triangles.len());
+        let header = std::str::from_utf8(&expected[..split])
+            .unwrap()
+            .replace(
+                &format!("element vertex {v}\n"),
+                &format!("element vertex {v:020}\n"),
+            )
+            .replace(
+                &format!("element face {t}\n"),
+                &format!("element face {t:020}\n"),
+            );
+        assert_eq!(
+            std::str::from_utf8(&out[6..6 + header.len()]),
+            Ok(&*header)
+        );
+        assert_eq!(&out[6 + header.len()..], &expected[split..]);
+    }
+
+    #[test]
+    fn stream_error() {
+        struct Fail(usize);
+        impl MeshSink for Fail {
+            fn vertex(
+                &mut self,
+                _pos: nalgebra::Vector3<f32>,
+            ) -> Result<(), Error> {
+                Ok(())
+            }
+            fn triangle(
+                &mut self,
+                _t: nalgebra::Vector3<usize>,
+                _pos: [nalgebra::Vector3<f32>; 3],
+            ) -> Result<(), Error> {
+                self.0 += 1;
+                Err(std::io::Error::other("sink is full").into())
+            }
+        }
+        let (shape, settings) = sphere_settings(ThreadCount::One);
+        let mut sink = Fail(0);
+        assert!(Octree::build_to(&shape, settings, &mut sink).is_err());
+        assert_eq!(sink.0, 1);
+    }
+}
@@ -0,0 +1,503 @@
+//! Tile caching for interactive 2D rendering
+use crate::{
+    eval::Function,
+    render::{
+        config::{copy_tile, crop_tile},
+        render2d::render_root_tiles,
+        ImageRect, ImageRenderConfig, ShadingMode,
+    },
+    shape::{Shape, ShapeVars},
+};
+use nalgebra::{Matrix2, Point2, Vector2};
+use std::collections::HashMap;
+
+/// Number of steps per pixel when quantizing sub-pixel offsets
+const SUBPIXEL_STEPS: f32 = 1024.0;
+
+/// Cache of rendered 2D tiles, for reuse between frames
+///
+/// Tiles are keyed by their bounds in model space.  When the view is panned by
+/// a whole number of pixels, most tiles in the new frame have the same bounds
+/// as tiles in the previous frame, so only the newly exposed region must be
+/// evaluated.  Zooming back to a previously rendered scale (e.g. stepping in
+/// and out by integer factors) also reuses tiles from that scale.
+///
+/// To make this possible, root tiles are aligned to a grid in model space
+/// (rather than to the corner of the image).  This means that interpolated
+/// tiles may differ slightly from the output of [`ImageRenderConfig::run`].
+/// Scales are treated as equal if they place every pixel in the frame within
+/// a small fraction of a pixel of each other.
+///
+/// [`PixelInfo::pixel`](crate::render::PixelInfo::pixel) and
+/// [`PixelInfo::pos`](crate::render::PixelInfo::pos) depend on where a tile
+/// lands in the image, so tiles from modes which set
+/// [`ShadingMode::POSITION`] are only reused at the same position in an image
+/// of the same size.
+///
+/// The cache is automatically cleared if it's used with a different shape,
+/// different variables, or different tile sizes.  Shapes are compared by
+/// identity, so a clone of the same shape will reuse cached tiles but an
+/// identical shape built from scratch will not.
+pub struct TileCache<F, M: ShadingMode> {
+    /// Shape, variables and tile sizes used to render the cached tiles
+    source: Option<(Shape<F>, ShapeVars<f32>, Vec<usize>)>,
+
+    /// Cached tiles, along with the frame in which they were last used
+    tiles: HashMap<TileKey, (u64, Vec<M::Output>)>,
+
+    /// Scales of the pixel grids used by cached tiles, indexed by ID
+    scales: Vec<(u32, Scale)>,
+
+    /// Next scale ID to assign
+    next_scale: u32,
+
+    /// Maximum number of tiles to keep
+    capacity: usize,
+
+    /// Frame counter, used to evict the least recently used tiles
+    frame: u64,
+}
+
+impl<F, M: ShadingMode> Default for TileCache<F, M> {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl<F, M: ShadingMode> TileCache<F, M> {
+    /// Builds a new cache which stores up to `capacity` root tiles
+    ///
+    /// The capacity should be large enough to hold every tile in a frame;
+    /// otherwise, tiles will be evicted before they can be reused.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            source: None,
+            tiles: HashMap::new(),
+            scales: vec![],
+            next_scale: 0,
+            capacity,
+            frame: 0,
+        }
+    }
+
+    /// Returns the number of cached tiles
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Checks whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Removes all cached tiles
+    pub fn clear(&mut self) {
+        self.source = None;
+        self.tiles.clear();
+        self.scales.clear();
+    }
+
+    /// Returns the ID of a matching scale, registering it if it's new
+    fn scale_id(&mut self, scale: Scale, extent: f32) -> u32 {
+        if let Some((id, _)) =
+            self.scales.iter().find(|(_, s)| s.matches(&scale, extent))
+        {
+            return *id;
+        }
+        let id = self.next_scale;
+        self.next_scale = self.next_scale.wrapping_add(1);
+        self.scales.push((id, scale));
+        id
+    }
+
+    /// Evicts the least recently used tiles, if we're over capacity
+    fn evict(&mut self) {
+        if self.tiles.len() <= self.capacity {
+            return;
+        }
+        let mut used: Vec<_> =
+            self.tiles.iter().map(|(k, (f, _))| (*f, *k)).collect();
+        used.sort_unstable_by_key(|(f, _)| *f);
+        for (_, k) in &used[..self.tiles.len() - self.capacity] {
+            self.tiles.remove(k);
+        }
+        self.scales
+            .retain(|(id, _)| self.tiles.keys().any(|k| k.scale == *id));
+    }
+}
+
+/// Key for a cached tile
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+struct TileKey {
+    /// ID of the pixel grid's scale (see [`TileCache::scales`])
+    scale: u32,
+
+    /// Quantized sub-pixel offset of the pixel grid in model space
+    frac: [u32; 2],
+
+    /// Position of the tile's corner on that pixel grid
+    corner: [i64; 2],
+
+    /// Tile corner and image size, for modes which set
+    /// [`ShadingMode::POSITION`]
+    image: Option<[i64; 4]>,
+}
+
+/// Screen-to-model scale of a pixel grid
+#[derive(Copy, Clone, Debug)]
+struct Scale {
+    /// Linear part of the pixel-to-model transform
+    lin: Matrix2<f32>,
+
+    /// Width of a pixel in world units
+    pixel_size: f32,
+}
+
+impl Scale {
+    /// Checks whether two scales produce the same pixel grid
+    ///
+    /// Scales match if every pixel within `extent` pixels of the grid origin
+    /// lands within half a sub-pixel step of its position under the other
+    /// scale, and pixel sizes match to the same relative precision.
+    fn matches(&self, other: &Scale, extent: f32) -> bool {
+        let Some(inv) = self.lin.try_inverse() else {
+            return false;
+        };
+        // Drift in pixels, bounded using the matrix's infinity norm
+        let d = inv * other.lin - Matrix2::identity();
+        let drift = d.abs().column_sum().max() * extent;
+        let tol = 0.5 / SUBPIXEL_STEPS;
+        drift < tol
+            && (self.pixel_size - other.pixel_size).abs()
+                < self.pixel_size * tol
+    }
+}
+
+/// Pixel grid for a particular render configuration
+struct Lattice {
+    /// Scale ID and quantized sub-pixel offset
+    scale: u32,
+    frac: [u32; 2],
+
+    /// Offset from image pixels to screen coordinates
+    ///
+    /// Root tiles are aligned to multiples of the tile size in (shifted)
+    /// screen coordinates.
+    shift: Vector2<usize>,
+
+    /// Position of the screen origin on the pixel grid
+    base: Vector2<i64>,
+
+    /// Image size, if tiles are keyed on their position in the image
+    image: Option<[i64; 2]>,
+}
+
+impl Lattice {
+    fn new<F, M: ShadingMode>(
+        config: &ImageRenderConfig,
+        cache: &mut TileCache<F, M>,
+    ) -> Self {
+        // Pixel `p` maps to `m = L * p + t` in model space, so it's at position
+        // `p + L⁻¹ t` on the model-space pixel grid.
+        let mat = config.mat();
+        let lin = mat.fixed_view::<2, 2>(0, 0).into_owned();
+        let t = mat.fixed_view::<2, 1>(0, 2).into_owned();
+        let offset = lin.try_inverse().expect("singular view matrix") * t;
+
+        // Split into whole pixels and (quantized) sub-pixel steps
+        let steps = (offset * SUBPIXEL_STEPS).map(|v| v.round() as i64);
+        let step = SUBPIXEL_STEPS as i64;
+        let whole = steps.map(|v| v.div_euclid(step));
+        let frac = steps.map(|v| v.rem_euclid(step) as u32);
+
+        let tile_size = config.tile_sizes[0] as i64;
+        let shift = whole.map(|v| v.rem_euclid(tile_size) as usize);
+        let base = whole - shift.cast::<i64>();
+
+        // Gradients and pixel sizes are reported in world units, so the world
+        // scale is also part of the key.  The tolerance for matching scales
+        // depends on how far this frame's pixels are from the grid origin.
+        let size = config.image_size;
+        let scale = Scale {
+            lin,
+            pixel_size: size.screen_to_world()[(0, 0)],
+        };
+        let extent = base.abs().max()
+            + (size.width().max(size.height()) as i64)
+            + 2 * tile_size;
+        let scale = cache.scale_id(scale, extent as f32);
+
+        let image =
+            M::POSITION.then(|| [size.width() as i64, size.height() as i64]);
+        Self {
+            scale,
+            frac: [frac.x, frac.y],
+            shift,
+            base,
+            image,
+        }
+    }
+
+    /// Returns the key for a root tile, given its corner in screen coordinates
+    fn key(&self, corner: Point2<usize>) -> TileKey {
+        let c = self.base + corner.coords.cast::<i64>();
+        let image = self.image.map(|[w, h]| {
+            let p = corner.coords.cast::<i64>() - self.shift.cast::<i64>();
+            [p.x, p.y, w, h]
+        });
+        TileKey {
+            scale: self.scale,
+            frac: self.frac,
+            corner: [c.x, c.y],
+            image,
+        }
+    }
+}
+
+/// Renders a 2D image, reusing tiles from the cache where possible
+pub(crate) fn render_cached<F: Function, M: ShadingMode + Sync>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+    cache: &mut TileCache<F, M>,
+) -> Vec<M::Output> {
+    let tile_sizes: Vec<usize> = config.tile_sizes.iter().cloned().collect();
+    let valid = cache.source.as_ref().is_some_and(|(s, v, t)| {
+        s.same_as(&shape) && v == vars && *t == tile_sizes
+    });
+    if !valid {
+        cache.clear();
+        cache.source = Some((shape.clone(), vars.clone(), tile_sizes));
+    }
+    cache.frame += 1;
+
+    let lattice = Lattice::new(config, cache);
+    let tile_size = config.tile_sizes[0];
+    let rect = ImageRect::from(config.image_size);
+    let mut image = vec![M::Output::default(); rect.area()];
+
+    // Copies a root tile (in shifted screen coordinates) into the image
+    let shift = lattice.shift;
+    let mut shifted = rect;
+    shifted.x += shift.x as u32;
+    shifted.y += shift.y as u32;
+    let copy = |image: &mut [M::Output], corner, data: &[M::Output]| {
+        if let Some((mut r, tile)) =
+            crop_tile(data, tile_size, corner, &shifted)
+        {
+            r.x -= shift.x as u32;
+            r.y -= shift.y as u32;
+            copy_tile(image, &rect, &r, &tile);
+        }
+    };
+
+    // Find root tiles which cover the image, in shifted screen coordinates
+    let mut missing = vec![];
+    let width = rect.width as usize + shift.x;
+    let height = rect.height as usize + shift.y;
+    for y in (0..height).step_by(tile_size) {
+        for x in (0..width).step_by(tile_size) {
+            let corner = Point2::new(x, y);
+            match cache.tiles.get_mut(&lattice.key(corner)) {
+                Some((frame, data)) => {
+                    *frame = cache.frame;
+                    copy(&mut image, corner, data);
+                }
+                None => missing.push(corner),
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        render_root_tiles::<F, M>(
+            shape,
+            vars,
+            config,
+            shift,
+            missing,
+            |corner, data| {
+                copy(&mut image, corner, &data);
+                cache.tiles.insert(lattice.key(corner), (cache.frame, data));
+            },
+        );
+    }
+    cache.evict();
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        eval::MathFunction,
+        render::{
+            BitRenderMode, ImageSize, IntervalAction, PixelInfo, SdfRenderMode,
+            TileSizes,
+        },
+        types::Interval,
+        var::Var,
+    };
+
+    fn circle() -> Tree {
+        let (x, y, _) = Tree::axes();
+        (x.square() + y.square()).sqrt() - 0.6
+    }
+
+    fn config() -> ImageRenderConfig {
+        // One pixel is exactly 1/64 of a model unit, so pans are exact
+        ImageRenderConfig {
+            image_size: ImageSize::from(128),
+            tile_sizes: TileSizes::new(&[32, 8]).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn cache_pan<F: Function + MathFunction>() {
+        let shape = Shape::<F>::from(circle());
+        let mut cfg = config();
+        let mut cache = TileCache::<F, BitRenderMode>::default();
+
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(out, cfg.run::<_, BitRenderMode>(shape.clone()));
+
+        // The model-space origin is on pixel row 63 (not 64), so root tiles
+        // are shifted by a pixel vertically and we need an extra row of them.
+        assert_eq!(cache.len(), 4 * 5);
+
+        // Panning by a fraction of a tile only renders one new column of tiles
+        cfg.view.translate(Vector2::new(8.0 / 64.0, 0.0));
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(out, cfg.run::<_, BitRenderMode>(shape.clone()));
+        assert_eq!(cache.len(), 5 * 5);
+
+        // Panning back reuses everything
+        cfg.view.translate(Vector2::new(-8.0 / 64.0, 0.0));
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(out, cfg.run::<_, BitRenderMode>(shape.clone()));
+        assert_eq!(cache.len(), 5 * 5);
+
+        // Zooming in renders new tiles; zooming back out reuses old ones
+        cfg.view.zoom(0.5, None);
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(out, cfg.run::<_, BitRenderMode>(shape.clone()));
+        let n = cache.len();
+        assert!(n > 5 * 5);
+        cfg.view.zoom(2.0, None);
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(out, cfg.run::<_, BitRenderMode>(shape.clone()));
+        assert_eq!(cache.len(), n);
+    }
+
+    fn cache_interpolated<F: Function + MathFunction>() {
+        // Tiles are aligned in model space, so an incrementally-updated image
+        // matches a fresh render with the same cache alignment.
+        let shape = Shape::<F>::from(circle());
+        let mut cfg = config();
+        let mut cache = TileCache::<F, SdfRenderMode>::default();
+        cfg.run_cached(shape.clone(), &mut cache);
+        for dx in [3, 17, 40] {
+            cfg.view.translate(Vector2::new(dx as f32 / 64.0, 0.0));
+            let out = cfg.run_cached(shape.clone(), &mut cache);
+            let mut fresh = TileCache::<F, SdfRenderMode>::default();
+            assert_eq!(out, cfg.run_cached(shape.clone(), &mut fresh));
+        }
+    }
+
+    fn cache_invalidate<F: Function + MathFunction>() {
+        let v = Var::new();
+        let (x, y, _) = Tree::axes();
+        let tree = (x.square() + y.square()).sqrt() - v;
+        let shape = Shape::<F>::from(tree.clone());
+        let cfg = config();
+        let mut cache = TileCache::<F, BitRenderMode>::new(25);
+
+        let mut vars = ShapeVars::new();
+        vars.insert(v.index().unwrap(), 0.5);
+        let a = cfg.run_cached_with_vars(shape.clone(), &vars, &mut cache);
+        assert_eq!(cache.len(), 20);
+
+        // Changing variables invalidates the cache
+        vars.insert(v.index().unwrap(), 0.25);
+        let b = cfg.run_cached_with_vars(shape.clone(), &vars, &mut cache);
+        assert_ne!(a, b);
+        assert_eq!(b, cfg.run_with_vars::<_, BitRenderMode>(shape, &vars));
+        assert_eq!(cache.len(), 20);
+
+        // So does changing the shape, even if it's equivalent
+        let other = Shape::<F>::from(tree);
+        let c = cfg.run_cached_with_vars(other.clone(), &vars, &mut cache);
+        assert_eq!(b, c);
+        assert_eq!(cache.len(), 20);
+
+        // The least recently used tiles are evicted
+        let mut cfg = cfg;
+        for _ in 0..2 {
+            cfg.view.translate(Vector2::new(0.5, 0.0));
+            cfg.run_cached_with_vars(other.clone(), &vars, &mut cache);
+        }
+        assert_eq!(cache.len(), 25);
+    }
+
+    fn cache_scale<F: Function + MathFunction>() {
+        let shape = Shape::<F>::from(circle());
+        let mut cfg = config();
+        let mut cache = TileCache::<F, SdfRenderMode>::default();
+        cfg.run_cached(shape.clone(), &mut cache);
+        assert_eq!(cache.len(), 4 * 5);
+
+        // A tiny zoom moves the far side of the image by a few thousandths of
+        // a pixel, which is too much to reuse tiles.
+        cfg.view.zoom(1.0 + 1e-5, None);
+        let out = cfg.run_cached(shape.clone(), &mut cache);
+        let mut fresh = TileCache::<F, SdfRenderMode>::default();
+        assert_eq!(out, cfg.run_cached(shape.clone(), &mut fresh));
+        assert_eq!(cache.len(), 2 * 4 * 5);
+    }
+
+    /// Shading mode which records each pixel's position in the image
+    struct PixelPositionMode;
+
+    impl ShadingMode for PixelPositionMode {
+        type Output = [usize; 2];
+        const POSITION: bool = true;
+        fn interval(
+            _i: Interval,
+            _depth: usize,
+        ) -> IntervalAction<Self::Output> {
+            IntervalAction::Recurse
+        }
+        fn shade(p: &PixelInfo) -> Self::Output {
+            [p.pixel.x, p.pixel.y]
+        }
+    }
+
+    fn cache_position<F: Function + MathFunction>() {
+        let shape = Shape::<F>::from(circle());
+        let mut cfg = config();
+        let mut cache = TileCache::<F, PixelPositionMode>::default();
+        let check = |out: &[[usize; 2]]| {
+            for (i, p) in out.iter().enumerate() {
+                assert_eq!(*p, [i % 128, i / 128]);
+            }
+        };
+        check(&cfg.run_cached(shape.clone(), &mut cache));
+        assert_eq!(cache.len(), 4 * 5);
+
+        // Panning can't reuse tiles, because they'd land elsewhere in the image
+        cfg.view.translate(Vector2::new(8.0 / 64.0, 0.0));
+        check(&cfg.run_cached(shape.clone(), &mut cache));
+        assert_eq!(cache.len(), 4 * 5 + 5 * 5);
+
+        // Panning back reuses the original tiles
+        cfg.view.translate(Vector2::new(-8.0 / 64.0, 0.0));
+        check(&cfg.run_cached(shape.clone(), &mut cache));
+        assert_eq!(cache.len(), 4 * 5 + 5 * 5);
+    }
+
+    render_tests!(cache_pan);
+    render_tests!(cache_interpolated);
+    render_tests!(cache_invalidate);
+    render_tests!(cache_scale);
+    render_tests!(cache_position);
+}
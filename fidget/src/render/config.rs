@@ -1,6 +1,9 @@
 use crate::{
     eval::Function,
-    render::{ImageSize, RenderMode, TileSizes, View2, View3, VoxelSize},
+    render::{
+        ImageRect, ImageSize, ShadingMode, TileCache, TileSizes, View2, View3,
+        VoxelSize,
+    },
     shape::{Shape, ShapeVars},
 };
 use nalgebra::{Const, Matrix3, Matrix4, OPoint, Point2, Vector2};
@@ -102,22 +105,164 @@ impl Default for ImageRenderConfig {
 
 impl ImageRenderConfig {
     /// Render a shape in 2D using this configuration
-    pub fn run<F: Function, M: RenderMode + Sync>(
+    pub fn run<F: Function, M: ShadingMode + Sync>(
         &self,
         shape: Shape<F>,
-    ) -> Vec<<M as RenderMode>::Output> {
+    ) -> Vec<<M as ShadingMode>::Output> {
         self.run_with_vars::<F, M>(shape, &ShapeVars::new())
     }
 
     /// Render a shape in 2D using this configuration and variables
-    pub fn run_with_vars<F: Function, M: RenderMode + Sync>(
+    pub fn run_with_vars<F: Function, M: ShadingMode + Sync>(
         &self,
         shape: Shape<F>,
         vars: &ShapeVars<f32>,
-    ) -> Vec<<M as RenderMode>::Output> {
+    ) -> Vec<<M as ShadingMode>::Output> {
         crate::render::render2d::<F, M>(shape, vars, self)
     }
 
+    /// Render a sub-region of the image using this configuration
+    ///
+    /// The result is identical to the corresponding pixels of a full render
+    /// (from [`run`](Self::run)), and is of size `rect.width × rect.height`.
+    ///
+    /// # Panics
+    /// If the region extends beyond the image
+    pub fn run_region<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        rect: ImageRect,
+    ) -> Vec<<M as ShadingMode>::Output> {
+        self.run_region_with_vars::<F, M>(shape, &ShapeVars::new(), rect)
+    }
+
+    /// Render a sub-region of the image using this configuration and
+    /// variables
+    pub fn run_region_with_vars<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        rect: ImageRect,
+    ) -> Vec<<M as ShadingMode>::Output> {
+        let mut image = vec![M::Output::default(); rect.area()];
+        self.run_tiles_with_vars::<F, M>(shape, vars, rect, |r, data| {
+            copy_tile(&mut image, &rect, &r, data)
+        });
+        image
+    }
+
+    /// Render a sub-region of the image, streaming finished tiles to a
+    /// callback
+    ///
+    /// The callback is invoked (on the calling thread) with the region of each
+    /// tile, in image coordinates, and its pixels in row-major order.  Tiles
+    /// are delivered in arbitrary order; together, they cover `rect` exactly
+    /// once.  Only a few finished tiles are buffered: if the callback is slower
+    /// than rendering, worker threads wait for it.
+    ///
+    /// # Panics
+    /// If the region extends beyond the image
+    pub fn run_tiles<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        rect: ImageRect,
+        f: impl FnMut(ImageRect, &[<M as ShadingMode>::Output]),
+    ) {
+        self.run_tiles_with_vars::<F, M>(shape, &ShapeVars::new(), rect, f)
+    }
+
+    /// Render a sub-region of the image with the given variables, streaming
+    /// finished tiles to a callback
+    pub fn run_tiles_with_vars<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        rect: ImageRect,
+        f: impl FnMut(ImageRect, &[<M as ShadingMode>::Output]),
+    ) {
+        crate::render::render2d::render_tiles::<F, M>(
+            shape, vars, self, rect, f,
+        )
+    }
+
+    /// Render a shape in 2D progressively, for interactive preview
+    ///
+    /// The callback is first invoked with a low-resolution preview, where each
+    /// cell the size of the smallest tile is filled with a single sample from
+    /// its center.  It is then invoked again (on the calling thread) each time
+    /// a root tile is refined, with the full image so far.
+    ///
+    /// Returns the final image, which is identical to the output of
+    /// [`run`](Self::run).
+    pub fn run_progressive<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        f: impl FnMut(&[<M as ShadingMode>::Output]),
+    ) -> Vec<<M as ShadingMode>::Output> {
+        self.run_progressive_with_vars::<F, M>(shape, &ShapeVars::new(), f)
+    }
+
+    /// Render a shape in 2D progressively, with the given variables
+    pub fn run_progressive_with_vars<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        f: impl FnMut(&[<M as ShadingMode>::Output]),
+    ) -> Vec<<M as ShadingMode>::Output> {
+        crate::render::render2d::render_progressive::<F, M>(
+            shape, vars, self, f,
+        )
+    }
+
+    /// Render a shape in 2D, reusing tiles from a previous render
+    ///
+    /// This is useful for interactive panning and zooming: tiles are keyed by
+    /// their bounds in model space, so only newly exposed regions of the image
+    /// are evaluated.  See [`TileCache`] for details and caveats.
+    pub fn run_cached<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        cache: &mut TileCache<F, M>,
+    ) -> Vec<<M as ShadingMode>::Output> {
+        self.run_cached_with_vars::<F, M>(shape, &ShapeVars::new(), cache)
+    }
+
+    /// Render a shape in 2D with the given variables, reusing tiles from a
+    /// previous render
+    pub fn run_cached_with_vars<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        cache: &mut TileCache<F, M>,
+    ) -> Vec<<M as ShadingMode>::Output> {
+        crate::render::cache::render_cached::<F, M>(shape, vars, self, cache)
+    }
+
+    /// Render a scene of shapes in 2D using this configuration
+    ///
+    /// Each shape is tagged with a value (e.g. a color or material ID); the
+    /// returned image contains the tag of the topmost shape at each pixel,
+    /// where later shapes are drawn over earlier ones.
+    pub fn run_scene<F: Function, T: Copy>(
+        &self,
+        shapes: &[(Shape<F>, T)],
+    ) -> Vec<Option<T>> {
+        self.run_scene_with_vars::<F, T>(shapes, &ShapeVars::new())
+    }
+
+    /// Render a scene of shapes in 2D using this configuration and variables
+    pub fn run_scene_with_vars<F: Function, T: Copy>(
+        &self,
+        shapes: &[(Shape<F>, T)],
+        vars: &ShapeVars<f32>,
+    ) -> Vec<Option<T>> {
+        let (s, tags): (Vec<_>, Vec<_>) = shapes.iter().cloned().unzip();
+        crate::render::scene::render2d::<F>(&s, vars, self)
+            .into_iter()
+            .map(|i| i.map(|i| tags[i]))
+            .collect()
+    }
+
     /// Returns the combined screen-to-model transform matrix
     pub fn mat(&self) -> Matrix3<f32> {
         self.view.world_to_model() * self.image_size.screen_to_world()
@@ -179,11 +324,181 @@ impl VoxelRenderConfig {
         crate::render::render3d::<F>(shape, vars, self)
     }
 
+    /// Render a sub-region of the image using this configuration
+    ///
+    /// The region is a rectangle in the XY plane, covering the full depth of
+    /// the volume.  Returns a heightmap and RGB image of size
+    /// `rect.width × rect.height`, identical to the corresponding pixels of a
+    /// full render (from [`run`](Self::run)).
+    ///
+    /// # Panics
+    /// If the region extends beyond the image
+    pub fn run_region<F: Function>(
+        &self,
+        shape: Shape<F>,
+        rect: ImageRect,
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        self.run_region_with_vars::<F>(shape, &ShapeVars::new(), rect)
+    }
+
+    /// Render a sub-region of the image using this configuration and
+    /// variables
+    pub fn run_region_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        rect: ImageRect,
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        crate::render::render3d::render_region::<F>(shape, vars, self, rect)
+    }
+
+    /// Render a sub-region of the image, streaming finished tiles to a
+    /// callback
+    ///
+    /// The callback is invoked (on the calling thread) with the region of each
+    /// tile, in image coordinates, and its heightmap and RGB pixels in
+    /// row-major order.  Tiles are delivered in arbitrary order; together, they
+    /// cover `rect` exactly once.  Only a few finished tiles are buffered: if
+    /// the callback is slower than rendering, worker threads wait for it.
+    ///
+    /// # Panics
+    /// If the region extends beyond the image
+    pub fn run_tiles<F: Function>(
+        &self,
+        shape: Shape<F>,
+        rect: ImageRect,
+        f: impl FnMut(ImageRect, &[u32], &[[u8; 3]]),
+    ) {
+        self.run_tiles_with_vars::<F>(shape, &ShapeVars::new(), rect, f)
+    }
+
+    /// Render a sub-region of the image with the given variables, streaming
+    /// finished tiles to a callback
+    pub fn run_tiles_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        rect: ImageRect,
+        f: impl FnMut(ImageRect, &[u32], &[[u8; 3]]),
+    ) {
+        crate::render::render3d::render_tiles::<F>(shape, vars, self, rect, f)
+    }
+
+    /// Render a shape in 3D progressively, for interactive preview
+    ///
+    /// The callback is first invoked with a low-resolution preview, where each
+    /// column of cells the size of the smallest tile is sampled along its
+    /// center pixel.  It is then invoked again (on the calling thread) each
+    /// time a column of root tiles is refined, with the full heightmap and RGB
+    /// image so far.
+    ///
+    /// Returns the final heightmap and RGB image, which are identical to the
+    /// output of [`run`](Self::run).
+    pub fn run_progressive<F: Function>(
+        &self,
+        shape: Shape<F>,
+        f: impl FnMut(&[u32], &[[u8; 3]]),
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        self.run_progressive_with_vars::<F>(shape, &ShapeVars::new(), f)
+    }
+
+    /// Render a shape in 3D progressively, with the given variables
+    pub fn run_progressive_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        f: impl FnMut(&[u32], &[[u8; 3]]),
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        crate::render::render3d::render_progressive::<F>(shape, vars, self, f)
+    }
+
+    /// Render a shape in 3D by ray-marching, using this configuration
+    ///
+    /// This produces the same heightmap and RGB image as [`run`](Self::run),
+    /// but its cost scales with the complexity of the surface rather than the
+    /// depth of the volume.
+    pub fn run_raymarch<F: Function>(
+        &self,
+        shape: Shape<F>,
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        self.run_raymarch_with_vars::<F>(shape, &ShapeVars::new())
+    }
+
+    /// Render a shape in 3D by ray-marching, using this configuration and
+    /// variables
+    pub fn run_raymarch_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+    ) -> (Vec<u32>, Vec<[u8; 3]>) {
+        crate::render::raymarch::<F>(shape, vars, self)
+    }
+
+    /// Render a scene of shapes in 3D using this configuration
+    ///
+    /// Each shape is tagged with a value (e.g. a color or material ID).
+    /// Returns a tuple of heightmap, RGB image, and the tag of the visible
+    /// shape at each pixel; if shapes meet at the same depth, the later shape
+    /// wins.
+    pub fn run_scene<F: Function, T: Copy>(
+        &self,
+        shapes: &[(Shape<F>, T)],
+    ) -> (Vec<u32>, Vec<[u8; 3]>, Vec<Option<T>>) {
+        self.run_scene_with_vars::<F, T>(shapes, &ShapeVars::new())
+    }
+
+    /// Render a scene of shapes in 3D using this configuration and variables
+    pub fn run_scene_with_vars<F: Function, T: Copy>(
+        &self,
+        shapes: &[(Shape<F>, T)],
+        vars: &ShapeVars<f32>,
+    ) -> (Vec<u32>, Vec<[u8; 3]>, Vec<Option<T>>) {
+        let (s, tags): (Vec<_>, Vec<_>) = shapes.iter().cloned().unzip();
+        let (depth, color, owner) =
+            crate::render::scene::render3d::<F>(&s, vars, self);
+        let owner = owner.into_iter().map(|i| i.map(|i| tags[i])).collect();
+        (depth, color, owner)
+    }
+
+    /// Computes full-precision surface normals for a rendered heightmap
+    ///
+    /// The shape must be the same one which was used to produce `depth`.
+    /// Normals are unit vectors in camera coordinates (with `+z` pointing
+    /// towards the viewer); pixels without any depth have a zero normal.
+    pub fn run_normals<F: Function>(
+        &self,
+        shape: Shape<F>,
+        depth: &[u32],
+    ) -> Vec<[f32; 3]> {
+        self.run_normals_with_vars::<F>(shape, &ShapeVars::new(), depth)
+    }
+
+    /// Computes surface normals for a heightmap, using the given variables
+    pub fn run_normals_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        depth: &[u32],
+    ) -> Vec<[f32; 3]> {
+        crate::render::shade::normals::<F>(shape, vars, self, depth)
+    }
+
     /// Returns the combined screen-to-model transform matrix
     pub fn mat(&self) -> Matrix4<f32> {
         self.view.world_to_model() * self.image_size.screen_to_world()
     }
 
+    /// Returns the perspective transform in screen coordinates (if present)
+    ///
+    /// This is used to convert gradients back into orthographic screen
+    /// coordinates, so that they can be used as normals.
+    pub(crate) fn perspective_correction(&self) -> Option<Matrix4<f32>> {
+        self.view.perspective().map(|p| {
+            let s = self.image_size.screen_to_world();
+            s.try_inverse().unwrap() * p.matrix() * s
+        })
+    }
+
     /// Returns the data offset of a row within a subtile
     pub(crate) fn tile_row_offset(&self, tile: Tile<3>, row: usize) -> usize {
         self.tile_sizes.pixel_offset(tile.add(Vector2::new(0, row)))
@@ -192,6 +507,95 @@ impl VoxelRenderConfig {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Checks that a region lies within an image
+pub(crate) fn check_rect(rect: &ImageRect, width: usize, height: usize) {
+    assert!(
+        rect.x as usize + rect.width as usize <= width
+            && rect.y as usize + rect.height as usize <= height,
+        "region {rect:?} extends beyond the image ({width} × {height})"
+    );
+}
+
+/// Returns corners of the root tiles which intersect the given region
+pub(crate) fn root_tiles(rect: &ImageRect, t: usize) -> Vec<Point2<usize>> {
+    let x0 = rect.x as usize / t;
+    let y0 = rect.y as usize / t;
+    let x1 = rect.x as usize + rect.width as usize;
+    let y1 = rect.y as usize + rect.height as usize;
+    let mut out = vec![];
+    for i in x0..x1.div_ceil(t) {
+        for j in y0..y1.div_ceil(t) {
+            out.push(Point2::new(i * t, j * t));
+        }
+    }
+    out
+}
+
+/// Copies the part of a root tile which lies within the given region
+///
+/// `data` is the tile's pixels in row-major order.  Returns the cropped region
+/// (in image coordinates) and its pixels, or `None` if the tile doesn't
+/// intersect the region.
+pub(crate) fn crop_tile<T: Copy>(
+    data: &[T],
+    tile_size: usize,
+    corner: Point2<usize>,
+    rect: &ImageRect,
+) -> Option<(ImageRect, Vec<T>)> {
+    let tile = ImageRect::new(
+        corner.x as u32,
+        corner.y as u32,
+        tile_size as u32,
+        tile_size as u32,
+    );
+    let r = tile.intersection(rect)?;
+    let mut out = Vec::with_capacity(r.area());
+    for y in r.y..r.y + r.height {
+        let o = (y as usize - corner.y) * tile_size + r.x as usize - corner.x;
+        out.extend(&data[o..o + r.width as usize]);
+    }
+    Some((r, out))
+}
+
+/// Copies the part of a root tile which lies within the image into the image
+///
+/// This is equivalent to [`crop_tile`] followed by [`copy_tile`] for a region
+/// covering the whole image, but doesn't allocate.
+pub(crate) fn copy_root_tile<T: Copy>(
+    image: &mut [T],
+    width: usize,
+    height: usize,
+    corner: Point2<usize>,
+    tile_size: usize,
+    data: &[T],
+) {
+    let w = tile_size.min(width.saturating_sub(corner.x));
+    for (j, row) in data.chunks(tile_size).enumerate() {
+        let y = corner.y + j;
+        if y >= height {
+            break;
+        }
+        let o = y * width + corner.x;
+        image[o..o + w].copy_from_slice(&row[..w]);
+    }
+}
+
+/// Copies a tile (in image coordinates) into an image of the given region
+pub(crate) fn copy_tile<T: Copy>(
+    image: &mut [T],
+    rect: &ImageRect,
+    tile: &ImageRect,
+    data: &[T],
+) {
+    let w = tile.width as usize;
+    for (j, row) in data.chunks(w).enumerate() {
+        let y = tile.y as usize + j - rect.y as usize;
+        let x = tile.x as usize - rect.x as usize;
+        let o = y * rect.width as usize + x;
+        image[o..o + w].copy_from_slice(row);
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct Tile<const N: usize> {
     /// Corner of this tile, in global screen (pixel) coordinates
@@ -233,6 +637,47 @@ impl<const N: usize> Queue<N> {
     }
 }
 
+/// Runs workers on a queue of tiles, passing their output to a callback
+///
+/// Each worker is called with a render handle, the shared queue, and a
+/// function to emit its output.  In multithreaded mode, every worker thread
+/// gets its own clone of `handle`.  The callback is invoked on the calling
+/// thread; output is sent through a bounded channel, so workers wait if it
+/// falls behind (rather than buffering the whole image).
+pub(crate) fn run_workers<const N: usize, H: Clone + Send, T: Send>(
+    threads: ThreadCount,
+    tiles: Vec<Tile<N>>,
+    handle: H,
+    worker: impl Fn(H, &Queue<N>, &mut dyn FnMut(T)) + Sync,
+    mut f: impl FnMut(T),
+) {
+    let queue = Queue::new(tiles);
+
+    // Special-case for single-threaded operation, to give simpler backtraces
+    match threads {
+        ThreadCount::One => worker(handle, &queue, &mut f),
+
+        #[cfg(not(target_arch = "wasm32"))]
+        ThreadCount::Many(v) => std::thread::scope(|s| {
+            let (tx, rx) = std::sync::mpsc::sync_channel(v.get());
+            for _ in 0..v.get() {
+                let handle = handle.clone();
+                let tx = tx.clone();
+                let (queue, worker) = (&queue, &worker);
+                s.spawn(move || {
+                    worker(handle, queue, &mut |t| tx.send(t).unwrap())
+                });
+            }
+            // Drop our sender, so that the receiver finishes when the workers
+            // are all done.
+            drop(tx);
+            for t in rx {
+                f(t);
+            }
+        }),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -332,4 +777,10 @@ mod test {
             Point2::new(0.75, 0.25)
         );
     }
+
+    #[test]
+    #[should_panic(expected = "extends beyond the image")]
+    fn test_check_rect_overflow() {
+        check_rect(&ImageRect::new(u32::MAX, 0, 2, 1), 512, 512);
+    }
 }
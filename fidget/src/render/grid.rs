@@ -0,0 +1,442 @@
+//! Sampling of shapes onto dense or sparse 3D grids
+use super::RenderHandle;
+use crate::{
+    eval::Function,
+    render::{
+        config::{run_workers, Queue, ThreadCount, Tile},
+        View3,
+    },
+    shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
+    types::Interval,
+};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+/// Settings for sampling a shape onto a 3D grid
+///
+/// Samples are evenly spaced on the ±1 cube in world coordinates (including
+/// its corners), then mapped into model space by `view`.
+///
+/// The grid is divided into cubic blocks, each of which is evaluated with
+/// interval arithmetic first.  Blocks that are farther than `band` from the
+/// surface are stored as constants (clamped to `±band`) without per-sample
+/// evaluation; the remaining blocks are evaluated with a float slice evaluator
+/// using a simplified tape.
+#[derive(Clone, Debug)]
+pub struct GridConfig {
+    /// Number of samples along each axis
+    pub size: [usize; 3],
+
+    /// World-to-model transform
+    pub view: View3,
+
+    /// Distance (in model units) beyond which values are clamped
+    pub band: f32,
+
+    /// Number of samples along each side of a block
+    pub block_size: usize,
+
+    /// Number of worker threads
+    pub threads: ThreadCount,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            size: [64; 3],
+            view: View3::default(),
+            band: 0.25,
+            block_size: 8,
+            threads: ThreadCount::default(),
+        }
+    }
+}
+
+impl GridConfig {
+    /// Samples a shape onto a dense grid
+    pub fn run<F: Function>(&self, shape: Shape<F>) -> SdfGrid {
+        self.run_with_vars(shape, &ShapeVars::new())
+    }
+
+    /// Samples a shape onto a dense grid, with user-provided variables
+    pub fn run_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+    ) -> SdfGrid {
+        self.run_sparse_with_vars(shape, vars).to_dense()
+    }
+
+    /// Samples a shape onto a sparse (narrow-band) grid
+    pub fn run_sparse<F: Function>(&self, shape: Shape<F>) -> SparseSdfGrid {
+        self.run_sparse_with_vars(shape, &ShapeVars::new())
+    }
+
+    /// Samples a shape onto a sparse grid, with user-provided variables
+    pub fn run_sparse_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+    ) -> SparseSdfGrid {
+        sample(shape, vars, self)
+    }
+
+    /// Returns the transform from grid indices to model coordinates
+    pub fn mat(&self) -> Matrix4<f32> {
+        let mut scale = Vector3::zeros();
+        let mut offset = Vector3::zeros();
+        for i in 0..3 {
+            if self.size[i] > 1 {
+                scale[i] = 2.0 / (self.size[i] - 1) as f32;
+                offset[i] = -1.0;
+            }
+        }
+        self.view.world_to_model()
+            * Matrix4::new_translation(&offset)
+            * Matrix4::new_nonuniform_scaling(&scale)
+    }
+
+    /// Returns the number of blocks along each axis
+    fn blocks(&self) -> [usize; 3] {
+        self.size.map(|s| s.div_ceil(self.block_size))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A dense 3D grid of field values
+///
+/// Values are stored with X varying fastest, then Y, then Z.
+#[derive(Clone, Debug)]
+pub struct SdfGrid {
+    /// Number of samples along each axis
+    pub size: [usize; 3],
+
+    /// Transform from grid indices to model coordinates
+    pub mat: Matrix4<f32>,
+
+    /// Field values
+    pub data: Vec<f32>,
+}
+
+impl SdfGrid {
+    /// Returns the value at the given grid index
+    pub fn get(&self, i: usize, j: usize, k: usize) -> f32 {
+        self.data[i + self.size[0] * (j + self.size[1] * k)]
+    }
+
+    /// Returns the position of a grid index in model coordinates
+    pub fn pos(&self, i: usize, j: usize, k: usize) -> Point3<f32> {
+        self.mat
+            .transform_point(&Point3::new(i as f32, j as f32, k as f32))
+    }
+}
+
+/// A block within a [`SparseSdfGrid`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum GridBlock {
+    /// Every sample in the block is at least `band` inside the shape
+    Inside,
+    /// Every sample in the block is at least `band` outside the shape
+    Outside,
+    /// Per-sample values, with X varying fastest
+    ///
+    /// Blocks on the far edges of the grid may be smaller than the block size.
+    Dense(Vec<f32>),
+}
+
+/// A narrow-band 3D grid of field values
+///
+/// Only blocks near the surface store per-sample values; blocks far from the
+/// surface are stored as constants, equal to `-band` or `+band`.
+#[derive(Clone, Debug)]
+pub struct SparseSdfGrid {
+    /// Number of samples along each axis
+    pub size: [usize; 3],
+
+    /// Transform from grid indices to model coordinates
+    pub mat: Matrix4<f32>,
+
+    /// Distance beyond which values are clamped
+    pub band: f32,
+
+    /// Number of samples along each side of a block
+    pub block_size: usize,
+
+    /// Blocks, with X varying fastest, then Y, then Z
+    pub blocks: Vec<GridBlock>,
+}
+
+impl SparseSdfGrid {
+    /// Returns the number of blocks along each axis
+    pub fn blocks(&self) -> [usize; 3] {
+        self.size.map(|s| s.div_ceil(self.block_size))
+    }
+
+    /// Returns the value at the given grid index
+    pub fn get(&self, i: usize, j: usize, k: usize) -> f32 {
+        let b = self.block_size;
+        let nb = self.blocks();
+        match &self.blocks[i / b + nb[0] * (j / b + nb[1] * (k / b))] {
+            GridBlock::Inside => -self.band,
+            GridBlock::Outside => self.band,
+            GridBlock::Dense(data) => {
+                let corner = [i / b * b, j / b * b, k / b * b];
+                let [sx, sy, _] = self.block_extent(corner);
+                let (i, j, k) = (i - corner[0], j - corner[1], k - corner[2]);
+                data[i + sx * (j + sy * k)]
+            }
+        }
+    }
+
+    /// Converts to a dense grid
+    pub fn to_dense(&self) -> SdfGrid {
+        let [nx, ny, nz] = self.size;
+        let mut data = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    data.push(self.get(i, j, k));
+                }
+            }
+        }
+        SdfGrid {
+            size: self.size,
+            mat: self.mat,
+            data,
+        }
+    }
+
+    /// Returns the number of samples along each axis of a block
+    fn block_extent(&self, corner: [usize; 3]) -> [usize; 3] {
+        std::array::from_fn(|i| self.block_size.min(self.size[i] - corner[i]))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Worker<'a, F: Function> {
+    config: &'a GridConfig,
+    vars: &'a ShapeVars<f32>,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+}
+
+impl<F: Function> Worker<'_, F> {
+    fn block(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        corner: [usize; 3],
+    ) -> GridBlock {
+        let band = self.config.band;
+        let extent: [usize; 3] = std::array::from_fn(|i| {
+            self.config.block_size.min(self.config.size[i] - corner[i])
+        });
+        let [x, y, z] = std::array::from_fn(|i| {
+            Interval::new(corner[i] as f32, (corner[i] + extent[i] - 1) as f32)
+        });
+        let (i, trace) = self
+            .eval_interval
+            .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, self.vars)
+            .unwrap();
+        if i.upper() <= -band {
+            return GridBlock::Inside;
+        } else if i.lower() >= band {
+            return GridBlock::Outside;
+        }
+
+        let sub_tape = if let Some(trace) = trace.as_ref() {
+            shape.simplify(
+                trace,
+                &mut self.workspace,
+                &mut self.shape_storage,
+                &mut self.tape_storage,
+            )
+        } else {
+            shape
+        };
+
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        for k in 0..extent[2] {
+            for j in 0..extent[1] {
+                for i in 0..extent[0] {
+                    self.x.push((corner[0] + i) as f32);
+                    self.y.push((corner[1] + j) as f32);
+                    self.z.push((corner[2] + k) as f32);
+                }
+            }
+        }
+        let out = self
+            .eval_float_slice
+            .eval_v(
+                sub_tape.f_tape(&mut self.tape_storage),
+                &self.x,
+                &self.y,
+                &self.z,
+                self.vars,
+            )
+            .unwrap();
+
+        // The interval result may be conservative, so check again
+        if out.iter().all(|v| *v >= band) {
+            GridBlock::Outside
+        } else if out.iter().all(|v| *v <= -band) {
+            GridBlock::Inside
+        } else {
+            GridBlock::Dense(out.iter().map(|v| v.clamp(-band, band)).collect())
+        }
+    }
+}
+
+fn worker<F: Function>(
+    mut shape: RenderHandle<F>,
+    vars: &ShapeVars<f32>,
+    queue: &Queue<3>,
+    config: &GridConfig,
+    mut emit: impl FnMut(usize, GridBlock),
+) {
+    let mut w: Worker<F> = Worker {
+        config,
+        vars,
+        eval_float_slice: Default::default(),
+        eval_interval: Default::default(),
+        x: vec![],
+        y: vec![],
+        z: vec![],
+        tape_storage: vec![],
+        shape_storage: vec![],
+        workspace: Default::default(),
+    };
+    let nb = config.blocks();
+    let b = config.block_size;
+    while let Some(tile) = queue.next() {
+        let corner = [tile.corner[0], tile.corner[1], tile.corner[2]];
+        let (i, j, k) = (corner[0] / b, corner[1] / b, corner[2] / b);
+        let index = i + nb[0] * (j + nb[1] * k);
+        emit(index, w.block(&mut shape, corner));
+    }
+}
+
+fn sample<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &GridConfig,
+) -> SparseSdfGrid {
+    assert!(config.block_size > 0, "block size must be positive");
+    let mat = config.mat();
+    let shape = shape.apply_transform(mat);
+
+    let b = config.block_size;
+    let nb = config.blocks();
+    let mut tiles = vec![];
+    for k in 0..nb[2] {
+        for j in 0..nb[1] {
+            for i in 0..nb[0] {
+                tiles.push(Tile::new(Point3::new(i * b, j * b, k * b)));
+            }
+        }
+    }
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+
+    let mut blocks = vec![GridBlock::Outside; nb.iter().product()];
+    run_workers(
+        config.threads,
+        tiles,
+        rh,
+        |rh, queue, emit| {
+            worker::<F>(rh, vars, queue, config, |i, b| emit((i, b)))
+        },
+        |(i, b)| blocks[i] = b,
+    );
+    SparseSdfGrid {
+        size: config.size,
+        mat,
+        band: config.band,
+        block_size: config.block_size,
+        blocks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::Tree, eval::MathFunction};
+
+    fn sphere<F: Function + MathFunction>() -> Shape<F> {
+        let (x, y, z) = Tree::axes();
+        Shape::from((x.square() + y.square() + z.square()).sqrt() - 0.6)
+    }
+
+    fn grid_dense<F: Function + MathFunction>() {
+        let cfg = GridConfig {
+            size: [17, 13, 9],
+            band: 0.2,
+            block_size: 4,
+            ..Default::default()
+        };
+        let grid = cfg.run(sphere::<F>());
+        assert_eq!(grid.data.len(), 17 * 13 * 9);
+        assert_eq!(grid.pos(0, 0, 0), Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(grid.pos(16, 12, 8), Point3::new(1.0, 1.0, 1.0));
+        for k in 0..9 {
+            for j in 0..13 {
+                for i in 0..17 {
+                    let d = grid.pos(i, j, k).coords.norm() - 0.6;
+                    let v = grid.get(i, j, k);
+                    let expected = d.clamp(-0.2, 0.2);
+                    assert!(
+                        (v - expected).abs() < 1e-5,
+                        "bad value at {i} {j} {k}: {v} != {expected}"
+                    );
+                }
+            }
+        }
+    }
+
+    fn grid_sparse<F: Function + MathFunction>() {
+        let cfg = GridConfig {
+            size: [32; 3],
+            band: 0.1,
+            block_size: 4,
+            ..Default::default()
+        };
+        let sparse = cfg.run_sparse(sphere::<F>());
+        assert_eq!(sparse.blocks.len(), 8 * 8 * 8);
+
+        // The corners are far outside and the center is far inside
+        assert_eq!(sparse.blocks[0], GridBlock::Outside);
+        let center = 4 + 8 * (4 + 8 * 4);
+        assert_eq!(sparse.blocks[center], GridBlock::Inside);
+
+        // Only blocks near the surface are stored densely
+        let dense = sparse
+            .blocks
+            .iter()
+            .filter(|b| matches!(b, GridBlock::Dense(..)))
+            .count();
+        assert!(dense > 0);
+        assert!(dense < sparse.blocks.len() / 2, "too many dense blocks");
+
+        let grid = sparse.to_dense();
+        let single = GridConfig {
+            threads: ThreadCount::One,
+            ..cfg
+        }
+        .run(sphere::<F>());
+        assert_eq!(grid.data, single.data);
+    }
+
+    render_tests!(grid_dense);
+    render_tests!(grid_sparse);
+}
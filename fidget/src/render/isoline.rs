@@ -0,0 +1,368 @@
+//! Isoline (contour line) rendering
+use super::RenderHandle;
+use crate::{
+    eval::Function,
+    render::{
+        config::{copy_root_tile, root_tiles, run_workers, Queue, Tile},
+        ImageRect, ImageRenderConfig,
+    },
+    shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
+    types::{Grad, Interval},
+};
+use nalgebra::{Point2, Vector2};
+
+/// Settings for drawing isolines of a 2D field
+///
+/// Each pixel within `width / 2` pixels of an isoline is labelled with the
+/// index of that isoline's level in [`levels`](Self::levels).  Distance to an
+/// isoline is estimated to first order, as `|f - level| / |∇f|`.
+///
+/// Tiles whose interval result can't contain any level are skipped without
+/// per-pixel evaluation.
+#[derive(Clone, Debug)]
+pub struct IsolineConfig {
+    /// Values at which to draw isolines
+    pub levels: Vec<f32>,
+
+    /// Line width, in pixels
+    pub width: f32,
+}
+
+impl Default for IsolineConfig {
+    fn default() -> Self {
+        Self {
+            levels: vec![0.0],
+            width: 1.0,
+        }
+    }
+}
+
+impl IsolineConfig {
+    /// Builds a configuration with evenly spaced levels
+    ///
+    /// Levels are multiples of `spacing` within the given (inclusive) range.
+    pub fn spaced(spacing: f32, min: f32, max: f32) -> Self {
+        assert!(spacing > 0.0, "spacing must be positive");
+        let start = (min / spacing).ceil() as i64;
+        let end = (max / spacing).floor() as i64;
+        Self {
+            levels: (start..=end).map(|i| i as f32 * spacing).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Renders isolines of a shape using the given configuration
+    ///
+    /// Returns the index of the isoline level at each pixel (if any)
+    pub fn run<F: Function>(
+        &self,
+        shape: Shape<F>,
+        render: &ImageRenderConfig,
+    ) -> Vec<Option<usize>> {
+        self.run_with_vars::<F>(shape, &ShapeVars::new(), render)
+    }
+
+    /// Renders isolines of a shape using the given configuration and
+    /// variables
+    pub fn run_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        render: &ImageRenderConfig,
+    ) -> Vec<Option<usize>> {
+        render_isolines(shape, vars, render, self)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Worker<'a, F: Function> {
+    config: &'a ImageRenderConfig,
+    iso: &'a IsolineConfig,
+
+    xg: Vec<Grad>,
+    yg: Vec<Grad>,
+    zg: Vec<Grad>,
+
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+
+    /// Isoline labels for the current root tile
+    image: Vec<Option<usize>>,
+}
+
+impl<F: Function> Worker<'_, F> {
+    fn render_tile_recurse(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        vars: &ShapeVars<f32>,
+        levels: &[usize],
+        depth: usize,
+        tile: Tile<2>,
+    ) {
+        let tile_size = self.config.tile_sizes[depth];
+
+        // Expand the tile by half of the line width, since pixels near the
+        // tile's edges may be close to an isoline outside of the tile.
+        let base = Point2::from(tile.corner).cast::<f32>();
+        let m = self.iso.width / 2.0;
+        let x = Interval::new(base.x - m, base.x + tile_size as f32 + m);
+        let y = Interval::new(base.y - m, base.y + tile_size as f32 + m);
+        let z = Interval::new(0.0, 0.0);
+
+        let (i, trace) = self
+            .eval_interval
+            .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, vars)
+            .unwrap();
+
+        // Only keep levels which could be present in this tile
+        let levels: Vec<usize> = levels
+            .iter()
+            .cloned()
+            .filter(|j| {
+                let v = self.iso.levels[*j];
+                i.has_nan() || (v >= i.lower() && v <= i.upper())
+            })
+            .collect();
+        if levels.is_empty() {
+            return;
+        }
+
+        let sub_tape = if let Some(trace) = trace.as_ref() {
+            shape.simplify(
+                trace,
+                &mut self.workspace,
+                &mut self.shape_storage,
+                &mut self.tape_storage,
+            )
+        } else {
+            shape
+        };
+
+        if let Some(next_tile_size) = self.config.tile_sizes.get(depth + 1) {
+            let n = tile_size / next_tile_size;
+            for j in 0..n {
+                for i in 0..n {
+                    self.render_tile_recurse(
+                        sub_tape,
+                        vars,
+                        &levels,
+                        depth + 1,
+                        Tile::new(
+                            tile.corner + Vector2::new(i, j) * next_tile_size,
+                        ),
+                    );
+                }
+            }
+        } else {
+            self.render_tile_pixels(sub_tape, vars, &levels, tile_size, tile);
+        }
+    }
+
+    fn render_tile_pixels(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        vars: &ShapeVars<f32>,
+        levels: &[usize],
+        tile_size: usize,
+        tile: Tile<2>,
+    ) {
+        self.xg.clear();
+        self.yg.clear();
+        self.zg.clear();
+        for j in 0..tile_size {
+            for i in 0..tile_size {
+                let x = (tile.corner[0] + i) as f32;
+                let y = (tile.corner[1] + j) as f32;
+                self.xg.push(Grad::new(x, 1.0, 0.0, 0.0));
+                self.yg.push(Grad::new(y, 0.0, 1.0, 0.0));
+                self.zg.push(Grad::from(0.0));
+            }
+        }
+
+        let out = self
+            .eval_grad_slice
+            .eval_v(
+                shape.g_tape(&mut self.tape_storage),
+                &self.xg,
+                &self.yg,
+                &self.zg,
+                vars,
+            )
+            .unwrap();
+
+        // Gradients are with respect to screen coordinates, so distances are
+        // in pixels.
+        let half_width = self.iso.width / 2.0;
+        let mut index = 0;
+        for j in 0..tile_size {
+            let o = self
+                .config
+                .tile_sizes
+                .pixel_offset(tile.add(Vector2::new(0, j)));
+            for p in &mut self.image[o..][..tile_size] {
+                let g = out[index];
+                let norm = (g.dx.powi(2) + g.dy.powi(2)).sqrt();
+                let mut best = None;
+                for &k in levels {
+                    let d = (g.v - self.iso.levels[k]).abs() / norm;
+                    if d < half_width && best.is_none_or(|(_, b)| d < b) {
+                        best = Some((k, d));
+                    }
+                }
+                *p = best.map(|(k, _)| k);
+                index += 1;
+            }
+        }
+    }
+}
+
+fn worker<F: Function>(
+    mut shape: RenderHandle<F>,
+    vars: &ShapeVars<f32>,
+    queue: &Queue<2>,
+    config: &ImageRenderConfig,
+    iso: &IsolineConfig,
+    mut emit: impl FnMut(Tile<2>, Vec<Option<usize>>),
+) {
+    let mut w: Worker<F> = Worker {
+        config,
+        iso,
+        xg: vec![],
+        yg: vec![],
+        zg: vec![],
+        eval_grad_slice: Default::default(),
+        eval_interval: Default::default(),
+        tape_storage: vec![],
+        shape_storage: vec![],
+        workspace: Default::default(),
+        image: vec![],
+    };
+
+    let levels: Vec<usize> = (0..iso.levels.len()).collect();
+    while let Some(tile) = queue.next() {
+        w.image = vec![None; config.tile_sizes[0].pow(2)];
+        w.render_tile_recurse(&mut shape, vars, &levels, 0, tile);
+        emit(tile, std::mem::take(&mut w.image));
+    }
+}
+
+fn render_isolines<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+    iso: &IsolineConfig,
+) -> Vec<Option<usize>> {
+    // Convert to a 4x4 matrix and apply to the shape
+    let mat = config.mat();
+    let mat = mat.insert_row(2, 0.0);
+    let mat = mat.insert_column(2, 0.0);
+    let shape = shape.apply_transform(mat);
+
+    let t = config.tile_sizes[0];
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let tiles = root_tiles(&ImageRect::from(config.image_size), t);
+
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+
+    let mut image = vec![None; width * height];
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        rh,
+        |rh, queue, emit| {
+            worker::<F>(rh, vars, queue, config, iso, |t, d| emit((t, d)))
+        },
+        |(tile, data)| {
+            copy_root_tile(&mut image, width, height, tile.corner, t, &data)
+        },
+    );
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::Tree, eval::MathFunction, render::ImageSize};
+
+    fn isoline_x<F: Function + MathFunction>() {
+        let (x, _, _) = Tree::axes();
+        let shape = Shape::<F>::from(x);
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::from(64),
+            ..Default::default()
+        };
+        let iso = IsolineConfig::spaced(0.5, -0.6, 0.6);
+        assert_eq!(iso.levels, [-0.5, 0.0, 0.5]);
+        let out = iso.run(shape, &cfg);
+
+        // World X = -0.5, 0, and 0.5 are at pixel columns 16, 32, and 48
+        for (i, p) in out.iter().enumerate() {
+            let expected = match i % 64 {
+                16 => Some(0),
+                32 => Some(1),
+                48 => Some(2),
+                _ => None,
+            };
+            assert_eq!(*p, expected, "bad pixel at {}, {}", i % 64, i / 64);
+        }
+    }
+
+    fn isoline_circle<F: Function + MathFunction>() {
+        // Non-distance field, so that levels aren't evenly spaced
+        let (x, y, _) = Tree::axes();
+        let shape = Shape::<F>::from(x.square() + y.square());
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::from(128),
+            ..Default::default()
+        };
+        let iso = IsolineConfig {
+            levels: vec![0.25, 0.5625],
+            width: 2.0,
+        };
+        let out = iso.run(shape, &cfg);
+
+        // Distances are only estimated to first order, so allow for a little
+        // error in the line boundaries
+        let mat = cfg.image_size.screen_to_world();
+        let pixel = 2.0 / 128.0;
+        let mut counts = [0; 2];
+        for (i, p) in out.iter().enumerate() {
+            let pos = Point2::new((i % 128) as f32, (i / 128) as f32);
+            let r = mat.transform_point(&pos).coords.norm();
+            match p {
+                Some(k) => {
+                    let target = [0.5, 0.75][*k];
+                    assert!(
+                        (r - target).abs() <= pixel * 1.05,
+                        "pixel {pos} at radius {r} is not on line {k}"
+                    );
+                    counts[*k] += 1;
+                }
+                None => {
+                    for target in [0.5, 0.75] {
+                        assert!(
+                            (r - target).abs() >= pixel * 0.95,
+                            "pixel {pos} at radius {r} should be labelled"
+                        );
+                    }
+                }
+            }
+        }
+        // Each line should be roughly two pixels wide
+        for (k, r) in [0.5f32, 0.75].iter().enumerate() {
+            let expected = 2.0 * std::f32::consts::TAU * r / pixel;
+            let c = counts[k] as f32;
+            assert!((c - expected).abs() / expected < 0.1, "{c} {expected}");
+        }
+    }
+
+    render_tests!(isoline_x);
+    render_tests!(isoline_circle);
+}
@@ -9,22 +9,62 @@ use crate::{
 };
 use std::sync::Arc;
 
+/// Declares `vm`, `vm3`, and `jit` tests for a function generic over
+/// [`Function`]
+#[cfg(test)]
+macro_rules! render_tests {
+    ($i:ident) => {
+        mod $i {
+            use super::*;
+            #[test]
+            fn vm() {
+                $i::<$crate::vm::VmFunction>();
+            }
+            #[test]
+            fn vm3() {
+                $i::<$crate::vm::GenericVmFunction<3>>();
+            }
+            #[cfg(feature = "jit")]
+            #[test]
+            fn jit() {
+                $i::<$crate::jit::JitFunction>();
+            }
+        }
+    };
+}
+
+mod cache;
 mod config;
+mod grid;
+mod isoline;
+mod output;
+mod raymarch;
 mod region;
 mod render2d;
 mod render3d;
+mod scene;
+mod shade;
+mod slice;
 mod view;
 
+pub use cache::TileCache;
 pub use config::{ImageRenderConfig, ThreadCount, VoxelRenderConfig};
-pub use region::{ImageSize, RegionSize, VoxelSize};
-pub use view::{View2, View3};
+pub use grid::{GridBlock, GridConfig, SdfGrid, SparseSdfGrid};
+pub use isoline::IsolineConfig;
+pub use output::{write_pfm, write_pfm_rgb};
+pub use region::{ImageRect, ImageSize, RegionSize, VoxelSize};
+pub use shade::{Light, Occlusion, ShadeConfig};
+pub use slice::SliceConfig;
+pub use view::{Perspective, View2, View3};
 
+use raymarch::render as raymarch;
 use render2d::render as render2d;
 use render3d::render as render3d;
 
 pub use render2d::{
-    BitRenderMode, DebugRenderMode, RenderMode, SdfPixelRenderMode,
-    SdfRenderMode,
+    BitRenderMode, CoverageRenderMode, DebugRenderMode, FieldRenderMode,
+    IntervalAction, PixelInfo, RenderMode, SdfPixelRenderMode, SdfRenderMode,
+    ShadingMode, SupersampledCoverageRenderMode,
 };
 
 /// A `RenderHandle` contains lazily-populated tapes for rendering
@@ -110,6 +150,7 @@ impl<F: Function> RenderHandle<F> {
     ///
     /// As an internal optimization, this may reuse a previous simplification if
     /// the trace matches.
+    #[allow(clippy::unnecessary_unwrap)] // borrow checker workaround
     pub fn simplify(
         &mut self,
         trace: &F::Trace,
@@ -225,7 +266,7 @@ impl TileSizes {
         for i in 1..sizes.len() {
             if sizes[i - 1] <= sizes[i] {
                 return Err(Error::BadTileOrder(sizes[i - 1], sizes[i]));
-            } else if sizes[i - 1] % sizes[i] != 0 {
+            } else if !sizes[i - 1].is_multiple_of(sizes[i]) {
                 return Err(Error::BadTileSize(sizes[i - 1], sizes[i]));
             }
         }
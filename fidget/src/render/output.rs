@@ -0,0 +1,265 @@
+//! Grid and image output implementation
+use super::{ImageSize, SdfGrid};
+use nalgebra::{Matrix3, Point3, Vector3};
+use std::io::Write;
+
+impl SdfGrid {
+    /// Returns the model-space origin and per-index step along each axis
+    fn frame(&self) -> (Point3<f32>, [Vector3<f32>; 3]) {
+        let origin = self.pos(0, 0, 0);
+        let steps = std::array::from_fn(|i| {
+            let mut p = Point3::origin();
+            p[i] = 1.0;
+            self.mat.transform_point(&p) - origin
+        });
+        (origin, steps)
+    }
+
+    /// Writes the raw field values to the given output
+    ///
+    /// Values are written as little-endian `f32`, with X varying fastest.
+    pub fn write_raw<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        for v in &self.data {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes an NRRD file (with an attached header) to the given output
+    ///
+    /// The header records the grid's position in model space with `space
+    /// origin` and `space directions` fields.
+    pub fn write_nrrd<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let (origin, steps) = self.frame();
+        let [nx, ny, nz] = self.size;
+        writeln!(out, "NRRD0004")?;
+        writeln!(out, "# Written by Fidget")?;
+        writeln!(out, "type: float")?;
+        writeln!(out, "dimension: 3")?;
+        writeln!(out, "sizes: {nx} {ny} {nz}")?;
+        writeln!(out, "space dimension: 3")?;
+        write!(out, "space directions:")?;
+        for s in steps {
+            write!(out, " ({},{},{})", s.x, s.y, s.z)?;
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "space origin: ({},{},{})",
+            origin.x, origin.y, origin.z
+        )?;
+        writeln!(out, "endian: little")?;
+        writeln!(out, "encoding: raw")?;
+        writeln!(out)?;
+        self.write_raw(out)
+    }
+
+    /// Writes a VTK image data (`.vti`) file to the given output
+    ///
+    /// Field values are stored as a point data array named `sdf`, in raw
+    /// appended binary format.
+    pub fn write_vti<F: std::io::Write>(
+        &self,
+        out: &mut F,
+    ) -> Result<(), crate::Error> {
+        let (origin, steps) = self.frame();
+        let spacing = steps.map(|s| s.norm());
+        let dir = Matrix3::from_columns(&std::array::from_fn::<_, 3, _>(|i| {
+            if spacing[i] > 0.0 {
+                steps[i] / spacing[i]
+            } else {
+                Vector3::ith(i, 1.0)
+            }
+        }));
+        let [nx, ny, nz] = self.size.map(|s| s.max(1) - 1);
+        let extent = format!("0 {nx} 0 {ny} 0 {nz}");
+
+        writeln!(out, r#"<?xml version="1.0"?>"#)?;
+        writeln!(
+            out,
+            r#"<VTKFile type="ImageData" version="1.0" byte_order="LittleEndian" header_type="UInt64">"#
+        )?;
+        write!(
+            out,
+            r#"  <ImageData WholeExtent="{extent}" Origin="{} {} {}" Spacing="{} {} {}" Direction=""#,
+            origin.x, origin.y, origin.z, spacing[0], spacing[1], spacing[2],
+        )?;
+        for r in 0..3 {
+            for c in 0..3 {
+                let sep = if r + c == 0 { "" } else { " " };
+                write!(out, "{sep}{}", dir[(r, c)])?;
+            }
+        }
+        writeln!(out, r#"">"#)?;
+        writeln!(out, r#"    <Piece Extent="{extent}">"#)?;
+        writeln!(out, r#"      <PointData Scalars="sdf">"#)?;
+        writeln!(
+            out,
+            r#"        <DataArray type="Float32" Name="sdf" format="appended" offset="0"/>"#
+        )?;
+        writeln!(out, "      </PointData>")?;
+        writeln!(out, "    </Piece>")?;
+        writeln!(out, "  </ImageData>")?;
+        write!(out, r#"  <AppendedData encoding="raw">"#)?;
+        write!(out, "\n   _")?;
+        let bytes = (self.data.len() * std::mem::size_of::<f32>()) as u64;
+        out.write_all(&bytes.to_le_bytes())?;
+        self.write_raw(out)?;
+        writeln!(out, "\n  </AppendedData>")?;
+        writeln!(out, "</VTKFile>")?;
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writes a single-channel portable float map (PFM) to the given output
+///
+/// `data` is in row-major order, starting from the top-left pixel; this is
+/// suitable for raw field values (e.g. from
+/// [`FieldRenderMode`](super::FieldRenderMode)) or heightmaps.  Values are
+/// written as little-endian `f32`.
+pub fn write_pfm<W: Write>(
+    out: &mut W,
+    size: ImageSize,
+    data: &[f32],
+) -> Result<(), crate::Error> {
+    write_pfm_channels(out, size, b"Pf", data)
+}
+
+/// Writes a three-channel portable float map (PFM) to the given output
+///
+/// This is suitable for full-precision normal maps, e.g. from
+/// [`VoxelRenderConfig::run_normals`](super::VoxelRenderConfig::run_normals).
+pub fn write_pfm_rgb<W: Write>(
+    out: &mut W,
+    size: ImageSize,
+    data: &[[f32; 3]],
+) -> Result<(), crate::Error> {
+    write_pfm_channels(out, size, b"PF", data.as_flattened())
+}
+
+fn write_pfm_channels<W: Write>(
+    out: &mut W,
+    size: ImageSize,
+    magic: &[u8],
+    data: &[f32],
+) -> Result<(), crate::Error> {
+    let (width, height) = (size.width() as usize, size.height() as usize);
+    let channels = if magic == b"PF" { 3 } else { 1 };
+    assert_eq!(data.len(), width * height * channels);
+
+    out.write_all(magic)?;
+    // A negative scale indicates little-endian data
+    write!(out, "\n{width} {height}\n-1.0\n")?;
+    // Rows are stored from bottom to top
+    for row in data.chunks(width.max(1) * channels).rev() {
+        for v in row {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nalgebra::Matrix4;
+
+    fn grid() -> SdfGrid {
+        SdfGrid {
+            size: [3, 2, 2],
+            mat: Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0))
+                * Matrix4::new_scaling(0.5),
+            data: (0..12).map(|i| i as f32 - 5.5).collect(),
+        }
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn grid_raw() {
+        let g = grid();
+        let mut out = vec![];
+        g.write_raw(&mut out).unwrap();
+        assert_eq!(floats(&out), g.data);
+    }
+
+    #[test]
+    fn grid_nrrd() {
+        let g = grid();
+        let mut out = vec![];
+        g.write_nrrd(&mut out).unwrap();
+        let split = out.windows(2).position(|w| w == b"\n\n").unwrap();
+        let header = std::str::from_utf8(&out[..split]).unwrap();
+        let lines: Vec<_> = header.lines().collect();
+        assert_eq!(lines[0], "NRRD0004");
+        assert!(lines.contains(&"sizes: 3 2 2"));
+        assert!(lines.contains(&"space origin: (1,2,3)"));
+        assert!(
+            lines.contains(&"space directions: (0.5,0,0) (0,0.5,0) (0,0,0.5)")
+        );
+        assert_eq!(floats(&out[split + 2..]), g.data);
+    }
+
+    #[test]
+    fn grid_vti() {
+        let g = grid();
+        let mut out = vec![];
+        g.write_vti(&mut out).unwrap();
+
+        // Raw data begins after an underscore within the AppendedData tag
+        let tag = b"<AppendedData encoding=\"raw\">";
+        let tag = out.windows(tag.len()).position(|w| w == tag).unwrap();
+        let start =
+            tag + out[tag..].iter().position(|c| *c == b'_').unwrap() + 1;
+        let header = std::str::from_utf8(&out[..start]).unwrap();
+        assert!(header.contains(r#"WholeExtent="0 2 0 1 0 1""#));
+        assert!(header.contains(r#"Origin="1 2 3""#));
+        assert!(header.contains(r#"Spacing="0.5 0.5 0.5""#));
+        assert!(header.contains(r#"Direction="1 0 0 0 1 0 0 0 1""#));
+
+        let n = u64::from_le_bytes(out[start..start + 8].try_into().unwrap());
+        assert_eq!(n, 48);
+        let data = &out[start + 8..start + 8 + n as usize];
+        assert_eq!(floats(data), g.data);
+        let tail = std::str::from_utf8(&out[start + 8 + n as usize..]).unwrap();
+        assert!(tail.trim_end().ends_with("</VTKFile>"));
+    }
+
+    #[test]
+    fn pfm() {
+        let size = ImageSize::new(3, 2);
+        let data = [1.0, 2.0, 3.0, -4.0, 5.5, f32::MAX];
+        let mut out = vec![];
+        write_pfm(&mut out, size, &data).unwrap();
+        let header = b"Pf\n3 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        // Rows are flipped
+        assert_eq!(
+            floats(&out[header.len()..]),
+            [-4.0, 5.5, f32::MAX, 1.0, 2.0, 3.0]
+        );
+
+        let data = [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0]];
+        let mut out = vec![];
+        write_pfm_rgb(&mut out, ImageSize::new(1, 2), &data).unwrap();
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(
+            floats(&out[header.len()..]),
+            [1.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+        );
+    }
+}
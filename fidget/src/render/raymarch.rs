@@ -0,0 +1,455 @@
+//! 3D rendering by ray-marching
+//!
+//! Instead of evaluating every voxel in a column, rays are marched through the
+//! scene front-to-back.  Interval evaluation over bundles of rays (and
+//! adaptively-sized segments along them) is used to skip empty space; when a
+//! segment is ambiguous, the bundle is split until we reach the smallest tile
+//! size, then its rays are refined with float evaluation.
+//!
+//! If the shape is a distance field, its value is used to propose longer steps
+//! along each ray (as in sphere tracing).  Every step is still confirmed with
+//! interval arithmetic, so this is safe for fields which are not true SDFs.
+use super::RenderHandle;
+use crate::{
+    eval::Function,
+    render::{
+        config::{
+            copy_root_tile, root_tiles, run_workers, Queue, Tile,
+            VoxelRenderConfig,
+        },
+        render3d::unproject_grad,
+        ImageRect,
+    },
+    shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
+    types::{Grad, Interval},
+};
+use nalgebra::{Matrix4, Point2, Point3};
+
+/// A rectangular bundle of rays, in global pixel coordinates
+#[derive(Copy, Clone, Debug)]
+struct Bundle {
+    min: Point2<usize>,
+    /// Exclusive upper bound
+    max: Point2<usize>,
+}
+
+impl Bundle {
+    fn width(&self) -> usize {
+        (self.max.x - self.min.x).max(self.max.y - self.min.y)
+    }
+
+    /// Splits the bundle into (up to) four children
+    fn split(&self) -> impl Iterator<Item = Bundle> + '_ {
+        let mid = Point2::new(
+            (self.min.x + self.max.x).div_ceil(2),
+            (self.min.y + self.max.y).div_ceil(2),
+        );
+        [(self.min.y, mid.y), (mid.y, self.max.y)]
+            .into_iter()
+            .flat_map(move |(y0, y1)| {
+                [(self.min.x, mid.x), (mid.x, self.max.x)].into_iter().map(
+                    move |(x0, x1)| Bundle {
+                        min: Point2::new(x0, y0),
+                        max: Point2::new(x1, y1),
+                    },
+                )
+            })
+            .filter(|b| b.min.x < b.max.x && b.min.y < b.max.y)
+    }
+
+    /// Returns an iterator over `(x, y)` pixel positions in the bundle
+    fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (self.min.y..self.max.y)
+            .flat_map(move |y| (self.min.x..self.max.x).map(move |x| (x, y)))
+    }
+}
+
+struct Worker<'a, F: Function> {
+    config: &'a VoxelRenderConfig,
+
+    /// Screen-to-model transform, used to estimate voxel size
+    mat: Matrix4<f32>,
+
+    /// Perspective transform in screen coordinates, used to correct normals
+    perspective: Option<Matrix4<f32>>,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+
+    /// Rays being refined, as `(x, y)` pixel positions
+    rays: Vec<(usize, usize)>,
+
+    /// Corner of the current root tile
+    corner: Point2<usize>,
+
+    /// Depth image for the current root tile
+    depth: Vec<u32>,
+}
+
+impl<F: Function> Worker<'_, F> {
+    fn offset(&self, x: usize, y: usize) -> usize {
+        (y - self.corner.y) * self.config.tile_sizes[0] + (x - self.corner.x)
+    }
+
+    fn all_filled(&self, b: &Bundle) -> bool {
+        b.pixels().all(|(x, y)| self.depth[self.offset(x, y)] != 0)
+    }
+
+    fn fill(&mut self, b: &Bundle, z: u32) {
+        for (x, y) in b.pixels() {
+            let o = self.offset(x, y);
+            if self.depth[o] == 0 {
+                self.depth[o] = z;
+            }
+        }
+    }
+
+    /// Marches a bundle of rays from voxel `hi` down to `bottom` (inclusive)
+    fn march(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        vars: &ShapeVars<f32>,
+        b: Bundle,
+        mut hi: i64,
+        bottom: i64,
+    ) {
+        let leaf = b.width() <= self.config.tile_sizes.last();
+        let min_step = b.width() as i64;
+        let mut step = min_step;
+
+        let x = Interval::new(b.min.x as f32, (b.max.x - 1) as f32);
+        let y = Interval::new(b.min.y as f32, (b.max.y - 1) as f32);
+        while hi >= bottom {
+            if self.all_filled(&b) {
+                return;
+            }
+            let lo = (hi - step + 1).max(bottom);
+            let z = Interval::new(lo as f32, hi as f32);
+            let (i, trace) = self
+                .eval_interval
+                .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, vars)
+                .unwrap();
+
+            if i.lower() > 0.0 {
+                // Empty segment, so skip it and take a larger step next time
+                hi = lo - 1;
+                step = step.saturating_mul(2);
+                continue;
+            } else if i.upper() < 0.0 {
+                // Every ray in the bundle hits the top of this segment
+                self.fill(&b, (hi + 1) as u32);
+                return;
+            } else if step > min_step {
+                step = (step / 2).max(min_step);
+                continue;
+            }
+
+            // The segment is ambiguous, so we'll look at it more closely
+            // (using a tape simplified to this region)
+            let sub_tape = if let Some(trace) = trace.as_ref() {
+                shape.simplify(
+                    trace,
+                    &mut self.workspace,
+                    &mut self.shape_storage,
+                    &mut self.tape_storage,
+                )
+            } else {
+                &mut *shape
+            };
+            if leaf {
+                let hint = self.refine(sub_tape, vars, &b, lo, hi);
+                step = hint.max(min_step);
+            } else {
+                for child in b.split() {
+                    self.march(sub_tape, vars, child, hi, lo);
+                }
+                step = min_step;
+            }
+            hi = lo - 1;
+        }
+    }
+
+    /// Evaluates every voxel in the given segment of each unfilled ray
+    ///
+    /// Rays which hit the surface are filled in.  Returns a suggested step
+    /// size (in voxels) for the remaining rays, based on the distance field
+    /// value at the bottom of the segment.
+    fn refine(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        vars: &ShapeVars<f32>,
+        b: &Bundle,
+        lo: i64,
+        hi: i64,
+    ) -> i64 {
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        self.rays.clear();
+        for (x, y) in b.pixels() {
+            if self.depth[self.offset(x, y)] != 0 {
+                continue;
+            }
+            for k in (lo..=hi).rev() {
+                self.x.push(x as f32);
+                self.y.push(y as f32);
+                self.z.push(k as f32);
+            }
+            self.rays.push((x, y));
+        }
+        let out = self
+            .eval_float_slice
+            .eval_v(
+                shape.f_tape(&mut self.tape_storage),
+                &self.x,
+                &self.y,
+                &self.z,
+                vars,
+            )
+            .unwrap();
+
+        // Voxels get larger (in model space) further from the camera, so the
+        // size of a voxel at the bottom of the segment is a conservative
+        // estimate of the space that we can skip.
+        let a = self.mat.transform_point(&Point3::new(
+            b.min.x as f32,
+            b.min.y as f32,
+            lo as f32,
+        ));
+        let c = self.mat.transform_point(&Point3::new(
+            b.min.x as f32,
+            b.min.y as f32,
+            (lo - 1) as f32,
+        ));
+        let size = (a - c).norm();
+
+        let mut dist = f32::INFINITY;
+        let n = (hi - lo + 1) as usize;
+        let tile_size = self.config.tile_sizes[0];
+        for (column, &(x, y)) in out.chunks(n).zip(&self.rays) {
+            if let Some(i) = column.iter().position(|v| *v < 0.0) {
+                let o = (y - self.corner.y) * tile_size + (x - self.corner.x);
+                self.depth[o] = (hi - i as i64 + 1) as u32;
+            } else {
+                dist = dist.min(*column.last().unwrap());
+            }
+        }
+
+        // Convert from a distance to a number of voxels, treating the field as
+        // an SDF (the resulting step is checked with interval arithmetic)
+        if dist.is_finite() && size > 0.0 {
+            (dist / size).clamp(0.0, i64::MAX as f32 / 2.0) as i64
+        } else {
+            0
+        }
+    }
+
+    /// Computes normals for every filled pixel in the current tile
+    fn normals(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        vars: &ShapeVars<f32>,
+    ) -> Vec<[u8; 3]> {
+        let tile_size = self.config.tile_sizes[0];
+        let mut xg = vec![];
+        let mut yg = vec![];
+        let mut zg = vec![];
+        let mut index = vec![];
+        for (o, d) in self.depth.iter().enumerate() {
+            if *d != 0 {
+                let x = (self.corner.x + o % tile_size) as f32;
+                let y = (self.corner.y + o / tile_size) as f32;
+                let z = (*d - 1) as f32;
+                xg.push(Grad::new(x, 1.0, 0.0, 0.0));
+                yg.push(Grad::new(y, 0.0, 1.0, 0.0));
+                zg.push(Grad::new(z, 0.0, 0.0, 1.0));
+                index.push(o);
+            }
+        }
+        let mut color = vec![[0; 3]; self.depth.len()];
+        if index.is_empty() {
+            return color;
+        }
+        let out = self
+            .eval_grad_slice
+            .eval_v(shape.g_tape(&mut self.tape_storage), &xg, &yg, &zg, vars)
+            .unwrap();
+        for (i, o) in index.iter().enumerate() {
+            let mut g = out[i];
+            if let Some(mat) = self.perspective {
+                let p = Point3::new(xg[i].v, yg[i].v, zg[i].v);
+                g = unproject_grad(&mat, p, g);
+            }
+            color[*o] = g.to_rgb().unwrap_or([255, 0, 0]);
+        }
+        color
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct Image {
+    depth: Vec<u32>,
+    color: Vec<[u8; 3]>,
+}
+
+fn worker<F: Function>(
+    mut shape: RenderHandle<F>,
+    vars: &ShapeVars<f32>,
+    queue: &Queue<2>,
+    config: &VoxelRenderConfig,
+    mut emit: impl FnMut(Tile<2>, Image),
+) {
+    let tile_size = config.tile_sizes[0];
+    let mut w: Worker<F> = Worker {
+        config,
+        mat: config.mat(),
+        perspective: config.perspective_correction(),
+
+        eval_float_slice: Default::default(),
+        eval_grad_slice: Default::default(),
+        eval_interval: Default::default(),
+
+        tape_storage: vec![],
+        shape_storage: vec![],
+        workspace: Default::default(),
+
+        x: vec![],
+        y: vec![],
+        z: vec![],
+        rays: vec![],
+
+        corner: Point2::origin(),
+        depth: vec![],
+    };
+
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let top = config.image_size.depth() as i64 - 1;
+    while let Some(tile) = queue.next() {
+        w.corner = tile.corner;
+        w.depth = vec![0; tile_size.pow(2)];
+        let b = Bundle {
+            min: tile.corner,
+            max: Point2::new(
+                (tile.corner.x + tile_size).min(width),
+                (tile.corner.y + tile_size).min(height),
+            ),
+        };
+        w.march(&mut shape, vars, b, top, 0);
+        let color = w.normals(&mut shape, vars);
+        let depth = std::mem::take(&mut w.depth);
+        emit(tile, Image { depth, color });
+    }
+}
+
+/// Renders the given shape into a 3D image by ray-marching
+///
+/// The output is equivalent to [`render3d`](super::render3d).
+pub fn render<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+) -> (Vec<u32>, Vec<[u8; 3]>) {
+    let shape = shape.apply_transform(config.mat());
+
+    let t = config.tile_sizes[0];
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let tiles = root_tiles(&ImageRect::from(config.image_size), t);
+
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+
+    let mut image_depth = vec![0; width * height];
+    let mut image_color = vec![[0; 3]; width * height];
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        rh,
+        |rh, queue, emit| {
+            worker::<F>(rh, vars, queue, config, |t, d| emit((t, d)))
+        },
+        |(tile, patch)| {
+            let c = tile.corner;
+            copy_root_tile(&mut image_depth, width, height, c, t, &patch.depth);
+            copy_root_tile(&mut image_color, width, height, c, t, &patch.color);
+        },
+    );
+    (image_depth, image_color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        eval::MathFunction,
+        render::{Perspective, ThreadCount, VoxelSize},
+    };
+
+    /// Compares ray-marching against voxel rendering
+    fn check_against_voxels<F: Function>(
+        shape: Shape<F>,
+        cfg: &VoxelRenderConfig,
+    ) {
+        let (depth_a, color_a) = cfg.run(shape.clone());
+        let (depth_b, color_b) = cfg.run_raymarch(shape);
+        let mut filled = 0;
+        for (i, (a, b)) in depth_a.iter().zip(&depth_b).enumerate() {
+            assert_eq!(a, b, "depth mismatch at pixel {i}");
+            if *a != 0 {
+                assert_eq!(color_a[i], color_b[i], "normal mismatch at {i}");
+                filled += 1;
+            }
+        }
+        assert!(filled > 0);
+    }
+
+    fn raymarch_sphere<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere =
+            ((x - 0.2).square() + y.square() + (z - 0.1).square()).sqrt() - 0.6;
+        let shape = Shape::<F>::from(sphere);
+
+        let mut cfg = VoxelRenderConfig {
+            image_size: VoxelSize::new(80, 64, 96),
+            ..Default::default()
+        };
+        check_against_voxels(shape.clone(), &cfg);
+
+        cfg.view.set_perspective(Some(Perspective::new(
+            std::f32::consts::FRAC_PI_3,
+        )));
+        check_against_voxels(shape, &cfg);
+    }
+
+    fn raymarch_non_sdf<F: Function + MathFunction>() {
+        // Thin plates, scaled so that this is very much not a distance field
+        let (x, y, z) = Tree::axes();
+        let plates = ((z.clone() * 16.0).sin() - 0.95) * -100.0;
+        let ball = (x.square() + y.square()).sqrt() - 0.75;
+        let slab = z.abs() - 0.9; // keep the shape within the render volume
+        let shape = Shape::<F>::from(plates.max(ball).max(slab));
+
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(64),
+            threads: ThreadCount::One,
+            ..Default::default()
+        };
+        check_against_voxels(shape, &cfg);
+    }
+
+    render_tests!(raymarch_sphere);
+    render_tests!(raymarch_non_sdf);
+}
@@ -151,6 +151,66 @@ impl VoxelSize {
     }
 }
 
+/// A rectangular region of an image, in pixels
+///
+/// The region spans `x..x + width` and `y..y + height` in screen coordinates
+/// (see [`RegionSize`] for a diagram), so `y = 0` is the top row of the image.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ImageRect {
+    /// Left edge of the region
+    pub x: u32,
+    /// Top edge of the region
+    pub y: u32,
+    /// Width of the region
+    pub width: u32,
+    /// Height of the region
+    pub height: u32,
+}
+
+impl ImageRect {
+    /// Builds a new region from its top-left corner and size
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the number of pixels in the region
+    pub fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Returns the overlap between two regions, if they intersect
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let x1 = (self.x as u64 + self.width as u64)
+            .min(other.x as u64 + other.width as u64);
+        let y1 = (self.y as u64 + self.height as u64)
+            .min(other.y as u64 + other.height as u64);
+        (x1 > x as u64 && y1 > y as u64).then(|| {
+            Self::new(x, y, (x1 - x as u64) as u32, (y1 - y as u64) as u32)
+        })
+    }
+}
+
+/// Builds a region covering an entire image
+impl From<ImageSize> for ImageRect {
+    fn from(size: ImageSize) -> Self {
+        Self::new(0, 0, size.width(), size.height())
+    }
+}
+
+/// Builds a region covering the XY extent of an entire volume
+impl From<VoxelSize> for ImageRect {
+    fn from(size: VoxelSize) -> Self {
+        Self::new(0, 0, size.width(), size.height())
+    }
+}
+
 impl<const N: usize> std::ops::Index<usize> for RegionSize<N>
 where
     Const<N>: DimNameAdd<U1>,
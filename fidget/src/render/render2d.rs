@@ -2,19 +2,25 @@
 use super::RenderHandle;
 use crate::{
     eval::Function,
-    render::config::{ImageRenderConfig, Queue, Tile},
-    render::ThreadCount,
+    render::config::{
+        check_rect, copy_root_tile, crop_tile, root_tiles, run_workers,
+        ImageRenderConfig, Queue, Tile,
+    },
+    render::ImageRect,
     shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
-    types::Interval,
+    types::{Grad, Interval},
 };
-use nalgebra::{Point2, Vector2};
+use nalgebra::{Matrix2, Matrix3, Point2, Vector2};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Response type for [`RenderMode::interval`]
 pub enum IntervalAction<T> {
+    /// Fill the entire tile with the given value
     Fill(T),
+    /// Draw the tile with bilinear interpolation from its corners
     Interpolate,
+    /// Subdivide the tile (or evaluate individual pixels)
     Recurse,
 }
 
@@ -30,6 +36,108 @@ pub trait RenderMode {
     fn pixel(f: f32) -> Self::Output;
 }
 
+/// Information about a single pixel, passed to [`ShadingMode::shade`]
+#[derive(Copy, Clone, Debug)]
+pub struct PixelInfo {
+    /// Pixel position within the image
+    pub pixel: Point2<usize>,
+    /// Sample position in world coordinates
+    ///
+    /// This is the pixel's center, unless the mode is supersampled.
+    pub pos: Point2<f32>,
+    /// Width of a pixel in world units
+    pub pixel_size: f32,
+    /// Value of the implicit function
+    pub value: f32,
+    /// Gradient of the implicit function with respect to world coordinates
+    ///
+    /// This is only populated if [`ShadingMode::GRADIENTS`] is `true`.  In
+    /// regions drawn with [`IntervalAction::Interpolate`], it's the gradient
+    /// of the interpolated value.
+    pub grad: Option<Vector2<f32>>,
+    /// Values of the implicit function one pixel away from the sample
+    ///
+    /// Values are in the order left, right, up, down (i.e. toward the
+    /// previous and next image row).  This is only populated if
+    /// [`ShadingMode::NEIGHBORS`] is `true`; in regions drawn with
+    /// [`IntervalAction::Interpolate`], values are interpolated.
+    pub neighbors: Option<[f32; 4]>,
+}
+
+/// Richer configuration trait for rendering
+///
+/// Unlike [`RenderMode`], per-pixel drawing receives a [`PixelInfo`] with the
+/// pixel's position and (optionally) its gradient.  Every [`RenderMode`] is
+/// also a `ShadingMode`, which ignores everything but the pixel's value.
+pub trait ShadingMode {
+    /// Type of output pixel
+    type Output: Default + Copy + Clone + Send;
+
+    /// Whether to evaluate gradients at each pixel
+    ///
+    /// Gradient evaluation is more expensive than plain evaluation, so this
+    /// should only be enabled if the mode uses [`PixelInfo::grad`].
+    const GRADIENTS: bool = false;
+
+    /// Whether to evaluate the field at each pixel's neighbors
+    ///
+    /// This is useful for modes which compare a pixel against its
+    /// surroundings (e.g. outlines); it quintuples the number of evaluations,
+    /// so it should only be enabled if the mode uses
+    /// [`PixelInfo::neighbors`].
+    const NEIGHBORS: bool = false;
+
+    /// Whether the mode reads [`PixelInfo::pixel`] or [`PixelInfo::pos`]
+    ///
+    /// Most modes only depend on the field, so their tiles can be reused by a
+    /// [`TileCache`](crate::render::TileCache) wherever they land in a new
+    /// image.  If this is `true`, cached tiles are only reused at the same
+    /// position in an image of the same size.
+    const POSITION: bool = false;
+
+    /// Number of samples per pixel along each axis
+    ///
+    /// If this is greater than 1, then [`shade`](Self::shade) is called for
+    /// each sample (on an evenly spaced grid within the pixel) and the results
+    /// are merged with [`combine`](Self::combine).  Filled and interpolated
+    /// tiles are not supersampled.
+    const SUPERSAMPLE: usize = 1;
+
+    /// Margin (in pixels) added around each tile during interval evaluation
+    ///
+    /// Modes whose pixels depend on the field away from the pixel center (e.g.
+    /// anti-aliasing) should set this, so that tiles are only filled if the
+    /// entire footprint of their pixels is inside or outside the shape.
+    const INTERVAL_MARGIN: f32 = 0.0;
+
+    /// Decide whether to subdivide or fill an interval
+    fn interval(i: Interval, depth: usize) -> IntervalAction<Self::Output>;
+
+    /// Per-pixel drawing
+    ///
+    /// This is named differently from [`RenderMode::pixel`] so that calls to
+    /// either are unambiguous, since every [`RenderMode`] is also a
+    /// `ShadingMode`.
+    fn shade(p: &PixelInfo) -> Self::Output;
+
+    /// Merges supersampled values into a single pixel
+    ///
+    /// The default implementation returns the first sample.
+    fn combine(samples: &[Self::Output]) -> Self::Output {
+        samples[0]
+    }
+}
+
+impl<M: RenderMode> ShadingMode for M {
+    type Output = M::Output;
+    fn interval(i: Interval, depth: usize) -> IntervalAction<Self::Output> {
+        M::interval(i, depth)
+    }
+    fn shade(p: &PixelInfo) -> Self::Output {
+        M::pixel(p.value)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Renderer that emits `DebugPixel`
@@ -161,6 +269,22 @@ impl RenderMode for SdfPixelRenderMode {
     }
 }
 
+/// Pixel-perfect render mode which returns the raw field value at each pixel
+///
+/// This mode recurses down to individual pixels, so every pixel is evaluated
+/// exactly.
+pub struct FieldRenderMode;
+
+impl RenderMode for FieldRenderMode {
+    type Output = f32;
+    fn interval(_i: Interval, _depth: usize) -> IntervalAction<f32> {
+        IntervalAction::Recurse
+    }
+    fn pixel(f: f32) -> f32 {
+        f
+    }
+}
+
 /// Fast rendering mode which mimics many SDF demos on ShaderToy
 ///
 /// Unlike [`SdfPixelRenderMode`], this mode uses linear interpolation when
@@ -181,32 +305,189 @@ impl RenderMode for SdfRenderMode {
     }
 }
 
+/// Anti-aliased renderer that emits per-pixel coverage (0 = empty, 255 = full)
+///
+/// Coverage is estimated from the distance to the boundary, which is
+/// approximated to first order as the value divided by the magnitude of the
+/// gradient.  This works well for distance fields and most smooth fields; use
+/// [`SupersampledCoverageRenderMode`] for fields with unreliable gradients, or
+/// which come close to zero inside the shape (e.g. along the seam between two
+/// touching shapes in a union).
+pub struct CoverageRenderMode;
+
+impl ShadingMode for CoverageRenderMode {
+    type Output = u8;
+    const GRADIENTS: bool = true;
+    const INTERVAL_MARGIN: f32 = 0.5;
+    fn interval(i: Interval, _depth: usize) -> IntervalAction<u8> {
+        coverage_interval(i)
+    }
+    fn shade(p: &PixelInfo) -> u8 {
+        let norm = p.grad.map(|g| g.norm()).unwrap_or(0.0);
+        if norm > 0.0 && norm.is_finite() {
+            // Distance to the boundary in pixels, positive outside
+            let d = p.value / norm / p.pixel_size;
+            ((0.5 - d).clamp(0.0, 1.0) * 255.0).round() as u8
+        } else if p.value < 0.0 {
+            u8::MAX
+        } else {
+            0
+        }
+    }
+}
+
+/// Anti-aliased renderer that emits coverage by supersampling
+///
+/// Each pixel is evaluated on an `N × N` grid of samples, and its coverage is
+/// the fraction of samples which are inside the shape.  This doesn't depend on
+/// the field's gradient, but is roughly `N²` times more expensive than
+/// [`CoverageRenderMode`] along edges.
+pub struct SupersampledCoverageRenderMode<const N: usize>;
+
+impl<const N: usize> ShadingMode for SupersampledCoverageRenderMode<N> {
+    type Output = u8;
+    const SUPERSAMPLE: usize = N;
+    const INTERVAL_MARGIN: f32 = 0.5;
+    fn interval(i: Interval, _depth: usize) -> IntervalAction<u8> {
+        coverage_interval(i)
+    }
+    fn shade(p: &PixelInfo) -> u8 {
+        if p.value < 0.0 {
+            u8::MAX
+        } else {
+            0
+        }
+    }
+    fn combine(samples: &[u8]) -> u8 {
+        let sum: usize = samples.iter().map(|s| *s as usize).sum();
+        ((sum + samples.len() / 2) / samples.len()) as u8
+    }
+}
+
+/// Fills tiles which are entirely inside or outside of the shape
+fn coverage_interval(i: Interval) -> IntervalAction<u8> {
+    if i.upper() < 0.0 {
+        IntervalAction::Fill(u8::MAX)
+    } else if i.lower() > 0.0 {
+        IntervalAction::Fill(0)
+    } else {
+        IntervalAction::Recurse
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Offsets (in pixels) of a sample and its neighbors
+///
+/// The neighbors are in the order used by [`PixelInfo::neighbors`].
+const NEIGHBOR_OFFSETS: [(f32, f32); 5] =
+    [(0.0, 0.0), (-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)];
+
+/// Returns the number of evaluations per sample for the given mode
+fn neighbor_stride<M: ShadingMode>() -> usize {
+    if M::NEIGHBORS {
+        NEIGHBOR_OFFSETS.len()
+    } else {
+        1
+    }
+}
+
 struct Scratch {
     x: Vec<f32>,
     y: Vec<f32>,
     z: Vec<f32>,
+
+    xg: Vec<Grad>,
+    yg: Vec<Grad>,
+    zg: Vec<Grad>,
 }
 
 impl Scratch {
-    fn new(size: usize) -> Self {
+    fn new(size: usize, gradients: bool) -> Self {
+        let grad_size = if gradients { size } else { 0 };
         Self {
             x: vec![0.0; size],
             y: vec![0.0; size],
             z: vec![0.0; size],
+
+            xg: vec![Grad::from(0.0); grad_size],
+            yg: vec![Grad::from(0.0); grad_size],
+            zg: vec![Grad::from(0.0); grad_size],
         }
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Converts screen-space pixel data into a [`PixelInfo`]
+#[derive(Copy, Clone)]
+struct PixelTransform {
+    /// Screen-to-world transform
+    screen_to_world: Matrix3<f32>,
+
+    /// Converts gradients from screen to world coordinates
+    grad_to_world: Matrix2<f32>,
+
+    /// Offset from image pixels to (shifted) screen coordinates
+    shift: Vector2<usize>,
+}
+
+impl PixelTransform {
+    fn new(config: &ImageRenderConfig, shift: Vector2<usize>) -> Self {
+        // Gradients transform by the inverse-transpose of the screen-to-world
+        // matrix, which is diagonal (so we don't bother transposing)
+        let screen_to_world = config.image_size.screen_to_world()
+            * Matrix3::new_translation(&-shift.cast::<f32>());
+        let grad_to_world = screen_to_world
+            .fixed_view::<2, 2>(0, 0)
+            .try_inverse()
+            .unwrap();
+        Self {
+            screen_to_world,
+            grad_to_world,
+            shift,
+        }
+    }
+
+    /// Builds a [`PixelInfo`] for a pixel in global screen coordinates
+    ///
+    /// `pos` is the sample position and `grad` is the gradient, both in
+    /// screen coordinates; they're converted to world coordinates.
+    fn info(
+        &self,
+        pixel: Point2<usize>,
+        pos: Point2<f32>,
+        value: f32,
+        grad: Option<Vector2<f32>>,
+        neighbors: Option<[f32; 4]>,
+    ) -> PixelInfo {
+        // Pixels outside the image are only rendered when they share a root
+        // tile with pixels inside it, so we clamp them to the image.
+        let pixel = Point2::new(
+            pixel.x.saturating_sub(self.shift.x),
+            pixel.y.saturating_sub(self.shift.y),
+        );
+        PixelInfo {
+            pixel,
+            pos: self.screen_to_world.transform_point(&pos),
+            pixel_size: self.screen_to_world[(0, 0)].abs(),
+            value,
+            grad: grad.map(|g| self.grad_to_world * g),
+            neighbors,
+        }
+    }
+}
+
 /// Per-thread worker
-struct Worker<'a, F: Function, M: RenderMode> {
+struct Worker<'a, F: Function, M: ShadingMode> {
     config: &'a ImageRenderConfig,
     scratch: Scratch,
 
+    /// Converts pixel positions and gradients into world coordinates
+    transform: PixelTransform,
+
     eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
     eval_interval: ShapeTracingEval<F::IntervalEval>,
 
     /// Spare tape storage for reuse
@@ -222,9 +503,12 @@ struct Worker<'a, F: Function, M: RenderMode> {
     ///
     /// This is a root tile, i.e. width and height of `config.tile_sizes[0]`
     image: Vec<M::Output>,
+
+    /// Samples for a single pixel, used when supersampling
+    samples: Vec<M::Output>,
 }
 
-impl<F: Function, M: RenderMode> Worker<'_, F, M> {
+impl<F: Function, M: ShadingMode> Worker<'_, F, M> {
     fn render_tile_recurse(
         &mut self,
         shape: &mut RenderHandle<F>,
@@ -236,8 +520,9 @@ impl<F: Function, M: RenderMode> Worker<'_, F, M> {
 
         // Find the interval bounds of the region, in screen coordinates
         let base = Point2::from(tile.corner).cast::<f32>();
-        let x = Interval::new(base.x, base.x + tile_size as f32);
-        let y = Interval::new(base.y, base.y + tile_size as f32);
+        let m = M::INTERVAL_MARGIN;
+        let x = Interval::new(base.x - m, base.x + tile_size as f32 + m);
+        let y = Interval::new(base.y - m, base.y + tile_size as f32 + m);
         let z = Interval::new(0.0, 0.0);
 
         // The shape applies the screen-to-model transform
@@ -266,25 +551,61 @@ impl<F: Function, M: RenderMode> Worker<'_, F, M> {
                     .eval(shape.f_tape(&mut self.tape_storage), &xs, &ys, &zs)
                     .unwrap();
 
+                let vs = [vs[0], vs[1], vs[2], vs[3]];
+
                 // Bilinear interpolation on a per-pixel basis
+                let scale = tile_size as f32;
+                let interp = |x: f32, y: f32| {
+                    let (x_frac, y_frac) =
+                        ((x - 1.0) / scale, (y - 1.0) / scale);
+                    let v0 = vs[0] * (1.0 - y_frac) + vs[1] * y_frac;
+                    let v1 = vs[2] * (1.0 - y_frac) + vs[3] * y_frac;
+                    v0 * (1.0 - x_frac) + v1 * x_frac
+                };
                 for y in 0..tile_size {
                     // Y interpolation
-                    let y_frac = (y as f32 - 1.0) / (tile_size as f32);
+                    let y_frac = (y as f32 - 1.0) / scale;
                     let v0 = vs[0] * (1.0 - y_frac) + vs[1] * y_frac;
                     let v1 = vs[2] * (1.0 - y_frac) + vs[3] * y_frac;
 
-                    let mut i = self
+                    let i = self
                         .config
                         .tile_sizes
                         .pixel_offset(tile.add(Vector2::new(0, y)));
                     for x in 0..tile_size {
                         // X interpolation
-                        let x_frac = (x as f32 - 1.0) / (tile_size as f32);
+                        let x_frac = (x as f32 - 1.0) / scale;
                         let v = v0 * (1.0 - x_frac) + v1 * x_frac;
 
+                        let grad = M::GRADIENTS.then(|| {
+                            let dy0 = vs[1] - vs[0];
+                            let dy1 = vs[3] - vs[2];
+                            Vector2::new(
+                                (v1 - v0) / scale,
+                                (dy0 * (1.0 - x_frac) + dy1 * x_frac) / scale,
+                            )
+                        });
+
+                        let neighbors = M::NEIGHBORS.then(|| {
+                            let (x, y) = (x as f32, y as f32);
+                            [
+                                interp(x - 1.0, y),
+                                interp(x + 1.0, y),
+                                interp(x, y - 1.0),
+                                interp(x, y + 1.0),
+                            ]
+                        });
+
                         // Write out the pixel
-                        self.image[i] = M::pixel(v);
-                        i += 1;
+                        let pixel = tile.add(Vector2::new(x, y));
+                        let info = self.transform.info(
+                            pixel,
+                            pixel.cast(),
+                            v,
+                            grad,
+                            neighbors,
+                        );
+                        self.image[i + x] = M::shade(&info);
                     }
                 }
                 return;
@@ -329,25 +650,65 @@ impl<F: Function, M: RenderMode> Worker<'_, F, M> {
         tile_size: usize,
         tile: Tile<2>,
     ) {
+        // Sample positions are packed pixel-by-pixel, so that each pixel's
+        // samples are contiguous.  If we need neighbors, then each sample is
+        // followed by its four neighbors.
+        let n = M::SUPERSAMPLE;
         let mut index = 0;
         for j in 0..tile_size {
             for i in 0..tile_size {
-                self.scratch.x[index] = (tile.corner[0] + i) as f32;
-                self.scratch.y[index] = (tile.corner[1] + j) as f32;
-                index += 1;
+                for sj in 0..n {
+                    for si in 0..n {
+                        let x = (tile.corner[0] + i) as f32
+                            + (si as f32 + 0.5) / n as f32
+                            - 0.5;
+                        let y = (tile.corner[1] + j) as f32
+                            + (sj as f32 + 0.5) / n as f32
+                            - 0.5;
+                        for (dx, dy) in
+                            NEIGHBOR_OFFSETS[..neighbor_stride::<M>()].iter()
+                        {
+                            let (x, y) = (x + dx, y + dy);
+                            self.scratch.x[index] = x;
+                            self.scratch.y[index] = y;
+                            if M::GRADIENTS {
+                                self.scratch.xg[index] =
+                                    Grad::new(x, 1.0, 0.0, 0.0);
+                                self.scratch.yg[index] =
+                                    Grad::new(y, 0.0, 1.0, 0.0);
+                            }
+                            index += 1;
+                        }
+                    }
+                }
             }
         }
 
-        let out = self
-            .eval_float_slice
-            .eval_v(
-                shape.f_tape(&mut self.tape_storage),
-                &self.scratch.x,
-                &self.scratch.y,
-                &self.scratch.z,
-                vars,
-            )
-            .unwrap();
+        let (values, grads): (&[f32], &[Grad]) = if M::GRADIENTS {
+            let out = self
+                .eval_grad_slice
+                .eval_v(
+                    shape.g_tape(&mut self.tape_storage),
+                    &self.scratch.xg,
+                    &self.scratch.yg,
+                    &self.scratch.zg,
+                    vars,
+                )
+                .unwrap();
+            (&[], out)
+        } else {
+            let out = self
+                .eval_float_slice
+                .eval_v(
+                    shape.f_tape(&mut self.tape_storage),
+                    &self.scratch.x,
+                    &self.scratch.y,
+                    &self.scratch.z,
+                    vars,
+                )
+                .unwrap();
+            (out, &[])
+        };
 
         let mut index = 0;
         for j in 0..tile_size {
@@ -356,8 +717,41 @@ impl<F: Function, M: RenderMode> Worker<'_, F, M> {
                 .tile_sizes
                 .pixel_offset(tile.add(Vector2::new(0, j)));
             for i in 0..tile_size {
-                self.image[o + i] = M::pixel(out[index]);
-                index += 1;
+                let pixel = tile.add(Vector2::new(i, j));
+                self.samples.clear();
+                for _ in 0..n * n {
+                    let pos = Point2::new(
+                        self.scratch.x[index],
+                        self.scratch.y[index],
+                    );
+                    let value = |i: usize| {
+                        if M::GRADIENTS {
+                            grads[i].v
+                        } else {
+                            values[i]
+                        }
+                    };
+                    let grad = M::GRADIENTS.then(|| {
+                        let g = grads[index];
+                        Vector2::new(g.dx, g.dy)
+                    });
+                    let neighbors = M::NEIGHBORS
+                        .then(|| std::array::from_fn(|i| value(index + i + 1)));
+                    let info = self.transform.info(
+                        pixel,
+                        pos,
+                        value(index),
+                        grad,
+                        neighbors,
+                    );
+                    self.samples.push(M::shade(&info));
+                    index += neighbor_stride::<M>();
+                }
+                self.image[o + i] = if n == 1 {
+                    self.samples[0]
+                } else {
+                    M::combine(&self.samples)
+                };
             }
         }
     }
@@ -365,33 +759,44 @@ impl<F: Function, M: RenderMode> Worker<'_, F, M> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-fn worker<F: Function, M: RenderMode>(
+fn worker<F: Function, M: ShadingMode>(
     mut shape: RenderHandle<F>,
     vars: &ShapeVars<f32>,
     queue: &Queue<2>,
     config: &ImageRenderConfig,
-) -> Vec<(Tile<2>, Vec<M::Output>)> {
-    let mut out = vec![];
-    let scratch = Scratch::new(config.tile_sizes.last().pow(2));
+    transform: PixelTransform,
+    mut emit: impl FnMut(Point2<usize>, Vec<M::Output>),
+) {
+    assert!(
+        M::SUPERSAMPLE > 0,
+        "must have at least one sample per pixel"
+    );
+    let scratch = Scratch::new(
+        (config.tile_sizes.last() * M::SUPERSAMPLE).pow(2)
+            * neighbor_stride::<M>(),
+        M::GRADIENTS,
+    );
 
     let mut w: Worker<F, M> = Worker {
         scratch,
         image: vec![],
+        samples: vec![],
         config,
+        transform,
         eval_float_slice: Default::default(),
+        eval_grad_slice: Default::default(),
         eval_interval: Default::default(),
         tape_storage: vec![],
         shape_storage: vec![],
         workspace: Default::default(),
     };
 
+    let tile_size = config.tile_sizes[0];
     while let Some(tile) = queue.next() {
-        w.image = vec![M::Output::default(); config.tile_sizes[0].pow(2)];
+        w.image = vec![M::Output::default(); tile_size.pow(2)];
         w.render_tile_recurse(&mut shape, vars, 0, tile);
-        let pixels = std::mem::take(&mut w.image);
-        out.push((tile, pixels))
+        emit(tile.corner, std::mem::take(&mut w.image));
     }
-    out
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -405,90 +810,244 @@ fn worker<F: Function, M: RenderMode>(
 /// This function is parameterized by both shape type (which determines how we
 /// perform evaluation) and render mode (which tells us how to color in the
 /// resulting pixels).
-pub fn render<F: Function, M: RenderMode + Sync>(
+pub fn render<F: Function, M: ShadingMode + Sync>(
     shape: Shape<F>,
     vars: &ShapeVars<f32>,
     config: &ImageRenderConfig,
 ) -> Vec<M::Output> {
-    // Convert to a 4x4 matrix and apply to the shape
-    let mat = config.mat();
-    let mat = mat.insert_row(2, 0.0);
-    let mat = mat.insert_column(2, 0.0);
-    let shape = shape.apply_transform(mat);
+    let rect = ImageRect::from(config.image_size);
+    let mut image = vec![M::Output::default(); rect.area()];
+    render_image::<F, M>(shape, vars, config, &mut image, |_| ());
+    image
+}
 
-    render_inner::<_, M>(shape, vars, config)
+/// Renders a 2D image progressively, passing intermediate images to a callback
+///
+/// The callback is first invoked with a low-resolution preview (from
+/// [`render_preview`]), then again each time a root tile is finished.  Once
+/// every tile is done, the image is identical to the output of [`render`].
+pub(crate) fn render_progressive<F: Function, M: ShadingMode + Sync>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+    mut f: impl FnMut(&[M::Output]),
+) -> Vec<M::Output> {
+    let mut image = render_preview::<F, M>(
+        &screen_shape(shape.clone(), config, Vector2::zeros()),
+        vars,
+        config,
+    );
+    f(&image);
+    render_image::<F, M>(shape, vars, config, &mut image, f);
+    image
 }
 
-fn render_inner<F: Function, M: RenderMode + Sync>(
+/// Renders every root tile of a 2D image, copying them into `image`
+///
+/// The callback is invoked with the full image each time a root tile is
+/// finished.  Unlike [`render_tiles`], tiles are copied directly into the
+/// image, without cropping them into temporary buffers.
+fn render_image<F: Function, M: ShadingMode + Sync>(
     shape: Shape<F>,
     vars: &ShapeVars<f32>,
     config: &ImageRenderConfig,
+    image: &mut [M::Output],
+    mut f: impl FnMut(&[M::Output]),
+) {
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let tile_size = config.tile_sizes[0];
+    let tiles = root_tiles(&ImageRect::from(config.image_size), tile_size);
+    render_root_tiles::<F, M>(
+        shape,
+        vars,
+        config,
+        Vector2::zeros(),
+        tiles,
+        |corner, data| {
+            copy_root_tile(image, width, height, corner, tile_size, &data);
+            f(image);
+        },
+    )
+}
+
+/// Renders a low-resolution preview of a 2D image
+///
+/// The image is divided into cells the size of the smallest tile.  Each cell is
+/// sampled once (at its center pixel), and every pixel in the cell is filled
+/// with that sample's color.
+///
+/// The shape must already be transformed into screen coordinates.
+fn render_preview<F: Function, M: ShadingMode>(
+    shape: &Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
 ) -> Vec<M::Output> {
-    let mut tiles = vec![];
-    let t = config.tile_sizes[0];
     let width = config.image_size.width() as usize;
     let height = config.image_size.height() as usize;
-    for i in 0..width.div_ceil(t) {
-        for j in 0..height.div_ceil(t) {
-            tiles.push(Tile::new(Point2::new(
-                i * config.tile_sizes[0],
-                j * config.tile_sizes[0],
-            )));
+    let cell = config.tile_sizes.last();
+
+    // Pick the sample pixel within each cell
+    let mut pixels = vec![];
+    for y in (0..height).step_by(cell) {
+        for x in (0..width).step_by(cell) {
+            let px = (x + cell / 2).min(width - 1);
+            let py = (y + cell / 2).min(height - 1);
+            pixels.push(Point2::new(px, py));
         }
     }
 
-    let queue = Queue::new(tiles);
-
-    let mut rh = RenderHandle::new(shape);
-    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
-
-    let out: Vec<_> = match config.threads {
-        ThreadCount::One => worker::<F, M>(rh, vars, &queue, config)
-            .into_iter()
-            .collect(),
-
-        #[cfg(not(target_arch = "wasm32"))]
-        ThreadCount::Many(v) => std::thread::scope(|s| {
-            let mut handles = vec![];
-            for _ in 0..v.get() {
-                let rh = rh.clone();
-                handles
-                    .push(s.spawn(|| worker::<F, M>(rh, vars, &queue, config)));
-            }
-            let mut out = vec![];
-            for h in handles {
-                out.extend(h.join().unwrap().into_iter());
-            }
-            out
-        }),
+    // Evaluate each sample pixel (and its neighbors, if needed)
+    let stride = neighbor_stride::<M>();
+    let samples: Vec<Point2<f32>> = pixels
+        .iter()
+        .flat_map(|p| {
+            NEIGHBOR_OFFSETS[..stride]
+                .iter()
+                .map(|(dx, dy)| Point2::new(p.x as f32 + dx, p.y as f32 + dy))
+        })
+        .collect();
+    let zs = vec![0.0; samples.len()];
+    let (values, grads): (Vec<f32>, Vec<Option<Vector2<f32>>>) = if M::GRADIENTS
+    {
+        let xs: Vec<_> = samples
+            .iter()
+            .map(|p| Grad::new(p.x, 1.0, 0.0, 0.0))
+            .collect();
+        let ys: Vec<_> = samples
+            .iter()
+            .map(|p| Grad::new(p.y, 0.0, 1.0, 0.0))
+            .collect();
+        let zs: Vec<_> = zs.iter().map(|z| Grad::from(*z)).collect();
+        let tape = shape.grad_slice_tape(Default::default());
+        let mut eval = ShapeBulkEval::<F::GradSliceEval>::default();
+        let out = eval.eval_v(&tape, &xs, &ys, &zs, vars).unwrap();
+        out.iter()
+            .map(|g| (g.v, Some(Vector2::new(g.dx, g.dy))))
+            .unzip()
+    } else {
+        let xs: Vec<_> = samples.iter().map(|p| p.x).collect();
+        let ys: Vec<_> = samples.iter().map(|p| p.y).collect();
+        let tape = shape.float_slice_tape(Default::default());
+        let mut eval = ShapeBulkEval::<F::FloatSliceEval>::default();
+        let out = eval.eval_v(&tape, &xs, &ys, &zs, vars).unwrap();
+        (out.to_vec(), vec![None; out.len()])
     };
-
+    let transform = PixelTransform::new(config, Vector2::zeros());
+    let info: Vec<PixelInfo> = pixels
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let k = i * stride;
+            let neighbors = M::NEIGHBORS
+                .then(|| std::array::from_fn(|j| values[k + j + 1]));
+            transform.info(*p, p.cast(), values[k], grads[k], neighbors)
+        })
+        .collect();
+
+    // Fill each cell with its sample
     let mut image = vec![M::Output::default(); width * height];
-    for (tile, data) in out.iter() {
-        let mut index = 0;
-        for j in 0..config.tile_sizes[0] {
-            let y = j + tile.corner.y;
-            for i in 0..config.tile_sizes[0] {
-                let x = i + tile.corner.x;
-                if y < height && x < width {
-                    image[y * width + x] = data[index];
-                }
-                index += 1;
+    let mut info = info.iter();
+    for y in (0..height).step_by(cell) {
+        for x in (0..width).step_by(cell) {
+            let v = M::shade(info.next().unwrap());
+            for row in y..(y + cell).min(height) {
+                image[row * width..][x..(x + cell).min(width)].fill(v);
             }
         }
     }
     image
 }
 
+/// Applies the image's screen-to-model transform to a shape
+///
+/// Screen coordinates are offset from image pixels by `shift`.
+fn screen_shape<F: Function>(
+    shape: Shape<F>,
+    config: &ImageRenderConfig,
+    shift: Vector2<usize>,
+) -> Shape<F> {
+    // Convert to a 4x4 matrix and apply to the shape
+    let mat = config.mat() * Matrix3::new_translation(&-shift.cast::<f32>());
+    let mat = mat.insert_row(2, 0.0);
+    let mat = mat.insert_column(2, 0.0);
+    shape.apply_transform(mat)
+}
+
+/// Renders a sub-region of a 2D image, passing finished tiles to a callback
+///
+/// Root tiles are aligned to the full image, so the results are identical to a
+/// full render; tiles on the edge of the region are cropped.
+pub(crate) fn render_tiles<F: Function, M: ShadingMode + Sync>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+    rect: ImageRect,
+    mut f: impl FnMut(ImageRect, &[M::Output]),
+) {
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    check_rect(&rect, width, height);
+
+    let tile_size = config.tile_sizes[0];
+    let tiles = root_tiles(&rect, tile_size);
+    render_root_tiles::<F, M>(
+        shape,
+        vars,
+        config,
+        Vector2::zeros(),
+        tiles,
+        |corner, data| {
+            if let Some((r, data)) = crop_tile(&data, tile_size, corner, &rect)
+            {
+                f(r, &data)
+            }
+        },
+    )
+}
+
+/// Renders a set of root tiles, passing each (uncropped) tile to a callback
+///
+/// Tile corners are given in screen coordinates, which are offset from image
+/// pixels by `shift`; this lets callers align root tiles to something other
+/// than the image's corner.  The callback is invoked on the calling thread;
+/// finished tiles are sent through a bounded channel, so workers wait if it
+/// falls behind (rather than buffering the whole image).
+pub(crate) fn render_root_tiles<F: Function, M: ShadingMode + Sync>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+    shift: Vector2<usize>,
+    tiles: Vec<Point2<usize>>,
+    mut f: impl FnMut(Point2<usize>, Vec<M::Output>),
+) {
+    let shape = screen_shape(shape, config, shift);
+    let transform = PixelTransform::new(config, shift);
+
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        rh,
+        |rh, queue, emit| {
+            worker::<F, M>(rh, vars, queue, config, transform, |c, d| {
+                emit((c, d))
+            })
+        },
+        |(corner, data)| f(corner, data),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
         eval::{Function, MathFunction},
-        render::{ImageSize, View2},
+        render::{ImageSize, TileSizes, View2},
         shape::Shape,
         var::Var,
-        vm::{GenericVmFunction, VmFunction},
         Context,
     };
 
@@ -838,25 +1397,242 @@ mod test {
         .test(shape, EXPECTED_05);
     }
 
-    macro_rules! render_tests {
-        ($i:ident) => {
-            mod $i {
-                use super::*;
-                #[test]
-                fn vm() {
-                    $i::<VmFunction>();
-                }
-                #[test]
-                fn vm3() {
-                    $i::<GenericVmFunction<3>>();
-                }
-                #[cfg(feature = "jit")]
-                #[test]
-                fn jit() {
-                    $i::<$crate::jit::JitFunction>();
+    /// Mode which records the value, position, and gradient of each pixel
+    struct InfoRenderMode<const G: bool>;
+
+    impl<const G: bool> ShadingMode for InfoRenderMode<G> {
+        type Output = (f32, [f32; 2], Option<[f32; 2]>);
+        const GRADIENTS: bool = G;
+        fn interval(
+            _i: Interval,
+            _depth: usize,
+        ) -> IntervalAction<Self::Output> {
+            IntervalAction::Recurse
+        }
+        fn shade(p: &PixelInfo) -> Self::Output {
+            (p.value, [p.pos.x, p.pos.y], p.grad.map(|g| [g.x, g.y]))
+        }
+    }
+
+    fn check_pixel_info<F: Function + MathFunction>() {
+        let mut ctx = Context::new();
+        let x = ctx.x();
+        let y = ctx.y();
+        let x2 = ctx.square(x).unwrap();
+        let y2 = ctx.square(y).unwrap();
+        let root = ctx.add(x2, y2).unwrap();
+        let shape = Shape::<F>::new(&ctx, root).unwrap();
+
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(48, 32),
+            ..Default::default()
+        };
+        let screen_to_world = cfg.image_size.screen_to_world();
+
+        let out = cfg.run::<_, InfoRenderMode<false>>(shape.clone());
+        for (i, (v, pos, grad)) in out.iter().enumerate() {
+            let p = Point2::new((i % 48) as f32, (i / 48) as f32);
+            let expected = screen_to_world.transform_point(&p);
+            assert_eq!(*pos, [expected.x, expected.y]);
+            assert!((v - (pos[0].powi(2) + pos[1].powi(2))).abs() < 1e-6);
+            assert!(grad.is_none());
+        }
+
+        // Gradients are in world coordinates
+        let out = cfg.run::<_, InfoRenderMode<true>>(shape);
+        for (v, pos, grad) in out.iter() {
+            assert!((v - (pos[0].powi(2) + pos[1].powi(2))).abs() < 1e-6);
+            let grad = grad.unwrap();
+            assert!((grad[0] - 2.0 * pos[0]).abs() < 1e-5, "{grad:?} {pos:?}");
+            assert!((grad[1] - 2.0 * pos[1]).abs() < 1e-5, "{grad:?} {pos:?}");
+        }
+    }
+
+    /// Mode which records the value and neighbors of each pixel
+    ///
+    /// If `I` is true, tiles are drawn with interpolation where possible.
+    struct NeighborRenderMode<const I: bool>;
+
+    impl<const I: bool> ShadingMode for NeighborRenderMode<I> {
+        type Output = (f32, [f32; 4]);
+        const NEIGHBORS: bool = true;
+        fn interval(
+            _i: Interval,
+            depth: usize,
+        ) -> IntervalAction<Self::Output> {
+            if I && depth > 0 {
+                IntervalAction::Interpolate
+            } else {
+                IntervalAction::Recurse
+            }
+        }
+        fn shade(p: &PixelInfo) -> Self::Output {
+            (p.value, p.neighbors.unwrap())
+        }
+    }
+
+    fn check_neighbors<F: Function + MathFunction>() {
+        let mut ctx = Context::new();
+        let x = ctx.x();
+        let y = ctx.y();
+        let y2 = ctx.mul(y, 2.0).unwrap();
+        let root = ctx.add(x, y2).unwrap();
+        let shape = Shape::<F>::new(&ctx, root).unwrap();
+
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(48, 32),
+            tile_sizes: TileSizes::new(&[16, 8]).unwrap(),
+            ..Default::default()
+        };
+        let check = |out: Vec<(f32, [f32; 4])>| {
+            let at = |x: usize, y: usize| out[y * 48 + x].0;
+            for y in 1..31 {
+                for x in 1..47 {
+                    let (_, n) = out[y * 48 + x];
+                    let expected = [
+                        at(x - 1, y),
+                        at(x + 1, y),
+                        at(x, y - 1),
+                        at(x, y + 1),
+                    ];
+                    for (a, b) in n.iter().zip(expected) {
+                        assert!((a - b).abs() < 1e-4, "{n:?} != {expected:?}");
+                    }
                 }
             }
         };
+        check(cfg.run::<_, NeighborRenderMode<false>>(shape.clone()));
+        check(cfg.run::<_, NeighborRenderMode<true>>(shape));
+    }
+
+    fn check_coverage<F: Function + MathFunction>() {
+        let mut ctx = Context::new();
+        let x = ctx.x();
+        let y = ctx.y();
+        let x2 = ctx.square(x).unwrap();
+        let y2 = ctx.square(y).unwrap();
+        let r2 = ctx.add(x2, y2).unwrap();
+
+        // Distance field and non-distance field, both of radius 0.5
+        let r = ctx.sqrt(r2).unwrap();
+        let sdf = ctx.sub(r, 0.5).unwrap();
+        let sdf = Shape::<F>::new(&ctx, sdf).unwrap();
+        let non_sdf = ctx.sub(r2, 0.25).unwrap();
+        let non_sdf = Shape::<F>::new(&ctx, non_sdf).unwrap();
+
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::from(64),
+            ..Default::default()
+        };
+        let expected = std::f32::consts::PI * 16.0f32.powi(2);
+        let check = |image: Vec<u8>| {
+            let area: f32 = image.iter().map(|p| *p as f32 / 255.0).sum();
+            assert!(
+                (area - expected).abs() / expected < 0.01,
+                "bad area: {area} != {expected}"
+            );
+            assert_eq!(image[0], 0);
+            assert_eq!(image[32 * 64 + 32], 255);
+            assert!(image.iter().any(|p| *p != 0 && *p != 255));
+        };
+        check(cfg.run::<_, CoverageRenderMode>(sdf.clone()));
+        check(cfg.run::<_, CoverageRenderMode>(non_sdf.clone()));
+        check(cfg.run::<_, SupersampledCoverageRenderMode<4>>(non_sdf));
+
+        // Coverage should be symmetric about the circle's center, which is at
+        // pixel (32, 31)
+        let image = cfg.run::<_, CoverageRenderMode>(sdf);
+        for y in 0..63 {
+            for x in 1..64 {
+                let a = image[y * 64 + x] as i32;
+                let b = image[(62 - y) * 64 + (64 - x)] as i32;
+                assert!((a - b).abs() <= 1, "asymmetric at {x}, {y}: {a} {b}");
+            }
+        }
+    }
+
+    fn check_region<F: Function + MathFunction>() {
+        let (ctx, root) = Context::from_text(HI.as_bytes()).unwrap();
+        let shape = Shape::<F>::new(&ctx, root).unwrap();
+
+        // Interpolated tiles depend on tile boundaries, so this also checks
+        // that regions are aligned with the full image's tiles
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(100, 70),
+            tile_sizes: TileSizes::new(&[32, 8]).unwrap(),
+            ..Default::default()
+        };
+        let full = cfg.run::<_, SdfRenderMode>(shape.clone());
+        for rect in [
+            ImageRect::new(0, 0, 100, 70),
+            ImageRect::new(13, 7, 50, 41),
+            ImageRect::new(99, 69, 1, 1),
+            ImageRect::new(32, 0, 32, 70),
+        ] {
+            let out = cfg.run_region::<_, SdfRenderMode>(shape.clone(), rect);
+            assert_eq!(out.len(), rect.area());
+            for (i, p) in out.iter().enumerate() {
+                let x = rect.x as usize + i % rect.width as usize;
+                let y = rect.y as usize + i / rect.width as usize;
+                assert_eq!(*p, full[y * 100 + x], "mismatch at {x}, {y}");
+            }
+
+            // Streamed tiles cover the region exactly once
+            let mut seen = vec![0; 100 * 70];
+            cfg.run_tiles::<_, SdfRenderMode>(
+                shape.clone(),
+                rect,
+                |r, data| {
+                    assert_eq!(data.len(), r.area());
+                    assert_eq!(r.intersection(&rect), Some(r));
+                    for (i, p) in data.iter().enumerate() {
+                        let x = r.x as usize + i % r.width as usize;
+                        let y = r.y as usize + i / r.width as usize;
+                        assert_eq!(*p, full[y * 100 + x]);
+                        seen[y * 100 + x] += 1;
+                    }
+                },
+            );
+            assert_eq!(seen.iter().sum::<usize>(), rect.area());
+            assert!(seen.iter().all(|s| *s <= 1));
+        }
+    }
+
+    fn check_progressive<F: Function + MathFunction>() {
+        let (ctx, root) = Context::from_text(HI.as_bytes()).unwrap();
+        let shape = Shape::<F>::new(&ctx, root).unwrap();
+
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(100, 70),
+            tile_sizes: TileSizes::new(&[32, 8]).unwrap(),
+            ..Default::default()
+        };
+        let full = cfg.run::<_, BitRenderMode>(shape.clone());
+
+        let mut images = vec![];
+        let out = cfg.run_progressive::<_, BitRenderMode>(shape, |image| {
+            images.push(image.to_vec())
+        });
+        assert_eq!(out, full);
+
+        // One preview, then one image per root tile
+        assert_eq!(images.len(), 1 + 4 * 3);
+        assert_eq!(images.last(), Some(&full));
+
+        // The preview is sampled at the center of each 8x8 cell
+        let preview = &images[0];
+        assert_ne!(*preview, full);
+        for y in (0..70).step_by(8) {
+            for x in (0..100).step_by(8) {
+                let (px, py) = ((x + 4).min(99), (y + 4).min(69));
+                let expected = full[py * 100 + px];
+                for j in y..(y + 8).min(70) {
+                    for i in x..(x + 8).min(100) {
+                        assert_eq!(preview[j * 100 + i], expected);
+                    }
+                }
+            }
+        }
     }
 
     render_tests!(check_hi);
@@ -865,4 +1641,9 @@ mod test {
     render_tests!(check_hi_bounded);
     render_tests!(check_quarter);
     render_tests!(check_circle_var);
+    render_tests!(check_pixel_info);
+    render_tests!(check_neighbors);
+    render_tests!(check_coverage);
+    render_tests!(check_region);
+    render_tests!(check_progressive);
 }
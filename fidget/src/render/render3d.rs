@@ -2,13 +2,16 @@
 use super::RenderHandle;
 use crate::{
     eval::Function,
-    render::config::{Queue, ThreadCount, Tile, VoxelRenderConfig},
+    render::config::{
+        check_rect, copy_root_tile, copy_tile, crop_tile, root_tiles,
+        run_workers, Queue, Tile, VoxelRenderConfig,
+    },
+    render::ImageRect,
     shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
     types::{Grad, Interval},
 };
 
-use nalgebra::{Point3, Vector2, Vector3};
-use std::collections::HashMap;
+use nalgebra::{Matrix3, Matrix4, Point2, Point3, Vector2, Vector3};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -56,6 +59,9 @@ struct Worker<'a, F: Function> {
     eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
     eval_interval: ShapeTracingEval<F::IntervalEval>,
 
+    /// Perspective transform in screen coordinates, used to correct normals
+    perspective: Option<Matrix4<f32>>,
+
     tape_storage: Vec<F::TapeStorage>,
     shape_storage: Vec<F::Storage>,
     workspace: F::Workspace,
@@ -269,12 +275,45 @@ impl<F: Function> Worker<'_, F> {
                 .unwrap();
 
             for (index, o) in self.scratch.columns[0..grad].iter().enumerate() {
-                self.color[*o] = out[index].to_rgb().unwrap_or([255, 0, 0]);
+                let mut g = out[index];
+                if let Some(mat) = self.perspective {
+                    let p = Point3::new(
+                        self.scratch.xg[index].v,
+                        self.scratch.yg[index].v,
+                        self.scratch.zg[index].v,
+                    );
+                    g = unproject_grad(&mat, p, g);
+                }
+                self.color[*o] = g.to_rgb().unwrap_or([255, 0, 0]);
             }
         }
     }
 }
 
+/// Converts a gradient from perspective to orthographic screen coordinates
+///
+/// `mat` is a projective map from screen coordinates into orthographic screen
+/// coordinates; the gradient is transformed by its inverse-transpose Jacobian,
+/// evaluated at `p`.
+pub(crate) fn unproject_grad(
+    mat: &Matrix4<f32>,
+    p: Point3<f32>,
+    g: Grad,
+) -> Grad {
+    let h = mat * p.to_homogeneous();
+    let q = h.xyz() / h.w;
+    let a: Matrix3<f32> = mat.fixed_view::<3, 3>(0, 0).into();
+    let m = mat.fixed_view::<1, 3>(3, 0);
+    let jacobian = (a - q * m) / h.w;
+    match jacobian.try_inverse() {
+        Some(inv) => {
+            let d = inv.transpose() * Vector3::new(g.dx, g.dy, g.dz);
+            Grad::new(g.v, d.x, d.y, d.z)
+        }
+        None => g,
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Default)]
@@ -297,12 +336,10 @@ impl Image {
 fn worker<F: Function>(
     mut shape: RenderHandle<F>,
     vars: &ShapeVars<f32>,
-    queues: &[Queue<3>],
-    mut index: usize,
+    queue: &Queue<2>,
     config: &VoxelRenderConfig,
-) -> HashMap<[usize; 2], Image> {
-    let mut out = HashMap::new();
-
+    mut emit: impl FnMut(Point2<usize>, Vec<u32>, Vec<[u8; 3]>),
+) {
     // Calculate maximum evaluation buffer size
     let buf_size = config.tile_sizes.last();
     let scratch = Scratch::new(buf_size);
@@ -316,43 +353,34 @@ fn worker<F: Function>(
         eval_interval: Default::default(),
         eval_grad_slice: Default::default(),
 
+        perspective: config.perspective_correction(),
+
         tape_storage: vec![],
         shape_storage: vec![],
         workspace: Default::default(),
     };
 
-    // Every thread has a set of tiles assigned to it, which are in Z-sorted
-    // order (to encourage culling).  Once the thread finishes its tiles, it
-    // begins stealing from other thread queues; if every single thread queue is
-    // empty, then we return.
-    let start = index;
-    loop {
-        while let Some(tile) = queues[index].next() {
-            let image = out
-                .remove(&[tile.corner[0], tile.corner[1]])
-                .unwrap_or_else(|| Image::new(config.tile_sizes[0]));
-
-            // Prepare to render, allocating space for a tile
-            w.depth = image.depth;
-            w.color = image.color;
+    // Each work item is a column of tiles, which is rendered from front to
+    // back (to encourage culling) and is finished once we reach the back.
+    let tile_size = config.tile_sizes[0];
+    let depth = config.image_size[2] as usize;
+    while let Some(column) = queue.next() {
+        let image = Image::new(tile_size);
+        w.depth = image.depth;
+        w.color = image.color;
+        for k in (0..depth.div_ceil(tile_size)).rev() {
+            let tile = Tile::new(Point3::new(
+                column.corner.x,
+                column.corner.y,
+                k * tile_size,
+            ));
             w.render_tile_recurse(&mut shape, vars, 0, tile);
-
-            // Steal the tile, replacing it with an empty vec
-            let depth = std::mem::take(&mut w.depth);
-            let color = std::mem::take(&mut w.color);
-            out.insert(
-                [tile.corner[0], tile.corner[1]],
-                Image { depth, color },
-            );
         }
-        // Move on to the next thread's queue
-        index = (index + 1) % queues.len();
-        if index == start {
-            break;
-        }
-    }
 
-    out
+        let depth = std::mem::take(&mut w.depth);
+        let color = std::mem::take(&mut w.color);
+        emit(column.corner, depth, color);
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -370,91 +398,233 @@ pub fn render<F: Function>(
     vars: &ShapeVars<f32>,
     config: &VoxelRenderConfig,
 ) -> (Vec<u32>, Vec<[u8; 3]>) {
-    let shape = shape.apply_transform(config.mat());
+    render_region(shape, vars, config, ImageRect::from(config.image_size))
+}
+
+/// Renders a sub-region of a 3D image
+///
+/// Returns a heightmap and normals, each of size `rect.width × rect.height`.
+pub(crate) fn render_region<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+    rect: ImageRect,
+) -> (Vec<u32>, Vec<[u8; 3]>) {
+    let mut image_depth = vec![0; rect.area()];
+    let mut image_color = vec![[0; 3]; rect.area()];
+    if rect == ImageRect::from(config.image_size) {
+        render_image(
+            shape,
+            vars,
+            config,
+            &mut image_depth,
+            &mut image_color,
+            |_, _| (),
+        );
+    } else {
+        render_tiles(shape, vars, config, rect, |r, depth, color| {
+            copy_tile(&mut image_depth, &rect, &r, depth);
+            copy_tile(&mut image_color, &rect, &r, color);
+        });
+    }
+    (image_depth, image_color)
+}
+
+/// Renders every column of root tiles in a 3D image, copying them into images
+///
+/// The callback is invoked with the full heightmap and normals each time a
+/// column is finished.  Unlike [`render_tiles`], tiles are copied directly
+/// into the images, without cropping them into temporary buffers.
+fn render_image<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+    image_depth: &mut [u32],
+    image_color: &mut [[u8; 3]],
+    mut f: impl FnMut(&[u32], &[[u8; 3]]),
+) {
+    let width = config.image_size[0] as usize;
+    let height = config.image_size[1] as usize;
+    let tile_size = config.tile_sizes[0];
+    let tiles = root_tiles(&ImageRect::from(config.image_size), tile_size);
+    render_root_tiles(shape, vars, config, tiles, |corner, depth, color| {
+        copy_root_tile(image_depth, width, height, corner, tile_size, &depth);
+        copy_root_tile(image_color, width, height, corner, tile_size, &color);
+        f(image_depth, image_color);
+    })
+}
 
-    let mut tiles = vec![];
-    let t = config.tile_sizes[0];
+/// Renders a 3D image progressively, passing intermediate images to a callback
+///
+/// The callback is first invoked with a low-resolution preview (from
+/// [`render_preview`]), then again each time a column of root tiles is
+/// finished.  Once every column is done, the heightmap and normals are
+/// identical to the output of [`render`].
+pub(crate) fn render_progressive<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+    mut f: impl FnMut(&[u32], &[[u8; 3]]),
+) -> (Vec<u32>, Vec<[u8; 3]>) {
+    let (mut image_depth, mut image_color) = render_preview(
+        &shape.clone().apply_transform(config.mat()),
+        vars,
+        config,
+    );
+    f(&image_depth, &image_color);
+    render_image(shape, vars, config, &mut image_depth, &mut image_color, f);
+    (image_depth, image_color)
+}
+
+/// Renders a low-resolution preview of a 3D image
+///
+/// The volume is divided into cubical cells the size of the smallest tile.
+/// Each column of cells is sampled along its center pixel (once per cell, from
+/// front to back), and every pixel in the column is filled with the depth and
+/// normal of the first sample inside the shape.
+///
+/// The shape must already be transformed into screen coordinates.
+fn render_preview<F: Function>(
+    shape: &Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+) -> (Vec<u32>, Vec<[u8; 3]>) {
     let width = config.image_size[0] as usize;
     let height = config.image_size[1] as usize;
     let depth = config.image_size[2] as usize;
-    for i in 0..width.div_ceil(t) {
-        for j in 0..height.div_ceil(t) {
-            for k in (0..depth.div_ceil(t)).rev() {
-                tiles.push(Tile::new(Point3::new(
-                    i * config.tile_sizes[0],
-                    j * config.tile_sizes[0],
-                    k * config.tile_sizes[0],
-                )));
+    let cell = config.tile_sizes.last();
+
+    // Sample Z positions, packed front-to-back
+    let zs: Vec<usize> = (0..depth)
+        .step_by(cell)
+        .rev()
+        .map(|z| (z + cell / 2).min(depth - 1))
+        .collect();
+
+    let mut pixels = vec![];
+    let (mut xs, mut ys, mut zv) = (vec![], vec![], vec![]);
+    for y in (0..height).step_by(cell) {
+        for x in (0..width).step_by(cell) {
+            let px = (x + cell / 2).min(width - 1);
+            let py = (y + cell / 2).min(height - 1);
+            pixels.push((x, y, px, py));
+            for z in &zs {
+                xs.push(px as f32);
+                ys.push(py as f32);
+                zv.push(*z as f32);
             }
         }
     }
 
-    let threads = config.threads.get().unwrap_or(1);
-    let tiles_per_thread = (tiles.len() / threads).max(1);
-    let mut tile_queues = vec![];
-    for ts in tiles.chunks(tiles_per_thread) {
-        tile_queues.push(Queue::new(ts.to_vec()));
-    }
-    tile_queues.resize_with(threads, || Queue::new(vec![]));
-
-    let mut rh = RenderHandle::new(shape);
-    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
-
-    // Special-case for single-threaded operation, to give simpler backtraces
-    let out: Vec<_> = match config.threads {
-        ThreadCount::One => {
-            worker::<F>(rh, vars, tile_queues.as_slice(), 0, config)
-                .into_iter()
-                .collect()
+    let tape = shape.float_slice_tape(Default::default());
+    let mut eval = ShapeBulkEval::<F::FloatSliceEval>::default();
+    let out = eval.eval_v(&tape, &xs, &ys, &zv, vars).unwrap();
+
+    // Find the first sample inside the shape in each column
+    let mut hits = vec![];
+    let (mut xg, mut yg, mut zg) = (vec![], vec![], vec![]);
+    for (col, values) in out.chunks(zs.len().max(1)).enumerate() {
+        if let Some(k) = values.iter().position(|v| *v < 0.0) {
+            let (_, _, px, py) = pixels[col];
+            let z = zs[k];
+            hits.push((col, z as u32 + 1));
+            xg.push(Grad::new(px as f32, 1.0, 0.0, 0.0));
+            yg.push(Grad::new(py as f32, 0.0, 1.0, 0.0));
+            zg.push(Grad::new(z as f32, 0.0, 0.0, 1.0));
         }
-
-        #[cfg(not(target_arch = "wasm32"))]
-        ThreadCount::Many(threads) => std::thread::scope(|s| {
-            let config = &config;
-            let mut handles = vec![];
-            let queues = tile_queues.as_slice();
-            for i in 0..threads.get() {
-                let rh = rh.clone();
-                handles.push(
-                    s.spawn(move || worker::<F>(rh, vars, queues, i, config)),
-                );
-            }
-            let mut out = vec![];
-            for h in handles {
-                out.extend(h.join().unwrap().into_iter());
-            }
-            out
-        }),
-    };
+    }
 
     let mut image_depth = vec![0; width * height];
     let mut image_color = vec![[0; 3]; width * height];
-    for (tile, patch) in out.iter() {
-        let mut index = 0;
-        for j in 0..config.tile_sizes[0] {
-            let y = j + tile[1];
-            for i in 0..config.tile_sizes[0] {
-                let x = i + tile[0];
-                if x < width && y < height {
-                    let o = y * width + x;
-                    if patch.depth[index] >= image_depth[o] {
-                        image_color[o] = patch.color[index];
-                        image_depth[o] = patch.depth[index];
-                    }
-                }
-                index += 1;
-            }
+    if hits.is_empty() {
+        return (image_depth, image_color);
+    }
+
+    let tape = shape.grad_slice_tape(Default::default());
+    let mut eval = ShapeBulkEval::<F::GradSliceEval>::default();
+    let grads = eval.eval_v(&tape, &xg, &yg, &zg, vars).unwrap();
+    let perspective = config.perspective_correction();
+    for (i, (col, z)) in hits.into_iter().enumerate() {
+        let mut g = grads[i];
+        if let Some(mat) = perspective {
+            let p = Point3::new(xg[i].v, yg[i].v, zg[i].v);
+            g = unproject_grad(&mat, p, g);
+        }
+        let c = g.to_rgb().unwrap_or([255, 0, 0]);
+        let (x, y, ..) = pixels[col];
+        for row in y..(y + cell).min(height) {
+            let r = row * width..;
+            image_depth[r.clone()][x..(x + cell).min(width)].fill(z);
+            image_color[r][x..(x + cell).min(width)].fill(c);
         }
     }
     (image_depth, image_color)
 }
 
+/// Renders a sub-region of a 3D image, passing finished tiles to a callback
+///
+/// Root tiles are aligned to the full image, so the results are identical to a
+/// full render; tiles on the edge of the region are cropped.
+pub(crate) fn render_tiles<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+    rect: ImageRect,
+    mut f: impl FnMut(ImageRect, &[u32], &[[u8; 3]]),
+) {
+    let width = config.image_size[0] as usize;
+    let height = config.image_size[1] as usize;
+    check_rect(&rect, width, height);
+
+    let tile_size = config.tile_sizes[0];
+    let tiles = root_tiles(&rect, tile_size);
+    render_root_tiles(shape, vars, config, tiles, |corner, depth, color| {
+        let depth = crop_tile(&depth, tile_size, corner, &rect);
+        let color = crop_tile(&color, tile_size, corner, &rect);
+        if let (Some((r, depth)), Some((_, color))) = (depth, color) {
+            f(r, &depth, &color);
+        }
+    })
+}
+
+/// Renders columns of root tiles, passing each (uncropped) column to a callback
+///
+/// The callback is invoked on the calling thread; finished columns are sent
+/// through a bounded channel, so workers wait if it falls behind (rather than
+/// buffering the whole image).
+fn render_root_tiles<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+    tiles: Vec<Point2<usize>>,
+    mut f: impl FnMut(Point2<usize>, Vec<u32>, Vec<[u8; 3]>),
+) {
+    let shape = shape.apply_transform(config.mat());
+
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        rh,
+        |rh, queue, emit| {
+            worker::<F>(rh, vars, queue, config, |c, d, rgb| emit((c, d, rgb)))
+        },
+        |(corner, depth, color)| f(corner, depth, color),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        context::Tree, eval::MathFunction, render::VoxelSize, var::Var,
-        vm::VmShape, Context,
+        context::Tree,
+        eval::MathFunction,
+        render::{ImageRect, Perspective, TileSizes, VoxelSize},
+        var::Var,
+        vm::VmShape,
+        Context,
     };
 
     /// Make sure we don't crash if there's only a single tile
@@ -518,26 +688,155 @@ mod test {
         }
     }
 
-    macro_rules! render_tests {
-        ($i:ident) => {
-            mod $i {
-                use super::*;
-                #[test]
-                fn vm() {
-                    $i::<$crate::vm::VmFunction>();
+    fn sphere_perspective<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere =
+            ((x - 0.2).square() + y.square() + (z - 0.3).square()).sqrt() - 0.5;
+        let shape = Shape::<F>::from(sphere);
+
+        let size = 64;
+        let mut cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(size),
+            ..Default::default()
+        };
+        cfg.view.set_perspective(Some(Perspective::new(
+            std::f32::consts::FRAC_PI_3,
+        )));
+        let (depth, normal) = cfg.run(shape);
+
+        // Compare against brute-force evaluation of every voxel column
+        let mat = cfg.mat();
+        let mut filled = 0;
+        for j in 0..size as usize {
+            for i in 0..size as usize {
+                let expected = (0..size as usize)
+                    .rev()
+                    .find(|k| {
+                        let p = mat.transform_point(&Point3::new(
+                            i as f32, j as f32, *k as f32,
+                        ));
+                        (p - Point3::new(0.2, 0.0, 0.3)).norm() < 0.5
+                    })
+                    .map(|k| k as u32 + 1)
+                    .unwrap_or(0);
+                let o = j * size as usize + i;
+                let actual = depth[o];
+                assert!(
+                    actual.abs_diff(expected) <= 1,
+                    "depth mismatch at ({i}, {j}): {actual} != {expected}"
+                );
+                filled += (actual > 0) as usize;
+            }
+        }
+        assert!(filled > 0);
+
+        // The pixel in the middle of the sphere should face the camera
+        let center = mat
+            .try_inverse()
+            .unwrap()
+            .transform_point(&Point3::new(0.2, 0.0, 0.8));
+        let o = center.y.round() as usize * size as usize
+            + center.x.round() as usize;
+        let [r, g, b] = normal[o];
+        assert!(b > 250 && r < 40 && g < 40, "bad normal {:?}", normal[o]);
+    }
+
+    fn sphere_region<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere =
+            ((x - 0.2).square() + y.square() + (z - 0.3).square()).sqrt() - 0.6;
+        let shape = Shape::<F>::from(sphere);
+
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::new(80, 60, 50),
+            tile_sizes: TileSizes::new(&[32, 8]).unwrap(),
+            ..Default::default()
+        };
+        let (depth, norm) = cfg.run(shape.clone());
+        for rect in [
+            ImageRect::new(0, 0, 80, 60),
+            ImageRect::new(11, 5, 40, 33),
+            ImageRect::new(79, 59, 1, 1),
+        ] {
+            let (d, n) = cfg.run_region(shape.clone(), rect);
+            assert_eq!(d.len(), rect.area());
+            assert_eq!(n.len(), rect.area());
+            for i in 0..rect.area() {
+                let x = rect.x as usize + i % rect.width as usize;
+                let y = rect.y as usize + i / rect.width as usize;
+                let o = y * 80 + x;
+                assert_eq!(d[i], depth[o], "depth mismatch at {x}, {y}");
+                assert_eq!(n[i], norm[o], "normal mismatch at {x}, {y}");
+            }
+
+            let mut seen = vec![0; 80 * 60];
+            cfg.run_tiles(shape.clone(), rect, |r, d, n| {
+                assert_eq!(d.len(), r.area());
+                assert_eq!(n.len(), r.area());
+                assert_eq!(r.intersection(&rect), Some(r));
+                for (i, d) in d.iter().enumerate() {
+                    let x = r.x as usize + i % r.width as usize;
+                    let y = r.y as usize + i / r.width as usize;
+                    assert_eq!(*d, depth[y * 80 + x]);
+                    seen[y * 80 + x] += 1;
                 }
-                #[test]
-                fn vm3() {
-                    $i::<$crate::vm::GenericVmFunction<3>>();
+            });
+            assert_eq!(seen.iter().sum::<usize>(), rect.area());
+            assert!(seen.iter().all(|s| *s <= 1));
+        }
+    }
+
+    fn sphere_progressive<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere = (x.square() + y.square() + z.square()).sqrt() - 0.6;
+        let shape = Shape::<F>::from(sphere);
+
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(64),
+            tile_sizes: TileSizes::new(&[32, 8]).unwrap(),
+            ..Default::default()
+        };
+        let (depth, norm) = cfg.run(shape.clone());
+
+        let mut images = vec![];
+        let out = cfg.run_progressive(shape, |d, n| {
+            images.push((d.to_vec(), n.to_vec()))
+        });
+        assert_eq!(out, (depth.clone(), norm.clone()));
+
+        // One preview, then one image per column of root tiles
+        assert_eq!(images.len(), 1 + 2 * 2);
+        assert_eq!(images.last(), Some(&(depth.clone(), norm)));
+
+        // The preview is sampled once per 8x8x8 cell, so it finds the surface
+        // at most one cell behind the full-resolution heightmap.
+        let (preview, color) = &images[0];
+        assert_ne!(*preview, depth);
+        let mut filled = 0;
+        for y in (0..64).step_by(8) {
+            for x in (0..64).step_by(8) {
+                let a = preview[(y + 4) * 64 + x + 4];
+                let b = depth[(y + 4) * 64 + x + 4];
+                if a > 0 {
+                    filled += 1;
+                    assert!(b >= a && b - a <= 8, "bad depth {a} (vs {b})");
                 }
-                #[cfg(feature = "jit")]
-                #[test]
-                fn jit() {
-                    $i::<$crate::jit::JitFunction>();
+                for j in y..y + 8 {
+                    for i in x..x + 8 {
+                        assert_eq!(preview[j * 64 + i], a);
+                    }
                 }
             }
-        };
+        }
+        assert!(filled > 0);
+
+        // Near the center, the sphere should face the camera
+        let [r, g, b] = color[36 * 64 + 36];
+        assert!(b > 200 && r < 128 && g < 128, "bad normal {:?}", [r, g, b]);
     }
 
     render_tests!(sphere_var);
+    render_tests!(sphere_perspective);
+    render_tests!(sphere_region);
+    render_tests!(sphere_progressive);
 }
@@ -0,0 +1,730 @@
+//! Rendering of scenes containing multiple shapes
+//!
+//! Every shape is rendered in a single pass over the tile hierarchy, so tile
+//! pruning is shared: a tile which is filled by one shape doesn't need to be
+//! evaluated for any shapes that it hides, and shapes are dropped from the set
+//! of active shapes as soon as they're known to be empty within a tile.
+use super::RenderHandle;
+use crate::{
+    eval::Function,
+    render::{
+        config::{copy_root_tile, root_tiles, run_workers, Queue, Tile},
+        render3d::unproject_grad,
+        ImageRect, ImageRenderConfig, VoxelRenderConfig,
+    },
+    shape::{Shape, ShapeBulkEval, ShapeTracingEval, ShapeVars},
+    types::{Grad, Interval},
+};
+use nalgebra::{Matrix4, Point2, Point3, Vector2, Vector3};
+
+/// A shape which is still active within a tile, tagged with its index
+type Active<'a, F> = (usize, &'a mut RenderHandle<F>);
+
+/// Returns `true` if shape `i` should be drawn over the current owner
+fn wins(owner: Option<usize>, i: usize) -> bool {
+    owner.is_none_or(|o| o < i)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Worker2<'a, F: Function> {
+    config: &'a ImageRenderConfig,
+
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+
+    /// Index of the topmost shape at each pixel of the current root tile
+    image: Vec<Option<usize>>,
+}
+
+impl<F: Function> Worker2<'_, F> {
+    fn render_tile_recurse(
+        &mut self,
+        shapes: &mut [Active<F>],
+        vars: &ShapeVars<f32>,
+        depth: usize,
+        tile: Tile<2>,
+    ) {
+        let tile_size = self.config.tile_sizes[depth];
+
+        let base = Point2::from(tile.corner).cast::<f32>();
+        let x = Interval::new(base.x, base.x + tile_size as f32);
+        let y = Interval::new(base.y, base.y + tile_size as f32);
+        let z = Interval::new(0.0, 0.0);
+
+        // Walk shapes from top to bottom, so that a filled shape lets us skip
+        // everything underneath it.
+        let mut next: Vec<Active<F>> = vec![];
+        for (i, shape) in shapes.iter_mut().rev() {
+            let (v, trace) = self
+                .eval_interval
+                .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, vars)
+                .unwrap();
+            if v.upper() < 0.0 {
+                for y in 0..tile_size {
+                    let o = self
+                        .config
+                        .tile_sizes
+                        .pixel_offset(tile.add(Vector2::new(0, y)));
+                    for p in &mut self.image[o..][..tile_size] {
+                        if wins(*p, *i) {
+                            *p = Some(*i);
+                        }
+                    }
+                }
+                break;
+            } else if v.lower() > 0.0 {
+                continue;
+            }
+            let sub_tape = if let Some(trace) = trace.as_ref() {
+                shape.simplify(
+                    trace,
+                    &mut self.workspace,
+                    &mut self.shape_storage,
+                    &mut self.tape_storage,
+                )
+            } else {
+                &mut **shape
+            };
+            next.push((*i, sub_tape));
+        }
+        if next.is_empty() {
+            return;
+        }
+        // Restore bottom-to-top order, which the loop above expects
+        next.reverse();
+
+        if let Some(next_tile_size) = self.config.tile_sizes.get(depth + 1) {
+            let n = tile_size / next_tile_size;
+            for j in 0..n {
+                for i in 0..n {
+                    self.render_tile_recurse(
+                        &mut next,
+                        vars,
+                        depth + 1,
+                        Tile::new(
+                            tile.corner + Vector2::new(i, j) * next_tile_size,
+                        ),
+                    );
+                }
+            }
+        } else {
+            self.render_tile_pixels(&mut next, vars, tile_size, tile);
+        }
+    }
+
+    fn render_tile_pixels(
+        &mut self,
+        shapes: &mut [Active<F>],
+        vars: &ShapeVars<f32>,
+        tile_size: usize,
+        tile: Tile<2>,
+    ) {
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        for j in 0..tile_size {
+            for i in 0..tile_size {
+                self.x.push((tile.corner[0] + i) as f32);
+                self.y.push((tile.corner[1] + j) as f32);
+                self.z.push(0.0);
+            }
+        }
+
+        for (s, shape) in shapes.iter_mut() {
+            let out = self
+                .eval_float_slice
+                .eval_v(
+                    shape.f_tape(&mut self.tape_storage),
+                    &self.x,
+                    &self.y,
+                    &self.z,
+                    vars,
+                )
+                .unwrap();
+
+            let mut index = 0;
+            for j in 0..tile_size {
+                let o = self
+                    .config
+                    .tile_sizes
+                    .pixel_offset(tile.add(Vector2::new(0, j)));
+                for p in &mut self.image[o..][..tile_size] {
+                    if out[index] < 0.0 && wins(*p, *s) {
+                        *p = Some(*s);
+                    }
+                    index += 1;
+                }
+            }
+        }
+    }
+}
+
+fn worker2<F: Function>(
+    mut shapes: Vec<RenderHandle<F>>,
+    vars: &ShapeVars<f32>,
+    queue: &Queue<2>,
+    config: &ImageRenderConfig,
+    mut emit: impl FnMut(Tile<2>, Vec<Option<usize>>),
+) {
+    let mut w: Worker2<F> = Worker2 {
+        config,
+        x: vec![],
+        y: vec![],
+        z: vec![],
+        eval_float_slice: Default::default(),
+        eval_interval: Default::default(),
+        tape_storage: vec![],
+        shape_storage: vec![],
+        workspace: Default::default(),
+        image: vec![],
+    };
+
+    while let Some(tile) = queue.next() {
+        w.image = vec![None; config.tile_sizes[0].pow(2)];
+        let mut active: Vec<Active<F>> =
+            shapes.iter_mut().enumerate().collect();
+        w.render_tile_recurse(&mut active, vars, 0, tile);
+        emit(tile, std::mem::take(&mut w.image));
+    }
+}
+
+/// Renders a scene of shapes into a 2D image
+///
+/// Each pixel contains the index of the topmost (i.e. last) shape covering it.
+pub(crate) fn render2d<F: Function>(
+    shapes: &[Shape<F>],
+    vars: &ShapeVars<f32>,
+    config: &ImageRenderConfig,
+) -> Vec<Option<usize>> {
+    // Convert to a 4x4 matrix and apply to the shapes
+    let mat = config.mat();
+    let mat = mat.insert_row(2, 0.0);
+    let mat = mat.insert_column(2, 0.0);
+    let handles: Vec<_> = shapes
+        .iter()
+        .map(|s| {
+            let mut rh = RenderHandle::new(s.clone().apply_transform(mat));
+            let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+            rh
+        })
+        .collect();
+
+    let t = config.tile_sizes[0];
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let tiles = root_tiles(&ImageRect::from(config.image_size), t);
+
+    let mut image = vec![None; width * height];
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        handles,
+        |handles, queue, emit| {
+            worker2::<F>(handles, vars, queue, config, |t, d| emit((t, d)))
+        },
+        |(tile, data)| {
+            copy_root_tile(&mut image, width, height, tile.corner, t, &data)
+        },
+    );
+    image
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Worker3<'a, F: Function> {
+    config: &'a VoxelRenderConfig,
+
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+
+    /// Pixel offsets of the columns being evaluated
+    columns: Vec<usize>,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+    eval_interval: ShapeTracingEval<F::IntervalEval>,
+
+    tape_storage: Vec<F::TapeStorage>,
+    shape_storage: Vec<F::Storage>,
+    workspace: F::Workspace,
+
+    /// Perspective transform in screen coordinates, used to correct normals
+    perspective: Option<Matrix4<f32>>,
+
+    /// Depth image for the current root tile
+    depth: Vec<u32>,
+
+    /// Index of the shape visible at each pixel of the current root tile
+    owner: Vec<Option<usize>>,
+
+    /// Marks pixels whose depth was found by per-voxel evaluation
+    ///
+    /// Pixels which were filled by an interval evaluation don't get normals,
+    /// matching the single-shape renderer.
+    surface: Vec<bool>,
+}
+
+impl<F: Function> Worker3<'_, F> {
+    fn render_tile_recurse(
+        &mut self,
+        shapes: &mut [Active<F>],
+        vars: &ShapeVars<f32>,
+        depth: usize,
+        tile: Tile<3>,
+    ) {
+        // Early exit if every single pixel is filled, and no active shape
+        // could win a tie with the current owner
+        let tile_size = self.config.tile_sizes[depth];
+        let fill_z = (tile.corner[2] + tile_size + 1).try_into().unwrap();
+        let top = shapes.last().map(|(i, _)| *i).unwrap_or(0);
+        if (0..tile_size).all(|y| {
+            let i = self.config.tile_row_offset(tile, y);
+            (0..tile_size).all(|x| {
+                let (d, o) = (self.depth[i + x], self.owner[i + x]);
+                d > fill_z || (d == fill_z && !wins(o, top))
+            })
+        }) {
+            return;
+        }
+
+        let base = Point3::from(tile.corner).cast::<f32>();
+        let x = Interval::new(base.x, base.x + tile_size as f32);
+        let y = Interval::new(base.y, base.y + tile_size as f32);
+        let z = Interval::new(base.z, base.z + tile_size as f32);
+
+        // Later shapes win ties, so we check them first
+        let mut next: Vec<Active<F>> = vec![];
+        for (i, shape) in shapes.iter_mut().rev() {
+            let (v, trace) = self
+                .eval_interval
+                .eval_v(shape.i_tape(&mut self.tape_storage), x, y, z, vars)
+                .unwrap();
+            if v.upper() < 0.0 {
+                // Shapes underneath this one are hidden within the tile, but
+                // the later shapes in `next` may still win ties at its top.
+                for y in 0..tile_size {
+                    let o = self.config.tile_row_offset(tile, y);
+                    for x in 0..tile_size {
+                        let d = self.depth[o + x];
+                        if d < fill_z
+                            || (d == fill_z && wins(self.owner[o + x], *i))
+                        {
+                            self.depth[o + x] = fill_z;
+                            self.owner[o + x] = Some(*i);
+                            self.surface[o + x] = false;
+                        }
+                    }
+                }
+                break;
+            } else if v.lower() > 0.0 {
+                continue;
+            }
+            let sub_tape = if let Some(trace) = trace.as_ref() {
+                shape.simplify(
+                    trace,
+                    &mut self.workspace,
+                    &mut self.shape_storage,
+                    &mut self.tape_storage,
+                )
+            } else {
+                &mut **shape
+            };
+            next.push((*i, sub_tape));
+        }
+        if next.is_empty() {
+            return;
+        }
+        // Restore bottom-to-top order, which the loop above expects
+        next.reverse();
+
+        if let Some(next_tile_size) = self.config.tile_sizes.get(depth + 1) {
+            let n = tile_size / next_tile_size;
+            for j in 0..n {
+                for i in 0..n {
+                    for k in (0..n).rev() {
+                        self.render_tile_recurse(
+                            &mut next,
+                            vars,
+                            depth + 1,
+                            Tile::new(
+                                tile.corner
+                                    + Vector3::new(i, j, k) * next_tile_size,
+                            ),
+                        );
+                    }
+                }
+            }
+        } else {
+            self.render_tile_pixels(&mut next, vars, tile_size, tile);
+        }
+    }
+
+    fn render_tile_pixels(
+        &mut self,
+        shapes: &mut [Active<F>],
+        vars: &ShapeVars<f32>,
+        tile_size: usize,
+        tile: Tile<3>,
+    ) {
+        let zmax: u32 = (tile.corner[2] + tile_size).try_into().unwrap();
+        for (s, shape) in shapes.iter_mut() {
+            self.x.clear();
+            self.y.clear();
+            self.z.clear();
+            self.columns.clear();
+            for j in 0..tile_size {
+                for i in 0..tile_size {
+                    let o = self
+                        .config
+                        .tile_sizes
+                        .pixel_offset(tile.add(Vector2::new(i, j)));
+
+                    // Skip pixels which are behind the image
+                    let d = self.depth[o];
+                    if d > zmax || (d == zmax && !wins(self.owner[o], *s)) {
+                        continue;
+                    }
+                    for k in (0..tile_size).rev() {
+                        self.x.push((tile.corner[0] + i) as f32);
+                        self.y.push((tile.corner[1] + j) as f32);
+                        self.z.push((tile.corner[2] + k) as f32);
+                    }
+                    self.columns.push(o);
+                }
+            }
+            if self.columns.is_empty() {
+                continue;
+            }
+
+            let out = self
+                .eval_float_slice
+                .eval_v(
+                    shape.f_tape(&mut self.tape_storage),
+                    &self.x,
+                    &self.y,
+                    &self.z,
+                    vars,
+                )
+                .unwrap();
+
+            for (col, o) in out.chunks(tile_size).zip(&self.columns) {
+                if let Some(k) = col.iter().position(|d| *d < 0.0) {
+                    // Voxels are packed front-to-back
+                    let z =
+                        (tile.corner[2] + tile_size - k).try_into().unwrap();
+                    let d = self.depth[*o];
+                    if z > d || (z == d && wins(self.owner[*o], *s)) {
+                        self.depth[*o] = z;
+                        self.owner[*o] = Some(*s);
+                        self.surface[*o] = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Computes normals for every filled pixel in the current root tile
+    fn normals(
+        &mut self,
+        shapes: &mut [RenderHandle<F>],
+        vars: &ShapeVars<f32>,
+        corner: Point2<usize>,
+    ) -> Vec<[u8; 3]> {
+        let tile_size = self.config.tile_sizes[0];
+        let mut color = vec![[0; 3]; self.depth.len()];
+        for (s, shape) in shapes.iter_mut().enumerate() {
+            let mut xg = vec![];
+            let mut yg = vec![];
+            let mut zg = vec![];
+            let mut index = vec![];
+            for (o, d) in self.depth.iter().enumerate() {
+                if self.owner[o] == Some(s) && self.surface[o] {
+                    let x = (corner.x + o % tile_size) as f32;
+                    let y = (corner.y + o / tile_size) as f32;
+                    let z = (*d - 1) as f32;
+                    xg.push(Grad::new(x, 1.0, 0.0, 0.0));
+                    yg.push(Grad::new(y, 0.0, 1.0, 0.0));
+                    zg.push(Grad::new(z, 0.0, 0.0, 1.0));
+                    index.push(o);
+                }
+            }
+            if index.is_empty() {
+                continue;
+            }
+            let out = self
+                .eval_grad_slice
+                .eval_v(
+                    shape.g_tape(&mut self.tape_storage),
+                    &xg,
+                    &yg,
+                    &zg,
+                    vars,
+                )
+                .unwrap();
+            for (i, o) in index.iter().enumerate() {
+                let mut g = out[i];
+                if let Some(mat) = self.perspective {
+                    let p = Point3::new(xg[i].v, yg[i].v, zg[i].v);
+                    g = unproject_grad(&mat, p, g);
+                }
+                color[*o] = g.to_rgb().unwrap_or([255, 0, 0]);
+            }
+        }
+        color
+    }
+}
+
+/// Rendered data for a single root tile
+struct Image3 {
+    depth: Vec<u32>,
+    color: Vec<[u8; 3]>,
+    owner: Vec<Option<usize>>,
+}
+
+fn worker3<F: Function>(
+    mut shapes: Vec<RenderHandle<F>>,
+    vars: &ShapeVars<f32>,
+    queue: &Queue<2>,
+    config: &VoxelRenderConfig,
+    mut emit: impl FnMut(Tile<2>, Image3),
+) {
+    let tile_size = config.tile_sizes[0];
+    let mut w: Worker3<F> = Worker3 {
+        config,
+        x: vec![],
+        y: vec![],
+        z: vec![],
+        columns: vec![],
+        eval_float_slice: Default::default(),
+        eval_grad_slice: Default::default(),
+        eval_interval: Default::default(),
+        tape_storage: vec![],
+        shape_storage: vec![],
+        workspace: Default::default(),
+        perspective: config.perspective_correction(),
+        depth: vec![],
+        owner: vec![],
+        surface: vec![],
+    };
+
+    let depth = config.image_size.depth() as usize;
+    while let Some(tile) = queue.next() {
+        w.depth = vec![0; tile_size.pow(2)];
+        w.owner = vec![None; tile_size.pow(2)];
+        w.surface = vec![false; tile_size.pow(2)];
+        for k in (0..depth.div_ceil(tile_size)).rev() {
+            let mut active: Vec<Active<F>> =
+                shapes.iter_mut().enumerate().collect();
+            let t = Tile::new(Point3::new(
+                tile.corner.x,
+                tile.corner.y,
+                k * tile_size,
+            ));
+            w.render_tile_recurse(&mut active, vars, 0, t);
+        }
+        let color = w.normals(&mut shapes, vars, tile.corner);
+        let depth = std::mem::take(&mut w.depth);
+        let owner = std::mem::take(&mut w.owner);
+        emit(
+            tile,
+            Image3 {
+                depth,
+                color,
+                owner,
+            },
+        );
+    }
+}
+
+/// Renders a scene of shapes into a 3D image
+///
+/// Returns a tuple of heightmap, RGB normals, and the index of the visible
+/// shape at each pixel.
+pub(crate) fn render3d<F: Function>(
+    shapes: &[Shape<F>],
+    vars: &ShapeVars<f32>,
+    config: &VoxelRenderConfig,
+) -> (Vec<u32>, Vec<[u8; 3]>, Vec<Option<usize>>) {
+    let mat = config.mat();
+    let handles: Vec<_> = shapes
+        .iter()
+        .map(|s| {
+            let mut rh = RenderHandle::new(s.clone().apply_transform(mat));
+            let _ = rh.i_tape(&mut vec![]); // populate i_tape before cloning
+            rh
+        })
+        .collect();
+
+    let t = config.tile_sizes[0];
+    let width = config.image_size.width() as usize;
+    let height = config.image_size.height() as usize;
+    let tiles = root_tiles(&ImageRect::from(config.image_size), t);
+
+    let mut image_depth = vec![0; width * height];
+    let mut image_color = vec![[0; 3]; width * height];
+    let mut image_owner = vec![None; width * height];
+    run_workers(
+        config.threads,
+        tiles.into_iter().map(Tile::new).collect(),
+        handles,
+        |handles, queue, emit| {
+            worker3::<F>(handles, vars, queue, config, |t, d| emit((t, d)))
+        },
+        |(tile, data)| {
+            let c = tile.corner;
+            copy_root_tile(&mut image_depth, width, height, c, t, &data.depth);
+            copy_root_tile(&mut image_color, width, height, c, t, &data.color);
+            copy_root_tile(&mut image_owner, width, height, c, t, &data.owner);
+        },
+    );
+    (image_depth, image_color, image_owner)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        eval::MathFunction,
+        render::{BitRenderMode, ImageSize, VoxelSize},
+    };
+
+    fn circle(x: f32, y: f32, r: f32) -> Tree {
+        let (tx, ty, _) = Tree::axes();
+        ((tx - x).square() + (ty - y).square()).sqrt() - r
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, r: f32) -> Tree {
+        let (tx, ty, tz) = Tree::axes();
+        ((tx - x).square() + (ty - y).square() + (tz - z).square()).sqrt() - r
+    }
+
+    fn scene_2d<F: Function + MathFunction>() {
+        let shapes = [
+            (Shape::<F>::from(circle(-0.3, 0.0, 0.5)), 'a'),
+            (Shape::<F>::from(circle(0.3, 0.0, 0.5)), 'b'),
+            (Shape::<F>::from(circle(0.0, 0.8, 0.1)), 'c'),
+        ];
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(80, 64),
+            ..Default::default()
+        };
+        let out = cfg.run_scene(&shapes);
+
+        // Compare against individual renders, composited by hand
+        let mut expected = vec![None; out.len()];
+        for (shape, c) in &shapes {
+            let image = cfg.run::<_, BitRenderMode>(shape.clone());
+            for (e, p) in expected.iter_mut().zip(image) {
+                if p {
+                    *e = Some(*c);
+                }
+            }
+        }
+        assert_eq!(out, expected);
+        for c in ['a', 'b', 'c'] {
+            assert!(out.contains(&Some(c)), "missing {c}");
+        }
+    }
+
+    fn scene_3d<F: Function + MathFunction>() {
+        let shapes = [
+            (Shape::<F>::from(sphere(-0.3, 0.0, 0.0, 0.5)), 1u8),
+            (Shape::<F>::from(sphere(0.3, 0.0, 0.2, 0.5)), 2),
+            (Shape::<F>::from(sphere(0.0, 0.0, -0.8, 0.1)), 3),
+        ];
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::new(80, 64, 64),
+            ..Default::default()
+        };
+        let (depth, color, owner) = cfg.run_scene(&shapes);
+
+        // Compare against individual renders, z-buffered by hand (with later
+        // shapes winning ties)
+        let mut expected_depth = vec![0; depth.len()];
+        let mut expected_color = vec![[0; 3]; depth.len()];
+        let mut expected_owner = vec![None; depth.len()];
+        for (shape, c) in &shapes {
+            let (d, n) = cfg.run(shape.clone());
+            for i in 0..d.len() {
+                if d[i] > 0 && d[i] >= expected_depth[i] {
+                    expected_depth[i] = d[i];
+                    expected_color[i] = n[i];
+                    expected_owner[i] = Some(*c);
+                }
+            }
+        }
+        assert_eq!(depth, expected_depth);
+        assert_eq!(color, expected_color);
+        assert_eq!(owner, expected_owner);
+
+        // The two large spheres are visible, but the small one is hidden
+        assert!(owner.contains(&Some(1)));
+        assert!(owner.contains(&Some(2)));
+        assert!(!owner.contains(&Some(3)));
+    }
+
+    fn scene_nested<F: Function + MathFunction>() {
+        // The later shape is inside the earlier one, so there are tiles which
+        // are filled by both (and must be drawn with the later shape).
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::new(512, 512),
+            ..Default::default()
+        };
+        let shapes = [
+            (Shape::<F>::from(circle(0.0, 0.0, 0.8)), 1u8),
+            (Shape::<F>::from(circle(0.1, 0.0, 0.5)), 2),
+        ];
+        let out = cfg.run_scene(&shapes);
+        let inner = cfg.run::<_, BitRenderMode>(shapes[1].0.clone());
+        for (o, p) in out.iter().zip(inner) {
+            if p {
+                assert_eq!(*o, Some(2));
+            }
+        }
+
+        // In 3D, a later shape whose top matches an earlier one wins the tie,
+        // even if the earlier shape fills the tile
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::new(128, 128, 128),
+            ..Default::default()
+        };
+        let (x, _, z) = Tree::axes();
+        let shapes = [
+            (Shape::<F>::from(sphere(0.0, 0.0, 0.0, 0.8)), 1u8),
+            (Shape::<F>::from(sphere(0.1, 0.0, -0.1, 0.5)), 2),
+            (Shape::<F>::from(z.clone() - 0.3), 3),
+            // Same as above, but with looser interval bounds
+            (Shape::<F>::from((z - 0.3) + (x.square() - x.square())), 4),
+        ];
+        let (depth, _color, owner) = cfg.run_scene(&shapes);
+        let mut expected_depth = vec![0; depth.len()];
+        let mut expected_owner = vec![None; depth.len()];
+        for (shape, c) in &shapes {
+            let (d, _) = cfg.run(shape.clone());
+            for i in 0..d.len() {
+                if d[i] > 0 && d[i] >= expected_depth[i] {
+                    expected_depth[i] = d[i];
+                    expected_owner[i] = Some(*c);
+                }
+            }
+        }
+        assert_eq!(depth, expected_depth);
+        assert_eq!(owner, expected_owner);
+        assert!(owner.contains(&Some(4)));
+    }
+
+    render_tests!(scene_2d);
+    render_tests!(scene_3d);
+    render_tests!(scene_nested);
+}
@@ -0,0 +1,675 @@
+//! Shading of 3D renders (lighting, shadows, and ambient occlusion)
+use super::RenderHandle;
+use crate::{
+    eval::Function,
+    render::{ThreadCount, VoxelRenderConfig},
+    shape::{Shape, ShapeBulkEval, ShapeVars},
+    types::Grad,
+};
+use nalgebra::{Point3, Vector3};
+
+/// A directional light
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    /// Direction pointing towards the light, in camera coordinates
+    ///
+    /// Camera coordinates are aligned with the screen: `+x` points right, `+y`
+    /// points up, and `+z` points out of the screen (towards the viewer).
+    pub direction: Vector3<f32>,
+
+    /// Brightness of the light, where `1.0` is full intensity
+    pub intensity: f32,
+}
+
+/// Strategy for computing ambient occlusion
+#[derive(Copy, Clone, Debug, Default)]
+pub enum Occlusion {
+    /// No ambient occlusion
+    #[default]
+    None,
+
+    /// Screen-space ambient occlusion, computed from the depth buffer
+    ///
+    /// This is cheap, but only sees geometry which is visible on screen.
+    ScreenSpace {
+        /// Sampling radius, in pixels
+        radius: usize,
+        /// Number of samples taken around each pixel
+        samples: usize,
+    },
+
+    /// Ambient occlusion computed by sampling the implicit function along the
+    /// surface normal
+    ///
+    /// This assumes that the function is roughly a distance field.
+    Sdf {
+        /// Maximum sampling distance, in camera units (where the view's ±1
+        /// region spans 2 units)
+        distance: f32,
+        /// Number of samples along the normal
+        steps: usize,
+    },
+}
+
+/// Settings for shading a 3D render
+///
+/// Shading takes the depth buffer from [`VoxelRenderConfig::run`] and produces
+/// a lit RGB image.  Surface normals are recomputed from the shape (rather
+/// than reusing the normal-encoded image, which discards their signs).
+#[derive(Clone, Debug)]
+pub struct ShadeConfig {
+    /// Directional lights illuminating the scene
+    pub lights: Vec<Light>,
+
+    /// Ambient light intensity
+    pub ambient: f32,
+
+    /// Base color of the surface
+    pub color: [u8; 3],
+
+    /// Trace hard shadows through the implicit function
+    pub shadows: bool,
+
+    /// Ambient occlusion strategy
+    pub occlusion: Occlusion,
+}
+
+impl Default for ShadeConfig {
+    fn default() -> Self {
+        Self {
+            lights: vec![Light {
+                direction: Vector3::new(-1.0, 1.0, 1.0),
+                intensity: 0.8,
+            }],
+            ambient: 0.2,
+            color: [255; 3],
+            shadows: false,
+            occlusion: Occlusion::None,
+        }
+    }
+}
+
+impl ShadeConfig {
+    /// Shades a depth buffer rendered with the given configuration
+    ///
+    /// The shape must be the same one which was used to produce `depth`.
+    /// Pixels without any depth are left black.
+    pub fn run<F: Function>(
+        &self,
+        shape: Shape<F>,
+        render: &VoxelRenderConfig,
+        depth: &[u32],
+    ) -> Vec<[u8; 3]> {
+        self.run_with_vars::<F>(shape, &ShapeVars::new(), render, depth)
+    }
+
+    /// Shades a depth buffer rendered with the given configuration and
+    /// variables
+    pub fn run_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        render: &VoxelRenderConfig,
+        depth: &[u32],
+    ) -> Vec<[u8; 3]> {
+        shade(shape, vars, render, self, depth)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A single visible point on the surface
+struct Sample {
+    /// Offset into the image
+    index: usize,
+    /// Position in camera coordinates
+    pos: Point3<f32>,
+    /// Size of a voxel at this position, in camera units
+    voxel: f32,
+    /// Screen-space ambient occlusion (1 if unoccluded)
+    ao: f32,
+}
+
+/// Axis-aligned bounds (in camera coordinates) of the render volume
+#[derive(Copy, Clone)]
+struct Bounds {
+    min: Point3<f32>,
+    max: Point3<f32>,
+}
+
+impl Bounds {
+    fn contains(&self, p: &Point3<f32>) -> bool {
+        (0..3).all(|i| p[i] >= self.min[i] && p[i] <= self.max[i])
+    }
+}
+
+/// Maximum number of steps when tracing shadow rays
+const MAX_SHADOW_STEPS: usize = 512;
+
+/// Maximum step size when tracing shadow rays, in voxels
+///
+/// This prevents us from stepping through geometry if the implicit surface
+/// isn't a true distance field.
+const MAX_SHADOW_STEP: f32 = 16.0;
+
+struct Worker<'a, F: Function> {
+    config: &'a ShadeConfig,
+    vars: &'a ShapeVars<f32>,
+    bounds: Bounds,
+
+    /// Scale from camera to model units, used to normalize distances
+    scale: f32,
+
+    eval_float_slice: ShapeBulkEval<F::FloatSliceEval>,
+    eval_grad_slice: ShapeBulkEval<F::GradSliceEval>,
+
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+}
+
+impl<F: Function> Worker<'_, F> {
+    /// Evaluates the shape at the positions stored in `self.{x,y,z}`
+    fn eval(&mut self, shape: &mut RenderHandle<F>) -> Vec<f32> {
+        self.eval_float_slice
+            .eval_v(
+                shape.f_tape(&mut vec![]),
+                &self.x,
+                &self.y,
+                &self.z,
+                self.vars,
+            )
+            .unwrap()
+            .to_vec()
+    }
+
+    /// Loads positions into `self.{x,y,z}`
+    fn load(&mut self, pts: impl Iterator<Item = Point3<f32>>) {
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        for p in pts {
+            self.x.push(p.x);
+            self.y.push(p.y);
+            self.z.push(p.z);
+        }
+    }
+
+    fn normals(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        samples: &[Sample],
+    ) -> Vec<Vector3<f32>> {
+        let xg: Vec<_> = samples
+            .iter()
+            .map(|s| Grad::new(s.pos.x, 1.0, 0.0, 0.0))
+            .collect();
+        let yg: Vec<_> = samples
+            .iter()
+            .map(|s| Grad::new(s.pos.y, 0.0, 1.0, 0.0))
+            .collect();
+        let zg: Vec<_> = samples
+            .iter()
+            .map(|s| Grad::new(s.pos.z, 0.0, 0.0, 1.0))
+            .collect();
+        let out = self
+            .eval_grad_slice
+            .eval_v(shape.g_tape(&mut vec![]), &xg, &yg, &zg, self.vars)
+            .unwrap();
+        out.iter()
+            .map(|g| {
+                Vector3::new(g.dx, g.dy, g.dz)
+                    .try_normalize(0.0)
+                    .unwrap_or(Vector3::z())
+            })
+            .collect()
+    }
+
+    /// Returns `true` for each sample which is lit by the given light
+    fn shadows(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        samples: &[Sample],
+        normals: &[Vector3<f32>],
+        dir: Vector3<f32>,
+    ) -> Vec<bool> {
+        let mut lit = vec![true; samples.len()];
+
+        // Start slightly above the surface, to avoid self-shadowing
+        let mut active: Vec<(usize, Point3<f32>)> = samples
+            .iter()
+            .zip(normals)
+            .enumerate()
+            .filter(|(_, (_, n))| n.dot(&dir) > 0.0)
+            .map(|(i, (s, n))| (i, s.pos + (n + dir) * s.voxel * 2.0))
+            .collect();
+
+        for _ in 0..MAX_SHADOW_STEPS {
+            active.retain(|(_, p)| self.bounds.contains(p));
+            if active.is_empty() {
+                break;
+            }
+            self.load(active.iter().map(|(_, p)| *p));
+            let out = self.eval(shape);
+            let mut j = 0;
+            active.retain_mut(|(i, p)| {
+                let d = out[j] / self.scale;
+                j += 1;
+                if d < 0.0 {
+                    lit[*i] = false;
+                    false
+                } else {
+                    let v = samples[*i].voxel;
+                    *p += dir * d.clamp(v, v * MAX_SHADOW_STEP);
+                    true
+                }
+            });
+        }
+        lit
+    }
+
+    /// Computes ambient occlusion by sampling the distance field
+    fn sdf_occlusion(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        samples: &[Sample],
+        normals: &[Vector3<f32>],
+        distance: f32,
+        steps: usize,
+    ) -> Vec<f32> {
+        let mut occ = vec![0.0; samples.len()];
+        let mut total = 0.0;
+        let mut weight = 1.0;
+        for k in 1..=steps {
+            let h = distance * k as f32 / steps as f32;
+            self.load(samples.iter().zip(normals).map(|(s, n)| s.pos + n * h));
+            let out = self.eval(shape);
+            for (o, d) in occ.iter_mut().zip(&out) {
+                *o += weight * ((h - d / self.scale) / h).clamp(0.0, 1.0);
+            }
+            total += weight;
+            weight *= 0.5;
+        }
+        occ.into_iter().map(|o| 1.0 - o / total).collect()
+    }
+
+    fn run(
+        &mut self,
+        shape: &mut RenderHandle<F>,
+        samples: &[Sample],
+    ) -> Vec<[u8; 3]> {
+        if samples.is_empty() {
+            return vec![];
+        }
+        let normals = self.normals(shape, samples);
+        let mut ao: Vec<f32> = samples.iter().map(|s| s.ao).collect();
+        if let Occlusion::Sdf { distance, steps } = self.config.occlusion {
+            let occ =
+                self.sdf_occlusion(shape, samples, &normals, distance, steps);
+            for (a, o) in ao.iter_mut().zip(occ) {
+                *a *= o;
+            }
+        }
+
+        let mut light: Vec<f32> =
+            ao.iter().map(|a| a * self.config.ambient).collect();
+        for l in &self.config.lights {
+            let Some(dir) = l.direction.try_normalize(0.0) else {
+                continue;
+            };
+            let lit = if self.config.shadows {
+                self.shadows(shape, samples, &normals, dir)
+            } else {
+                vec![true; samples.len()]
+            };
+            for ((v, n), lit) in light.iter_mut().zip(&normals).zip(lit) {
+                if lit {
+                    *v += l.intensity * n.dot(&dir).max(0.0);
+                }
+            }
+        }
+
+        light
+            .into_iter()
+            .map(|v| {
+                self.config
+                    .color
+                    .map(|c| (c as f32 * v).clamp(0.0, 255.0) as u8)
+            })
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes screen-space ambient occlusion for a single pixel
+fn screen_space_occlusion(
+    depth: &[u32],
+    width: usize,
+    height: usize,
+    (x, y): (usize, usize),
+    radius: usize,
+    samples: usize,
+) -> f32 {
+    let d = depth[y * width + x] as f32;
+    let mut occ = 0.0;
+    for s in 0..samples {
+        // Alternate between the full and half radius
+        let r = if s % 2 == 0 {
+            radius
+        } else {
+            radius.div_ceil(2)
+        } as f32;
+        let angle = std::f32::consts::TAU * s as f32 / samples as f32;
+        let nx = x as f32 + r * angle.cos();
+        let ny = y as f32 + r * angle.sin();
+        if nx < 0.0 || ny < 0.0 {
+            continue;
+        }
+        let (nx, ny) = (nx.round() as usize, ny.round() as usize);
+        if nx >= width || ny >= height {
+            continue;
+        }
+        let n = depth[ny * width + nx];
+        if n == 0 {
+            continue;
+        }
+        // Ignore large depth discontinuities, to avoid dark halos
+        let h = n as f32 - d;
+        if h > 0.0 && h < 4.0 * r {
+            occ += (h / r).min(1.0);
+        }
+    }
+    1.0 - occ / samples.max(1) as f32
+}
+
+fn shade<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    render: &VoxelRenderConfig,
+    config: &ShadeConfig,
+    depth: &[u32],
+) -> Vec<[u8; 3]> {
+    run_samples(shape, vars, render, config, depth, [0; 3], |w, rh, s| {
+        w.run(rh, s)
+    })
+}
+
+/// Computes surface normals (in camera coordinates) for a depth buffer
+///
+/// Pixels without any depth have a zero normal.
+pub(crate) fn normals<F: Function>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    render: &VoxelRenderConfig,
+    depth: &[u32],
+) -> Vec<[f32; 3]> {
+    let config = ShadeConfig {
+        occlusion: Occlusion::None,
+        ..Default::default()
+    };
+    run_samples(shape, vars, render, &config, depth, [0.0; 3], |w, rh, s| {
+        w.normals(rh, s).into_iter().map(|n| n.into()).collect()
+    })
+}
+
+/// Runs a per-sample function on every visible pixel of a depth buffer
+///
+/// Pixels without any depth are set to `empty`.
+fn run_samples<F: Function, T: Copy + Send>(
+    shape: Shape<F>,
+    vars: &ShapeVars<f32>,
+    render: &VoxelRenderConfig,
+    config: &ShadeConfig,
+    depth: &[u32],
+    empty: T,
+    f: impl Fn(&mut Worker<F>, &mut RenderHandle<F>, &[Sample]) -> Vec<T> + Sync,
+) -> Vec<T> {
+    let width = render.image_size.width() as usize;
+    let height = render.image_size.height() as usize;
+    assert_eq!(depth.len(), width * height);
+
+    // Build a transform from screen to camera coordinates
+    let screen_to_world = render.image_size.screen_to_world();
+    let perspective = render.view.perspective().map(|p| p.matrix());
+    let to_camera = |p: Point3<f32>| {
+        let p = screen_to_world.transform_point(&p);
+        match &perspective {
+            Some(m) => m.transform_point(&p),
+            None => p,
+        }
+    };
+
+    let mut min = Point3::from([f32::INFINITY; 3]);
+    let mut max = Point3::from([f32::NEG_INFINITY; 3]);
+    for i in 0..8 {
+        let p = to_camera(Point3::new(
+            if i & 1 == 0 { 0.0 } else { width as f32 },
+            if i & 2 == 0 { 0.0 } else { height as f32 },
+            if i & 4 == 0 {
+                0.0
+            } else {
+                render.image_size.depth() as f32
+            },
+        ));
+        min = min.inf(&p);
+        max = max.sup(&p);
+    }
+    let bounds = Bounds { min, max };
+
+    let mut samples = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let d = depth[index];
+            if d == 0 {
+                continue;
+            }
+            let p = Point3::new(x as f32, y as f32, (d - 1) as f32);
+            let pos = to_camera(p);
+            let voxel = (to_camera(p + Vector3::x()) - pos).norm();
+            let ao = match config.occlusion {
+                Occlusion::ScreenSpace { radius, samples } => {
+                    screen_space_occlusion(
+                        depth,
+                        width,
+                        height,
+                        (x, y),
+                        radius,
+                        samples,
+                    )
+                }
+                Occlusion::None | Occlusion::Sdf { .. } => 1.0,
+            };
+            samples.push(Sample {
+                index,
+                pos,
+                voxel,
+                ao,
+            });
+        }
+    }
+
+    let shape = shape.apply_transform(render.view.camera_to_model());
+    let mut rh = RenderHandle::new(shape);
+    let _ = rh.f_tape(&mut vec![]); // populate tapes before cloning
+    let _ = rh.g_tape(&mut vec![]);
+
+    let new_worker = || Worker::<F> {
+        config,
+        vars,
+        bounds,
+        scale: render.view.scale(),
+        eval_float_slice: Default::default(),
+        eval_grad_slice: Default::default(),
+        x: vec![],
+        y: vec![],
+        z: vec![],
+    };
+
+    let out: Vec<T> = match render.threads {
+        ThreadCount::One => f(&mut new_worker(), &mut rh, &samples),
+
+        #[cfg(not(target_arch = "wasm32"))]
+        ThreadCount::Many(threads) => std::thread::scope(|s| {
+            let chunk_size = samples.len().div_ceil(threads.get()).max(1);
+            let mut handles = vec![];
+            for chunk in samples.chunks(chunk_size) {
+                let mut rh = rh.clone();
+                let mut w = new_worker();
+                let f = &f;
+                handles.push(s.spawn(move || f(&mut w, &mut rh, chunk)));
+            }
+            let mut out = vec![];
+            for h in handles {
+                out.extend(h.join().unwrap());
+            }
+            out
+        }),
+    };
+
+    let mut image = vec![empty; width * height];
+    for (s, c) in samples.iter().zip(out) {
+        image[s.index] = c;
+    }
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::Tree, eval::MathFunction, render::VoxelSize};
+
+    fn brightness(c: [u8; 3]) -> u32 {
+        c.iter().map(|c| *c as u32).sum()
+    }
+
+    fn shade_sphere<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere = (x.square() + y.square() + z.square()).sqrt() - 0.5;
+        let shape = Shape::<F>::from(sphere);
+
+        let size = 64;
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(size),
+            ..Default::default()
+        };
+        let (depth, _) = cfg.run(shape.clone());
+
+        let shade = ShadeConfig {
+            lights: vec![Light {
+                direction: Vector3::new(-1.0, 0.0, 0.0),
+                intensity: 1.0,
+            }],
+            ambient: 0.0,
+            ..Default::default()
+        };
+        let image = shade.run(shape, &cfg, &depth);
+
+        // Background pixels are black
+        assert_eq!(image[0], [0, 0, 0]);
+
+        // The left side of the sphere is lit, and the right side is dark
+        let row = size as usize / 2 * size as usize;
+        let left = image[row + size as usize / 2 - 12];
+        let right = image[row + size as usize / 2 + 12];
+        assert!(brightness(left) > 300, "left side is too dark: {left:?}");
+        assert_eq!(right, [0, 0, 0]);
+    }
+
+    fn shade_shadow<F: Function + MathFunction>() {
+        // A floor (facing the viewer) with a sphere floating above it
+        let (x, y, z) = Tree::axes();
+        let floor = z.clone() + 0.5;
+        let ball = (x.square() + y.square() + z.square()).sqrt() - 0.25;
+        let shape = Shape::<F>::from(floor.min(ball));
+
+        let size = 64;
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(size),
+            ..Default::default()
+        };
+        let (depth, _) = cfg.run(shape.clone());
+
+        // Light from almost directly in front, offset to the left
+        let mut shade = ShadeConfig {
+            lights: vec![Light {
+                direction: Vector3::new(-0.5, 0.0, 1.0),
+                intensity: 1.0,
+            }],
+            ambient: 0.1,
+            ..Default::default()
+        };
+        let unshadowed = shade.run(shape.clone(), &cfg, &depth);
+        shade.shadows = true;
+        let shadowed = shade.run(shape.clone(), &cfg, &depth);
+
+        // The floor to the right of the ball is in its shadow
+        let row = size as usize / 2 * size as usize;
+        let o = row + size as usize / 2 + 12;
+        assert!(depth[o] < depth[row + size as usize / 2]);
+        assert!(brightness(unshadowed[o]) > 500);
+        assert!(brightness(shadowed[o]) < 100, "{:?}", shadowed[o]);
+
+        // The floor far from the ball is unchanged
+        assert_eq!(unshadowed[row + 2], shadowed[row + 2]);
+
+        // Ambient occlusion darkens the floor near the ball
+        for occlusion in [
+            Occlusion::ScreenSpace {
+                radius: 8,
+                samples: 16,
+            },
+            Occlusion::Sdf {
+                distance: 0.5,
+                steps: 8,
+            },
+        ] {
+            let shade = ShadeConfig {
+                lights: vec![],
+                ambient: 1.0,
+                occlusion,
+                ..Default::default()
+            };
+            let image = shade.run(shape.clone(), &cfg, &depth);
+            let near = image[row + size as usize / 2 - 10];
+            let far = image[row + 2];
+            assert!(
+                brightness(near) < brightness(far),
+                "{occlusion:?}: {near:?} is not darker than {far:?}"
+            );
+        }
+    }
+
+    fn shade_normals<F: Function + MathFunction>() {
+        let (x, y, z) = Tree::axes();
+        let sphere = (x.square() + y.square() + z.square()).sqrt() - 0.5;
+        let shape = Shape::<F>::from(sphere);
+
+        let size = 64;
+        let cfg = VoxelRenderConfig {
+            image_size: VoxelSize::from(size),
+            ..Default::default()
+        };
+        let (depth, _) = cfg.run(shape.clone());
+        let normals = cfg.run_normals(shape, &depth);
+
+        // Background pixels have no normal
+        assert_eq!(normals[0], [0.0; 3]);
+
+        // Normals are unit vectors pointing away from the sphere's center
+        let row = size as usize / 2 * size as usize;
+        let center = Vector3::from(normals[row + size as usize / 2]);
+        assert!((center.norm() - 1.0).abs() < 1e-5);
+        assert!(center.z > 0.99, "bad center normal {center:?}");
+        let left = Vector3::from(normals[row + size as usize / 2 - 12]);
+        assert!((left.norm() - 1.0).abs() < 1e-5);
+        assert!(left.x < -0.5 && left.z > 0.0, "bad left normal {left:?}");
+        let top = Vector3::from(normals[row / 2 + size as usize / 2]);
+        assert!(top.y > 0.5 && top.z > 0.0, "bad top normal {top:?}");
+    }
+
+    render_tests!(shade_sphere);
+    render_tests!(shade_shadow);
+    render_tests!(shade_normals);
+}
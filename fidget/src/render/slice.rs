@@ -0,0 +1,261 @@
+//! Z-slicing of 3D shapes (e.g. for 3D printing)
+use crate::{
+    eval::Function,
+    mesh::{ContourSettings, Contours},
+    render::{ImageRenderConfig, ShadingMode},
+    shape::{Shape, ShapeTracingEval, ShapeVars},
+    types::Interval,
+};
+use nalgebra::{Matrix3, Matrix4, Point2, Vector3};
+
+/// Settings for slicing a 3D shape into a stack of Z layers
+///
+/// Layer `i` is evaluated on the plane `z = start + i * pitch`, in model
+/// coordinates.
+///
+/// Neighbouring layers are grouped into slabs of `slab_size` layers.  Each slab
+/// is evaluated once with interval arithmetic over its Z range, and the
+/// resulting simplified tape is shared by every layer in that slab.
+#[derive(Copy, Clone, Debug)]
+pub struct SliceConfig {
+    /// Z position of the first layer
+    pub start: f32,
+
+    /// Distance between layers
+    pub pitch: f32,
+
+    /// Number of layers
+    pub layers: usize,
+
+    /// Number of layers which share a simplified tape
+    pub slab_size: usize,
+}
+
+impl Default for SliceConfig {
+    fn default() -> Self {
+        Self {
+            start: -1.0,
+            pitch: 0.1,
+            layers: 21,
+            slab_size: 8,
+        }
+    }
+}
+
+impl SliceConfig {
+    /// Builds a configuration covering the (inclusive) range `min..=max`
+    pub fn spaced(pitch: f32, min: f32, max: f32) -> Self {
+        assert!(pitch > 0.0, "pitch must be positive");
+        Self {
+            start: min,
+            pitch,
+            layers: ((max - min) / pitch + 1e-3).floor() as usize + 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the Z position of a particular layer
+    pub fn z(&self, layer: usize) -> f32 {
+        self.start + layer as f32 * self.pitch
+    }
+
+    /// Renders each layer as a 2D image
+    ///
+    /// Returns one image per layer, each of which is equivalent to rendering
+    /// the shape on that layer's Z plane with [`ImageRenderConfig::run`].
+    pub fn run<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        render: &ImageRenderConfig,
+    ) -> Vec<Vec<<M as ShadingMode>::Output>> {
+        self.run_with_vars::<F, M>(shape, &ShapeVars::new(), render)
+    }
+
+    /// Renders each layer as a 2D image, with user-provided variables
+    pub fn run_with_vars<F: Function, M: ShadingMode + Sync>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        render: &ImageRenderConfig,
+    ) -> Vec<Vec<<M as ShadingMode>::Output>> {
+        // The image covers [0, width] x [0, height] in screen coordinates
+        let size = render.image_size;
+        let (x, y) = bounds(
+            render.mat(),
+            size.width() as f32,
+            size.height() as f32,
+            0.0,
+        );
+        self.slabs(&shape, vars, x, y)
+            .into_iter()
+            .flat_map(|(layers, shape)| {
+                layers.map(move |i| {
+                    render.run_with_vars::<F, M>(
+                        shape.clone().apply_transform(self.layer_mat(i)),
+                        vars,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Builds polygon contours for each layer
+    ///
+    /// Returns one set of contours per layer, each of which is equivalent to
+    /// calling [`Contours::build`] on that layer's Z plane.
+    pub fn contours<F: Function>(
+        &self,
+        shape: Shape<F>,
+        settings: ContourSettings,
+    ) -> Vec<Contours> {
+        self.contours_with_vars(shape, &ShapeVars::new(), settings)
+    }
+
+    /// Builds polygon contours for each layer, with user-provided variables
+    pub fn contours_with_vars<F: Function>(
+        &self,
+        shape: Shape<F>,
+        vars: &ShapeVars<f32>,
+        settings: ContourSettings,
+    ) -> Vec<Contours> {
+        // Contours cover the ±1 square in world coordinates
+        let (x, y) = bounds(settings.view.world_to_model(), 1.0, 1.0, -1.0);
+        self.slabs(&shape, vars, x, y)
+            .into_iter()
+            .flat_map(|(layers, shape)| {
+                layers.map(move |i| {
+                    let s = shape.clone().apply_transform(self.layer_mat(i));
+                    Contours::build_with_vars(&s, vars, settings)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns a transform which moves the `z = 0` plane to a given layer
+    fn layer_mat(&self, layer: usize) -> Matrix4<f32> {
+        Matrix4::new_translation(&Vector3::new(0.0, 0.0, self.z(layer)))
+    }
+
+    /// Splits the layers into slabs, returning a simplified shape for each
+    fn slabs<F: Function>(
+        &self,
+        shape: &Shape<F>,
+        vars: &ShapeVars<f32>,
+        x: Interval,
+        y: Interval,
+    ) -> Vec<(std::ops::Range<usize>, Shape<F>)> {
+        let mut eval = ShapeTracingEval::<F::IntervalEval>::default();
+        let mut workspace = F::Workspace::default();
+        let tape = shape.interval_tape(Default::default());
+        let slab_size = self.slab_size.max(1);
+
+        let mut out = vec![];
+        for first in (0..self.layers).step_by(slab_size) {
+            let last = (first + slab_size).min(self.layers) - 1;
+            let (a, b) = (self.z(first), self.z(last));
+            let z = Interval::new(a.min(b), a.max(b));
+            let (_, trace) = eval.eval_v(&tape, x, y, z, vars).unwrap();
+            let s = match trace {
+                Some(trace) => shape
+                    .simplify(trace, Default::default(), &mut workspace)
+                    .unwrap(),
+                None => shape.clone(),
+            };
+            out.push((first..last + 1, s));
+        }
+        out
+    }
+}
+
+/// Returns the XY bounding box of a region after transforming it
+///
+/// The region spans `lo..=w` and `lo..=h` along the X and Y axes.
+fn bounds(mat: Matrix3<f32>, w: f32, h: f32, lo: f32) -> (Interval, Interval) {
+    let mut min = Point2::new(f32::INFINITY, f32::INFINITY);
+    let mut max = Point2::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for (x, y) in [(lo, lo), (w, lo), (lo, h), (w, h)] {
+        let p = mat.transform_point(&Point2::new(x, y));
+        min = min.inf(&p);
+        max = max.sup(&p);
+    }
+    (Interval::new(min.x, max.x), Interval::new(min.y, max.y))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::Tree,
+        eval::MathFunction,
+        render::{BitRenderMode, ImageSize},
+    };
+
+    fn sphere(r: f32, z: f32) -> Tree {
+        let (x, y, zv) = Tree::axes();
+        (x.square() + y.square() + (zv - z).square()).sqrt() - r
+    }
+
+    fn slice_bitmaps<F: Function + MathFunction>() {
+        let shape = Shape::<F>::from(sphere(0.8, 0.0));
+        let cfg = ImageRenderConfig {
+            image_size: ImageSize::from(64),
+            ..Default::default()
+        };
+        let slices = SliceConfig {
+            slab_size: 3,
+            ..SliceConfig::spaced(0.25, -1.0, 1.0)
+        };
+        assert_eq!(slices.layers, 9);
+        let out = slices.run::<_, BitRenderMode>(shape.clone(), &cfg);
+        assert_eq!(out.len(), 9);
+        for (i, image) in out.iter().enumerate() {
+            let t =
+                Matrix4::new_translation(&Vector3::new(0.0, 0.0, slices.z(i)));
+            let expected =
+                cfg.run::<_, BitRenderMode>(shape.clone().apply_transform(t));
+            assert_eq!(*image, expected, "layer {i} does not match");
+        }
+        // The outermost layers miss the sphere entirely
+        assert!(out[0].iter().all(|p| !p));
+        assert!(out[8].iter().all(|p| !p));
+        assert!(out[4].iter().any(|p| *p));
+    }
+
+    fn slice_contours<F: Function + MathFunction>() {
+        let shape = Shape::<F>::from(sphere(0.8, 0.0));
+        let slices = SliceConfig::spaced(0.4, -0.4, 0.4);
+        let out = slices.contours(shape, ContourSettings::default());
+        assert_eq!(out.len(), 3);
+        for (i, c) in out.iter().enumerate() {
+            let z = slices.z(i);
+            let r = (0.8f32.powi(2) - z.powi(2)).sqrt();
+            assert_eq!(c.polylines.len(), 1);
+            for p in &c.polylines[0] {
+                assert!((p.norm() - r).abs() < 0.01, "bad radius {p}");
+            }
+        }
+    }
+
+    fn slice_simplify<F: Function + MathFunction>() {
+        // Two spheres at different heights; each slab only touches one
+        let shape = Shape::<F>::from(sphere(0.3, -0.5).min(sphere(0.3, 0.5)));
+        let slices = SliceConfig {
+            slab_size: 2,
+            ..SliceConfig::spaced(0.1, -0.6, 0.6)
+        };
+        let slabs = slices.slabs(
+            &shape,
+            &ShapeVars::new(),
+            Interval::new(-0.3, 0.3),
+            Interval::new(-0.3, 0.3),
+        );
+        let covered: usize = slabs.iter().map(|(r, _)| r.len()).sum();
+        assert_eq!(covered, slices.layers);
+        assert!(slabs[0].1.size() < shape.size());
+        assert!(slabs.last().unwrap().1.size() < shape.size());
+    }
+
+    render_tests!(slice_bitmaps);
+    render_tests!(slice_contours);
+    render_tests!(slice_simplify);
+}
@@ -1,6 +1,7 @@
 use nalgebra::{
     geometry::{Similarity2, Similarity3},
-    Matrix3, Matrix4, Point2, Vector2, Vector3,
+    Isometry2, Isometry3, Matrix3, Matrix4, Point2, Point3, UnitComplex,
+    UnitQuaternion, Vector2, Vector3,
 };
 
 /// Object providing a view-to-model transform in 2D
@@ -96,18 +97,123 @@ impl View2 {
             }
         }
     }
+
+    /// Returns the rotation of the model on the screen (in radians)
+    ///
+    /// This uses the same convention as [`rotate`](Self::rotate).
+    pub fn rotation(&self) -> f32 {
+        -self.mat.isometry.rotation.angle()
+    }
+
+    /// Rotates the model about a particular position (in model space)
+    ///
+    /// Positive angles rotate the model counter-clockwise on the screen.  If
+    /// `pos` is `None`, the model is rotated about the center of the view.
+    pub fn rotate(&mut self, angle: f32, pos: Option<Point2<f32>>) {
+        let center = pos
+            .map(|p| self.mat.inverse_transform_point(&p))
+            .unwrap_or_else(Point2::origin);
+        let rot = UnitComplex::new(-angle);
+        self.mat *= Isometry2::rotation_wrt_point(rot, center);
+    }
+
+    /// Pans the view so that the model position under `before` is moved to be
+    /// under `after`
+    ///
+    /// Both positions are in world coordinates; this is used for dragging, so
+    /// that the cursor stays attached to the same point on the model.
+    pub fn pan(&mut self, before: Point2<f32>, after: Point2<f32>) {
+        let target = self.transform_point(&before);
+        let pos = self.transform_point(&after);
+        self.mat.append_translation_mut(&(target - pos).into());
+    }
 }
 
-/// Object providing a view-to-model transform in 2D
+/// Perspective projection parameters for a [`View3`]
+///
+/// The virtual camera sits on the `+z` axis at a distance of `1 / tan(fov / 2)`
+/// from the view's center, so the plane through the center (perpendicular to
+/// the camera) covers the same ±1 region as an orthographic view.
+///
+/// `near` and `far` are distances from the camera (in world units) which are
+/// mapped to world `z = +1` and `z = -1` respectively.  Because this is a
+/// projective transform, world `z` is no longer linear in camera depth.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Perspective {
+    /// Vertical field of view, in radians
+    pub fov: f32,
+    /// Distance from the camera to the near clipping plane
+    pub near: f32,
+    /// Distance from the camera to the far clipping plane
+    pub far: f32,
+}
+
+impl Perspective {
+    /// Builds a perspective with the given field of view (in radians)
+    ///
+    /// The near and far planes are placed one unit in front of and behind the
+    /// view's center, so the entire ±1 cube is visible (the near plane is
+    /// pulled away from the camera if it would otherwise be too close).
+    pub fn new(fov: f32) -> Self {
+        let d = Self::focal_distance(fov);
+        Self {
+            fov,
+            near: (d - 1.0).max(d / 8.0),
+            far: d + 1.0,
+        }
+    }
+
+    /// Returns the distance from the camera to the view's center
+    fn focal_distance(fov: f32) -> f32 {
+        1.0 / (fov / 2.0).tan()
+    }
+
+    /// Returns a matrix mapping from world coordinates into a frustum
+    ///
+    /// The frustum is centered on the origin, with `+z` pointing towards the
+    /// camera.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let d = Self::focal_distance(self.fov);
+        let (n, f) = (self.near, self.far);
+
+        // World z is a projective function of distance from the camera t:
+        //      z = a + b / t
+        // with z = +1 at the near plane and z = -1 at the far plane.
+        let b = 2.0 * n * f / (f - n);
+        let a = -(f + n) / (f - n);
+
+        // Each point is scaled by (1 / t) in homogeneous coordinates, then
+        // placed at (x * t / d, y * t / d, d - t)
+        #[rustfmt::skip]
+        let m = Matrix4::new(
+            1.0 / d, 0.0,     0.0,     0.0,
+            0.0,     1.0 / d, 0.0,     0.0,
+            0.0,     0.0,     d / b,   -d * a / b - 1.0,
+            0.0,     0.0,     1.0 / b, -a / b,
+        );
+        m
+    }
+}
+
+/// Object providing a view-to-model transform in 3D
+///
+/// By default, the view is orthographic; use
+/// [`set_perspective`](Self::set_perspective) to add a perspective projection.
+///
+/// Interactive helpers ([`pan`](Self::pan), [`arcball`](Self::arcball),
+/// [`orbit`](Self::orbit)) operate on the orthographic portion of the view,
+/// i.e. they take world coordinates before any perspective is applied.
 #[derive(Copy, Clone, Debug)]
 pub struct View3 {
     mat: Similarity3<f32>,
+    perspective: Option<Perspective>,
 }
 
 impl Default for View3 {
     fn default() -> Self {
         Self {
             mat: Similarity3::identity(),
+            perspective: None,
         }
     }
 }
@@ -118,9 +224,244 @@ impl View3 {
     pub fn from_center_and_scale(center: Vector3<f32>, scale: f32) -> Self {
         let mat =
             Similarity3::from_parts(center.into(), Default::default(), scale);
-        Self { mat }
+        Self {
+            mat,
+            perspective: None,
+        }
     }
     pub fn world_to_model(&self) -> Matrix4<f32> {
+        let mat: Matrix4<f32> = self.mat.into();
+        match &self.perspective {
+            Some(p) => mat * p.matrix(),
+            None => mat,
+        }
+    }
+    /// Returns the transform from camera to model coordinates
+    ///
+    /// Camera coordinates are world coordinates after undoing any perspective
+    /// projection, so they're a Euclidean frame aligned with the screen.
+    pub(crate) fn camera_to_model(&self) -> Matrix4<f32> {
         self.mat.into()
     }
+    /// Returns the scale factor from camera to model coordinates
+    pub(crate) fn scale(&self) -> f32 {
+        self.mat.scaling()
+    }
+    /// Transform a point from world to model space
+    ///
+    /// This ignores any perspective projection.
+    pub fn transform_point(&self, p: &Point3<f32>) -> Point3<f32> {
+        self.mat.transform_point(p)
+    }
+
+    /// Applies a translation (in model units) to the current camera position
+    pub fn translate(&mut self, dt: Vector3<f32>) {
+        self.mat.append_translation_mut(&dt.into());
+    }
+
+    /// Zooms the camera about a particular position (in model space)
+    pub fn zoom(&mut self, amount: f32, pos: Option<Point3<f32>>) {
+        match pos {
+            Some(before) => {
+                let p = self.mat.inverse_transform_point(&before);
+                self.mat.append_scaling_mut(amount);
+                let pos_after = self.transform_point(&p);
+                self.mat
+                    .append_translation_mut(&(before - pos_after).into());
+            }
+            None => {
+                self.mat.append_scaling_mut(amount);
+            }
+        }
+    }
+
+    /// Pans the view so that the model position under `before` is moved to be
+    /// under `after`
+    ///
+    /// Both positions are in world coordinates (ignoring any perspective
+    /// projection); this is used for dragging, so that the cursor stays
+    /// attached to the same point on the model.
+    pub fn pan(&mut self, before: Point3<f32>, after: Point3<f32>) {
+        let target = self.transform_point(&before);
+        let pos = self.transform_point(&after);
+        self.mat.append_translation_mut(&(target - pos).into());
+    }
+
+    /// Returns the rotation of the model relative to the screen
+    ///
+    /// This uses the same convention as [`orbit`](Self::orbit).
+    pub fn rotation(&self) -> UnitQuaternion<f32> {
+        self.mat.isometry.rotation.inverse()
+    }
+
+    /// Rotates the model about a pivot point (in model space)
+    ///
+    /// The rotation is expressed in world coordinates, i.e. relative to the
+    /// screen; for example, a rotation about the world `+y` axis spins the
+    /// model around the vertical axis of the screen.  If `pivot` is `None`,
+    /// the model is rotated about the center of the view.
+    pub fn orbit(
+        &mut self,
+        rot: UnitQuaternion<f32>,
+        pivot: Option<Point3<f32>>,
+    ) {
+        let center = pivot
+            .map(|p| self.mat.inverse_transform_point(&p))
+            .unwrap_or_else(Point3::origin);
+        self.mat *= Isometry3::rotation_wrt_point(rot.inverse(), center);
+    }
+
+    /// Applies an arcball rotation from a mouse drag
+    ///
+    /// `before` and `after` are the cursor positions in world coordinates,
+    /// which are projected onto a virtual trackball (a unit sphere blended
+    /// into a hyperbolic sheet, per Bell's trackball).  The model is rotated
+    /// about the center of the view, so that the point under the cursor
+    /// follows it.
+    pub fn arcball(&mut self, before: Point2<f32>, after: Point2<f32>) {
+        let a = Self::arcball_project(before);
+        let b = Self::arcball_project(after);
+        if let Some(rot) = UnitQuaternion::rotation_between(&a, &b) {
+            self.orbit(rot, None);
+        }
+    }
+
+    /// Projects a point in world coordinates onto the virtual trackball
+    fn arcball_project(p: Point2<f32>) -> Vector3<f32> {
+        let d2 = p.coords.norm_squared();
+        let z = if d2 <= 0.5 {
+            (1.0 - d2).sqrt()
+        } else {
+            0.5 / d2.sqrt()
+        };
+        Vector3::new(p.x, p.y, z)
+    }
+    /// Returns the perspective projection (if present)
+    pub fn perspective(&self) -> Option<Perspective> {
+        self.perspective
+    }
+    /// Sets the perspective projection, or `None` for an orthographic view
+    pub fn set_perspective(&mut self, p: Option<Perspective>) {
+        self.perspective = p;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nalgebra::Point3;
+
+    #[test]
+    fn perspective_center_plane() {
+        let p = Perspective::new(std::f32::consts::FRAC_PI_2);
+        let m = p.matrix();
+
+        // Find the world-space Z value which maps to the center plane
+        let z = m
+            .try_inverse()
+            .unwrap()
+            .transform_point(&Point3::origin())
+            .z;
+        assert!(z > -1.0 && z < 1.0);
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (-1.0, 0.5), (0.25, -1.0)] {
+            let out = m.transform_point(&Point3::new(x, y, z));
+            assert!((out.x - x).abs() < 1e-6, "bad x: {out:?}");
+            assert!((out.y - y).abs() < 1e-6, "bad y: {out:?}");
+            assert!(out.z.abs() < 1e-6, "bad z: {out:?}");
+        }
+    }
+
+    #[test]
+    fn perspective_near_far() {
+        let p = Perspective {
+            fov: 1.0,
+            near: 0.5,
+            far: 4.0,
+        };
+        let d = 1.0 / 0.5f32.tan();
+        let m = p.matrix();
+
+        // The near plane is small and close to the camera
+        let near = m.transform_point(&Point3::new(1.0, 1.0, 1.0));
+        assert!((near.z - (d - 0.5)).abs() < 1e-5, "bad near: {near:?}");
+        assert!((near.x - 0.5 / d).abs() < 1e-5, "bad near: {near:?}");
+
+        // The far plane is large and far from the camera
+        let far = m.transform_point(&Point3::new(1.0, 1.0, -1.0));
+        assert!((far.z - (d - 4.0)).abs() < 1e-5, "bad far: {far:?}");
+        assert!((far.x - 4.0 / d).abs() < 1e-5, "bad far: {far:?}");
+    }
+
+    #[test]
+    fn view2_rotate_and_pan() {
+        let mut view =
+            View2::from_center_and_scale(Vector2::new(1.0, 2.0), 2.0);
+        let pivot = Point2::new(3.0, 2.0);
+        let before = view.world_to_model().try_inverse().unwrap();
+        let pivot_world = before.transform_point(&pivot);
+
+        view.rotate(std::f32::consts::FRAC_PI_2, Some(pivot));
+        assert!((view.rotation() - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        // The pivot stays in place on the screen
+        let p = view.transform_point(&pivot_world);
+        assert!((p - pivot).norm() < 1e-5, "{p:?} != {pivot:?}");
+
+        // The model's +x axis now points up on the screen
+        let inv = view.world_to_model().try_inverse().unwrap();
+        let q = inv.transform_point(&(pivot + Vector2::new(1.0, 0.0)));
+        assert!((q - pivot_world - Vector2::new(0.0, 0.5)).norm() < 1e-5);
+
+        // Panning keeps the model point under the cursor
+        let a = Point2::new(0.25, -0.5);
+        let b = Point2::new(-0.5, 0.75);
+        let m = view.transform_point(&a);
+        view.pan(a, b);
+        assert!((view.transform_point(&b) - m).norm() < 1e-5);
+    }
+
+    #[test]
+    fn view3_arcball() {
+        let mut view =
+            View3::from_center_and_scale(Vector3::new(1.0, 2.0, 3.0), 0.5);
+        let front = view.transform_point(&Point3::new(0.0, 0.0, 1.0));
+
+        // A zero-length drag does nothing
+        view.arcball(Point2::new(0.1, 0.2), Point2::new(0.1, 0.2));
+        let p = view.transform_point(&Point3::new(0.0, 0.0, 1.0));
+        assert!((p - front).norm() < 1e-6);
+
+        // Dragging to the right brings the front of the model to the right
+        view.arcball(Point2::new(0.0, 0.0), Point2::new(0.5, 0.0));
+        let inv = view.world_to_model().try_inverse().unwrap();
+        let q = inv.transform_point(&front);
+        assert!(q.x > 0.4, "bad rotation: {q:?}");
+        assert!(q.y.abs() < 1e-6, "bad rotation: {q:?}");
+
+        // The center of the view is unchanged
+        let c = view.transform_point(&Point3::origin());
+        assert!((c - Point3::new(1.0, 2.0, 3.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn view3_orbit_and_pan() {
+        let mut view = View3::default();
+        let pivot = Point3::new(0.5, 0.0, 0.0);
+        let rot = UnitQuaternion::from_axis_angle(
+            &Vector3::y_axis(),
+            std::f32::consts::FRAC_PI_2,
+        );
+        view.orbit(rot, Some(pivot));
+
+        // The pivot is fixed in both model and world space
+        let p = view.transform_point(&Point3::new(0.5, 0.0, 0.0));
+        assert!((p - pivot).norm() < 1e-6, "{p:?}");
+        assert!(view.rotation().angle_to(&rot) < 1e-6);
+
+        let a = Point3::new(0.25, -0.5, 0.0);
+        let b = Point3::new(-0.5, 0.75, 0.0);
+        let m = view.transform_point(&a);
+        view.pan(a, b);
+        assert!((view.transform_point(&b) - m).norm() < 1e-5);
+    }
 }
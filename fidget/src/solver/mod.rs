@@ -185,7 +185,7 @@ impl<'a, F: Function> Solver<'a, F> {
 /// - [The Levenberg-Marquardt Algorithm (Ranganathan 2004)](http://ananth.in/docs/lmtut.pdf)
 /// - [Basics on Continuous Optimization § Levenberg-Marquardt](https://www.brnt.eu/phd/node10.html#SECTION00622700000000000000)
 /// - [Improvements to the Levenberg-Marquardt algorithm for nonlinear
-///    least-squares minimization (Transtrum 2012)](https://arxiv.org/pdf/1201.5885)
+///   least-squares minimization (Transtrum 2012)](https://arxiv.org/pdf/1201.5885)
 pub fn solve<F: Function>(
     eqs: &[F],
     vars: &HashMap<Var, Parameter>,